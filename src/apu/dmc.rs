@@ -1,3 +1,5 @@
+use crate::save_state::{Snapshot, StateWriter, StateReader, SaveStateError};
+
 pub(super) struct Dmc {
     interrupt_flag: bool,
     interrupt_enabled_flag: bool,
@@ -166,4 +168,43 @@ impl Dmc {
     pub(super) fn output(&self) -> u8 {
         self.output
     }
-}
\ No newline at end of file
+}
+
+impl Snapshot for Dmc {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.bool(self.interrupt_flag);
+        writer.bool(self.interrupt_enabled_flag);
+        writer.u16(self.timer_reset);
+        writer.u16(self.timer_counter);
+        writer.u8(self.sample_buffer);
+        writer.bool(self.sample_buffer_empty);
+        writer.u16(self.sample_address);
+        writer.u16(self.sample_length);
+        writer.u16(self.current_address);
+        writer.u16(self.bytes_remaining);
+        writer.bool(self.loop_flag);
+        writer.u8(self.shift_register);
+        writer.u8(self.bits_remaining);
+        writer.u8(self.output);
+        writer.bool(self.silence_flag);
+    }
+
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.interrupt_flag = reader.bool()?;
+        self.interrupt_enabled_flag = reader.bool()?;
+        self.timer_reset = reader.u16()?;
+        self.timer_counter = reader.u16()?;
+        self.sample_buffer = reader.u8()?;
+        self.sample_buffer_empty = reader.bool()?;
+        self.sample_address = reader.u16()?;
+        self.sample_length = reader.u16()?;
+        self.current_address = reader.u16()?;
+        self.bytes_remaining = reader.u16()?;
+        self.loop_flag = reader.bool()?;
+        self.shift_register = reader.u8()?;
+        self.bits_remaining = reader.u8()?;
+        self.output = reader.u8()?;
+        self.silence_flag = reader.bool()?;
+        Ok(())
+    }
+}
@@ -1,3 +1,4 @@
+use crate::save_state::{Snapshot, StateWriter, StateReader, SaveStateError};
 
 /// 用于生成包络:
 /// - 递减的锯齿包络, 是否循环可选
@@ -75,4 +76,25 @@ impl Envelope {
         }
     }
 
-}
\ No newline at end of file
+}
+
+impl Snapshot for Envelope {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.bool(self.start_flag);
+        writer.bool(self.loop_flag);
+        writer.bool(self.constant_volume_flag);
+        writer.u8(self.divider_counter);
+        writer.u8(self.decay_level_counter);
+        writer.u8(self.constant_volume);
+    }
+
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.start_flag = reader.bool()?;
+        self.loop_flag = reader.bool()?;
+        self.constant_volume_flag = reader.bool()?;
+        self.divider_counter = reader.u8()?;
+        self.decay_level_counter = reader.u8()?;
+        self.constant_volume = reader.u8()?;
+        Ok(())
+    }
+}
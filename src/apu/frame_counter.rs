@@ -1,4 +1,4 @@
-use crate::common::Clock;
+use crate::{common::Clock, save_state::{Snapshot, StateWriter, StateReader, SaveStateError}};
 
 enum Mode {
     Step4, // 4 步模式
@@ -154,4 +154,36 @@ impl Clock for FrameCounter {
         }
     }
 
-}
\ No newline at end of file
+}
+
+impl Snapshot for FrameCounter {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.u8(match self.mode {
+            Mode::Step4 => 0,
+            Mode::Step5 => 1,
+        });
+        writer.bool(self.frame_interrupt_flag);
+        writer.bool(self.interrupt_inhibit_flag);
+        writer.u8(self.step as u8);
+        writer.u32(self.cycles);
+        writer.bool(self.write_val.is_some());
+        writer.u8(self.write_val.unwrap_or(0));
+        writer.u8(self.write_delay);
+    }
+
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.mode = match reader.u8_max(1)? {
+            0 => Mode::Step4,
+            _ => Mode::Step5,
+        };
+        self.frame_interrupt_flag = reader.bool()?;
+        self.interrupt_inhibit_flag = reader.bool()?;
+        self.step = reader.u8_max(5)? as usize;
+        self.cycles = reader.u32()?;
+        let has_write = reader.bool()?;
+        let write_val = reader.u8()?;
+        self.write_val = has_write.then_some(write_val);
+        self.write_delay = reader.u8()?;
+        Ok(())
+    }
+}
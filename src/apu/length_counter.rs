@@ -1,3 +1,5 @@
+use crate::save_state::{Snapshot, StateWriter, StateReader, SaveStateError};
+
 pub(super) struct LengthCounter {
     enabled_flag: bool,
     counter: u8,
@@ -49,3 +51,18 @@ impl LengthCounter {
         self.counter -= 1;
     }
 }
+
+impl Snapshot for LengthCounter {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.bool(self.enabled_flag);
+        writer.u8(self.counter);
+        writer.bool(self.halt_flag);
+    }
+
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.enabled_flag = reader.bool()?;
+        self.counter = reader.u8()?;
+        self.halt_flag = reader.bool()?;
+        Ok(())
+    }
+}
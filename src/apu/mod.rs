@@ -8,7 +8,7 @@ mod dmc;
 mod envelope;
 mod length_counter;
 
-use crate::common::{Clock, Mem};
+use crate::{common::{Clock, Mem}, save_state::{Snapshot, StateWriter, StateReader, SaveStateError}};
 
 use self::{frame_counter::{FrameCounter, FrameCounterSignal}, pulse::Pulse, triangle::Triangle, noise::Noise, dmc::Dmc};
 
@@ -47,6 +47,10 @@ impl Samples {
     pub fn clear(&mut self) {
         self.data.clear()
     }
+
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.data.truncate(len)
+    }
 }
 
 impl Apu {
@@ -62,6 +66,13 @@ impl Apu {
         }
     }
 
+    /// 重新上电, 已生成但未取走的声音样本保留
+    pub(crate) fn power_cycle(&mut self) {
+        let samples = std::mem::replace(&mut self.samples, Samples { data: Vec::new() });
+        *self = Apu::new();
+        self.samples = samples;
+    }
+
     fn generate_a_sample(&mut self) {
         let pulse1 = self.pulse1.output() as f32;
         let pulse2 = self.pulse2.output() as f32;
@@ -87,8 +98,9 @@ impl Apu {
         &mut self.samples
     }
 
+    /// 帧计数器与 DMC 共用 IRQ 线, 任意一个请求中断都会将其拉低
     pub(crate) fn irq_line_level(&self) -> bool {
-        !(self.frame_counter.frame_interrupt() && self.dmc.interrupt())
+        !(self.frame_counter.frame_interrupt() || self.dmc.interrupt())
     }
 
     /// DMC 是否需要加载 sample
@@ -102,12 +114,20 @@ impl Apu {
     }
 
     // $4015 read | IF-D NT21 | DMC interrupt (I), frame interrupt (F), DMC active (D), length counter > 0 (N/T/2/1)
+    // 读取后清除 frame interrupt
     fn read_status(&mut self) -> u8 {
+        let status = self.peek_status();
+        self.frame_counter.poll_frame_interrupt();
+        status
+    }
+
+    /// 读取 $4015 将得到的值, 但不清除 frame interrupt
+    fn peek_status(&self) -> u8 {
         let mut status = 0u8;
         if self.dmc.interrupt() {
             status |= 0b1000_0000;
         }
-        if self.frame_counter.poll_frame_interrupt() {
+        if self.frame_counter.frame_interrupt() {
             status |= 0b0100_0000;
         }
         if self.dmc.bytes_remaining() > 0 {
@@ -172,6 +192,17 @@ impl Clock for Apu {
     }
 }
 
+impl Apu {
+    /// 读取 APU 寄存器将得到的值, 但不改变任何状态
+    pub(crate) fn peek(&self, addr: u16) -> u8 {
+        if addr == 0x4015 {
+            self.peek_status()
+        } else {
+            0
+        }
+    }
+}
+
 impl Mem for Apu {
     fn mem_read(&mut self, addr: u16) -> u8 {
         if addr == 0x4015 {
@@ -216,4 +247,25 @@ impl Mem for Apu {
             _ => (),
         }
     }
-}
\ No newline at end of file
+}
+
+/// 不含未取走的样本
+impl Snapshot for Apu {
+    fn save(&self, writer: &mut StateWriter) {
+        self.pulse1.save(writer);
+        self.pulse2.save(writer);
+        self.triangle.save(writer);
+        self.noise.save(writer);
+        self.dmc.save(writer);
+        self.frame_counter.save(writer);
+    }
+
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.pulse1.load(reader)?;
+        self.pulse2.load(reader)?;
+        self.triangle.load(reader)?;
+        self.noise.load(reader)?;
+        self.dmc.load(reader)?;
+        self.frame_counter.load(reader)
+    }
+}
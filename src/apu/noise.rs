@@ -1,4 +1,5 @@
 use super::{envelope::Envelope, length_counter::LengthCounter};
+use crate::save_state::{Snapshot, StateWriter, StateReader, SaveStateError};
 
 
 pub(super) struct Noise {
@@ -96,4 +97,25 @@ impl Noise {
             self.envelope.output()
         }
     }
-}
\ No newline at end of file
+}
+
+impl Snapshot for Noise {
+    fn save(&self, writer: &mut StateWriter) {
+        self.envelope.save(writer);
+        writer.u16(self.timer_reset);
+        writer.u16(self.timer_counter);
+        writer.u16(self.shift_register);
+        writer.bool(self.mode_flag);
+        self.length_counter.save(writer);
+    }
+
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.envelope.load(reader)?;
+        self.timer_reset = reader.u16()?;
+        self.timer_counter = reader.u16()?;
+        self.shift_register = reader.u16()?;
+        self.mode_flag = reader.bool()?;
+        self.length_counter.load(reader)?;
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 use super::{envelope::Envelope, length_counter::LengthCounter};
+use crate::save_state::{Snapshot, StateWriter, StateReader, SaveStateError};
 
 
 
@@ -180,4 +181,48 @@ impl Sweep {
         let change_amount = timer_reset >> self.shift;
         timer_reset < 8 || (!self.negate_flag && timer_reset + change_amount > 0x7ff)
     }
-}
\ No newline at end of file
+}
+
+impl Snapshot for Pulse {
+    fn save(&self, writer: &mut StateWriter) {
+        self.envelope.save(writer);
+        self.sweep.save(writer);
+        writer.u16(self.timer_reset);
+        writer.u16(self.timer_counter);
+        writer.u8(self.sequencer_duty_type as u8);
+        writer.u8(self.sequencer_step as u8);
+        self.length_counter.save(writer);
+    }
+
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.envelope.load(reader)?;
+        self.sweep.load(reader)?;
+        self.timer_reset = reader.u16()?;
+        self.timer_counter = reader.u16()?;
+        self.sequencer_duty_type = reader.u8_max(3)? as usize;
+        self.sequencer_step = reader.u8_max(7)? as usize;
+        self.length_counter.load(reader)?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Sweep {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.u8(self.divider_reset);
+        writer.u8(self.divider_counter);
+        writer.u8(self.shift);
+        writer.bool(self.reload_flag);
+        writer.bool(self.enable_flag);
+        writer.bool(self.negate_flag);
+    }
+
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.divider_reset = reader.u8()?;
+        self.divider_counter = reader.u8()?;
+        self.shift = reader.u8()?;
+        self.reload_flag = reader.bool()?;
+        self.enable_flag = reader.bool()?;
+        self.negate_flag = reader.bool()?;
+        Ok(())
+    }
+}
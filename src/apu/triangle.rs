@@ -1,4 +1,5 @@
 use super::length_counter::LengthCounter;
+use crate::save_state::{Snapshot, StateWriter, StateReader, SaveStateError};
 
 
 pub(super) struct Triangle {
@@ -131,4 +132,40 @@ impl LinearCounter {
             self.reload_flag = false;
         }
     }
-}
\ No newline at end of file
+}
+
+impl Snapshot for Triangle {
+    fn save(&self, writer: &mut StateWriter) {
+        self.linear_counter.save(writer);
+        self.length_counter.save(writer);
+        writer.u16(self.timer_reset);
+        writer.u16(self.timer_counter);
+        writer.u8(self.sequencer_step as u8);
+    }
+
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.linear_counter.load(reader)?;
+        self.length_counter.load(reader)?;
+        self.timer_reset = reader.u16()?;
+        self.timer_counter = reader.u16()?;
+        self.sequencer_step = reader.u8_max(31)? as usize;
+        Ok(())
+    }
+}
+
+impl Snapshot for LinearCounter {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.bool(self.control_flag);
+        writer.bool(self.reload_flag);
+        writer.u8(self.reload_val);
+        writer.u8(self.counter);
+    }
+
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.control_flag = reader.bool()?;
+        self.reload_flag = reader.bool()?;
+        self.reload_val = reader.u8()?;
+        self.counter = reader.u8()?;
+        Ok(())
+    }
+}
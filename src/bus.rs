@@ -1,4 +1,4 @@
-use crate::{cartridge::Rom, ppu::{Ppu, Frame}, joypad::{self, Joypad}, common::{Mem, Clock}, apu::{Apu, Samples}};
+use crate::{cartridge::Rom, ppu::{Ppu, Frame}, joypad::{Joypad, ControllerPort, Beam}, common::{Mem, Clock}, apu::{Apu, Samples}, cpu::{CpuBus, Peek, BusAccess, AccessKind}, cdl::{self, CodeDataLog, PrgFlags}, hooks::{Hooks, HookEvents, HookId, BusEvent}, event_viewer::{EventViewer, FrameEventKind}, debugger::Symbols, cheats::Cheats, movie, save_state::{self, Snapshot, StateWriter, StateReader, SaveStateError}, Interrupt};
 
 // CPU memory map
 //  _______________ $10000  _______________
@@ -39,39 +39,241 @@ use crate::{cartridge::Rom, ppu::{Ppu, Frame}, joypad::{self, Joypad}, common::{
 // Data:       0x2007
 // OAM DMA:    0x4014
 
-pub(crate) struct Bus {
+/// NES 的 CPU 总线, 连接 CPU RAM, PPU, APU, 手柄与卡带
+pub struct Bus {
     // 组成
     cpu_vram: [u8; 2048],  // 2KB CPU VRAM
     prg_rom: Vec<u8>,
+    prg_ram: [u8; 0x2000], // 8KB PRG RAM, 位于 $6000-$7FFF
     ppu: Ppu,
     apu: Apu,
     joypad: Joypad,
+    rom_crc32: u32,
+    rom_md5: [u8; 16],
     // 状态信息
-    cycles: u32, // CPU 时钟周期
+    cycles: u64, // CPU 时钟周期
+    cycles_ahead: u64, // CPU 已经访存但总线尚未经过的周期数, CPU 先访存后补齐周期
     nmi_line_level: bool,
     irq_line_level: bool,
+    input_polled: bool, // 本帧是否读取过 $4016/$4017
+    lag_frame: bool, // 上一帧是否为 lag 帧
+    lag_count: u64, // 上电以来的 lag 帧数
+    cheats: Cheats,
+    // 调试
+    access_log: Option<Vec<BusAccess>>, // 开启后记录 CPU 的每一次访问
+    prg_cdl: Option<Vec<u8>>, // 开启后记录 PRG ROM 每个字节的用途, 见 `PrgFlags`
+    hooks: Hooks,
+    event_viewer: Option<EventViewer>,
+    speculative: bool, // 正在预运行, 不触发回调也不记录事件
+    symbols: Symbols,
 }
 
 impl Bus {
     pub(crate) fn new(rom: Rom) -> Bus {
+        let rom_crc32 = save_state::crc32([&rom.prg_rom[..], &rom.chr_rom[..]]);
+        let rom_md5 = movie::md5([&rom.prg_rom[..], &rom.chr_rom[..]]);
         Bus {
             cpu_vram: [0; 2048],
             prg_rom: rom.prg_rom,
+            prg_ram: [0; 0x2000],
             ppu: Ppu::new(rom.chr_rom, rom.screen_mirroring),
             apu: Apu::new(),
-            joypad: Joypad::new(),
+            joypad: Joypad::for_expansion_device(rom.expansion_device),
+            rom_crc32,
+            rom_md5,
             cycles: 0,
+            cycles_ahead: 0,
             nmi_line_level: true,
             irq_line_level: true,
+            input_polled: false,
+            lag_frame: false,
+            lag_count: 0,
+            cheats: Cheats::new(),
+            access_log: None,
+            prg_cdl: None,
+            hooks: Hooks::new(),
+            event_viewer: None,
+            speculative: false,
+            symbols: Symbols::new(),
         }
     }
 
-    pub(crate) fn nmi_line_level(&self) -> bool {
-        self.nmi_line_level
+    /// 是否记录 CPU 的每一次访问(不含 DMA), 供调试器的读写断点使用
+    pub fn set_access_log_enabled(&mut self, enabled: bool) {
+        self.access_log = if enabled { Some(vec![]) } else { None };
     }
 
-    pub(crate) fn irq_line_level(&self) -> bool {
-        self.irq_line_level
+    /// 取出并清空已记录的访问, 按发生顺序排列
+    pub fn take_access_log(&mut self) -> Vec<BusAccess> {
+        match self.access_log.as_mut() {
+            Some(log) => std::mem::take(log),
+            None => vec![],
+        }
+    }
+
+    /// 开始记录 PRG ROM 与 CHR ROM 中每个字节的用途, 已在记录时不做任何事
+    pub fn start_code_data_log(&mut self) {
+        if self.prg_cdl.is_none() {
+            self.prg_cdl = Some(vec![0; self.prg_rom.len()]);
+            self.ppu.set_chr_cdl(Some(vec![0; self.ppu.chr_rom_len()]));
+        }
+    }
+
+    /// 在已有记录(例如从 `.cdl` 文件读取)的基础上继续记录, None 表示停止记录
+    ///
+    /// 记录的长度与 ROM 不一致时返回错误, 原有记录保持不变
+    pub fn set_code_data_log(&mut self, log: Option<CodeDataLog>) -> std::io::Result<()> {
+        match log {
+            Some(log) => {
+                log.check_len(self.prg_rom.len(), self.ppu.chr_rom_len())?;
+                self.prg_cdl = Some(log.prg);
+                self.ppu.set_chr_cdl(Some(log.chr));
+            }
+            None => {
+                self.prg_cdl = None;
+                self.ppu.set_chr_cdl(None);
+            }
+        }
+        Ok(())
+    }
+
+    /// 当前记录的副本, 未在记录时为 None
+    pub fn code_data_log(&self) -> Option<CodeDataLog> {
+        Some(CodeDataLog {
+            prg: self.prg_cdl.clone()?,
+            chr: self.ppu.chr_cdl()?.clone(),
+        })
+    }
+
+    /// 停止记录并取出结果
+    pub fn take_code_data_log(&mut self) -> Option<CodeDataLog> {
+        Some(CodeDataLog {
+            prg: self.prg_cdl.take()?,
+            chr: self.ppu.take_chr_cdl()?,
+        })
+    }
+
+    /// 注册回调, 在 events 中的事件发生时调用
+    pub fn add_hook<F>(&mut self, events: HookEvents, callback: F) -> HookId
+    where
+        F: FnMut(&BusEvent) + 'static
+    {
+        self.hooks.add(events, Box::new(callback))
+    }
+
+    /// 移除回调, 返回该回调是否存在
+    pub fn remove_hook(&mut self, id: HookId) -> bool {
+        self.hooks.remove(id)
+    }
+
+    /// 是否按光栅位置记录每帧的寄存器写入与中断等事件
+    pub fn set_event_viewer_enabled(&mut self, enabled: bool) {
+        self.event_viewer = if enabled { Some(EventViewer::new()) } else { None };
+    }
+
+    pub fn event_viewer(&self) -> Option<&EventViewer> {
+        self.event_viewer.as_ref()
+    }
+
+    /// 符号表, 用于 trace, 反汇编与调试器
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    /// 设置符号表, 之后 `cpu_trace` 会将跳转目标与访问的地址显示为符号
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    /// 是否正在预运行(见 `RunAhead`), 预运行的帧之后会被丢弃, 期间不触发回调也不记录事件
+    pub(crate) fn set_speculative(&mut self, speculative: bool) {
+        self.speculative = speculative;
+        self.hooks.set_muted(speculative);
+    }
+
+    pub fn cheats(&self) -> &Cheats {
+        &self.cheats
+    }
+
+    pub fn cheats_mut(&mut self) -> &mut Cheats {
+        &mut self.cheats
+    }
+
+    /// 每帧末将金手指的值写入 RAM
+    fn apply_ram_cheats(&mut self) {
+        for &(addr, value) in self.cheats.ram_writes() {
+            match addr {
+                0..=0x1fff => self.cpu_vram[(addr & 0b0000_0111_1111_1111) as usize] = value,
+                0x6000..=0x7fff => self.prg_ram[(addr - 0x6000) as usize] = value,
+                _ => {}
+            }
+        }
+    }
+
+    /// 当前 PPU 扫描线
+    pub fn scanline(&self) -> u16 {
+        self.ppu.scanline()
+    }
+
+    /// 当前扫描线内的 PPU 周期
+    pub fn ppu_cycle(&self) -> u16 {
+        self.ppu.cycle()
+    }
+
+    /// 上电以来完成的帧数
+    pub fn frame_count(&self) -> u64 {
+        self.ppu.frame_count()
+    }
+
+    /// 当前画面, 可以在帧末叠加文字与图形
+    pub fn frame_mut(&mut self) -> &mut Frame {
+        self.ppu.frame_mut()
+    }
+
+    /// ROM 的 CRC32, 按 PRG ROM, CHR ROM 的顺序计算
+    pub fn rom_crc32(&self) -> u32 {
+        self.rom_crc32
+    }
+
+    /// PRG ROM 与 CHR ROM 的 MD5, 即 FCEUX 录像中的 romChecksum
+    pub fn rom_md5(&self) -> [u8; 16] {
+        self.rom_md5
+    }
+
+    /// 重新上电: RAM, PPU, APU 与手柄端口恢复初始状态, 连接的设备, 金手指, 钩子与调试设置保留
+    pub(crate) fn power_cycle(&mut self) {
+        self.cpu_vram = [0; 2048];
+        self.prg_ram = [0; 0x2000];
+        self.ppu.power_cycle();
+        self.apu.power_cycle();
+        self.joypad.power_on();
+        self.cycles = 0;
+        self.cycles_ahead = 0;
+        self.nmi_line_level = true;
+        self.irq_line_level = true;
+        self.input_polled = false;
+        self.lag_frame = false;
+        self.lag_count = 0;
+    }
+
+    /// 刚结束的一帧是否为 lag 帧, 即两次帧末(vblank 开始)之间没有读取过手柄
+    pub fn lag_frame(&self) -> bool {
+        self.lag_frame
+    }
+
+    /// 上电以来的 lag 帧数
+    pub fn lag_count(&self) -> u64 {
+        self.lag_count
+    }
+
+    /// 修改 lag 帧数, 例如在开始计时的时候清零
+    pub fn set_lag_count(&mut self, count: u64) {
+        self.lag_count = count;
+    }
+
+    /// 上电以来经过的 CPU 周期数
+    pub fn cycles(&self) -> u64 {
+        self.cycles
     }
 
     fn read_prg_rom(&self, addr: u16) -> u8 {
@@ -79,7 +281,99 @@ impl Bus {
         if self.prg_rom.len() == 0x4000 && idx >= 0x4000 { // 仅仅有 lower bank
             idx = idx % 0x4000;
         }
-        self.prg_rom[idx as usize]
+        self.cheats.patch_rom(addr, self.prg_rom[idx as usize])
+    }
+
+    /// CPU 地址当前映射到的 PRG ROM 偏移(不含文件头), 不在 PRG ROM 中或没有 PRG ROM 时为 None
+    ///
+    /// 同一 CPU 地址在切换 bank 后可能对应不同的偏移, 符号与断点据此区分 bank
+    pub fn prg_rom_offset(&self, addr: u16) -> Option<u32> {
+        match addr {
+            0x8000..=0xffff if !self.prg_rom.is_empty() => Some((addr - 0x8000) as u32 % self.prg_rom.len() as u32),
+            _ => None,
+        }
+    }
+
+    /// 当前映射到 PRG ROM 偏移 offset 的所有 CPU 地址, 按从小到大排列
+    pub fn prg_rom_addresses(&self, offset: u32) -> Vec<u16> {
+        if self.prg_rom.is_empty() {
+            return vec![];
+        }
+        (0x8000..=0xffffu32)
+            .step_by(self.prg_rom.len())
+            .map(|bank_start| bank_start + offset)
+            .filter(|&addr| offset < self.prg_rom.len() as u32 && addr <= 0xffff)
+            .map(|addr| addr as u16)
+            .collect()
+    }
+
+    /// 无副作用地读取 PPU 地址空间(pattern table, name table 与调色板)
+    pub fn peek_ppu(&self, addr: u16) -> u8 {
+        self.ppu.peek_ppu(addr)
+    }
+
+    fn mark_prg(&mut self, addr: u16, flags: PrgFlags) {
+        let offset = self.prg_rom_offset(addr);
+        if let (Some(offset), Some(log)) = (offset, self.prg_cdl.as_mut()) {
+            cdl::mark_prg(log, offset as usize, addr, flags);
+        }
+    }
+
+    /// CPU 本次访存所在的周期
+    fn access_cycle(&mut self) -> u64 {
+        let cycle = self.cycles + self.cycles_ahead;
+        self.cycles_ahead += 1;
+        cycle
+    }
+
+    /// CPU 的一次读取, flags 为空时不计入 CDL
+    fn cpu_read(&mut self, addr: u16, flags: PrgFlags) -> u8 {
+        let cycle = self.access_cycle();
+        let data = self.mem_read(addr);
+        if !flags.is_empty() {
+            self.mark_prg(addr, flags);
+        }
+        if let Some(log) = self.access_log.as_mut() {
+            log.push(BusAccess { addr, data, kind: AccessKind::Read });
+        }
+        if self.hooks.wants(HookEvents::CPU_READ) {
+            self.hooks.emit(BusEvent::CpuRead { addr, data, cycle });
+        }
+        data
+    }
+
+    /// CPU 周期 cycle 开始时的光栅位置(扫描线, 点), cycle 可以位于总线尚未经过的周期中
+    fn raster_position(&self, cycle: u64) -> (u16, u16) {
+        let dots = self.ppu.cycle() as u64 + 3 * (cycle - self.cycles);
+        let scanline = (self.ppu.scanline() as u64 + dots / 341) % 262;
+        (scanline as u16, (dots % 341) as u16)
+    }
+
+    fn record_frame_event(&mut self, cycle: u64, kind: FrameEventKind) {
+        let (scanline, dot) = self.raster_position(cycle);
+        if let (false, Some(viewer)) = (self.speculative, self.event_viewer.as_mut()) {
+            viewer.push(scanline, dot, kind);
+        }
+    }
+
+    fn record_write_event(&mut self, addr: u16, data: u8, cycle: u64) {
+        let kind = match addr {
+            0x2000..=0x3fff | 0x4014 => FrameEventKind::PpuRegisterWrite { addr, data },
+            0x4000..=0x4017 => FrameEventKind::IoRegisterWrite { addr, data },
+            0x4020..=0x5fff | 0x8000..=0xffff => FrameEventKind::MapperRegisterWrite { addr, data },
+            _ => return,
+        };
+        self.record_frame_event(cycle, kind);
+    }
+
+    fn emit_write_events(&mut self, addr: u16, data: u8, cycle: u64) {
+        self.hooks.emit(BusEvent::CpuWrite { addr, data, cycle });
+        match addr {
+            0x2000..=0x3fff | 0x4014 => self.hooks.emit(BusEvent::PpuRegisterWrite { addr, data, cycle }),
+            0x4000..=0x4013 | 0x4015 | 0x4017 => self.hooks.emit(BusEvent::ApuRegisterWrite { addr, data, cycle }),
+            0x4020..=0x5fff | 0x8000..=0xffff => self.hooks.emit(BusEvent::MapperRegisterWrite { addr, data, cycle }),
+            _ => {}
+        }
     }
 
     pub(crate) fn io_interface(&mut self) -> (&Frame, &mut Joypad, &mut Samples) {
@@ -91,24 +385,97 @@ impl Bus {
     }
 }
 
+impl Snapshot for Bus {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.section(b"BUS ", |w| {
+            w.bytes(&self.cpu_vram);
+            w.u64(self.cycles);
+            w.u64(self.cycles_ahead);
+            w.bool(self.nmi_line_level);
+            w.bool(self.irq_line_level);
+            w.bool(self.input_polled);
+            w.bool(self.lag_frame);
+            w.u64(self.lag_count);
+        });
+        writer.section(b"PPU ", |w| self.ppu.save(w));
+        writer.section(b"APU ", |w| self.apu.save(w));
+        writer.section(b"JOYP", |w| self.joypad.save(w));
+        writer.section(b"MAPR", |w| w.bytes(&self.prg_ram));
+    }
+
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        reader.section(b"BUS ", |r| {
+            r.bytes(&mut self.cpu_vram)?;
+            self.cycles = r.u64()?;
+            self.cycles_ahead = r.u64()?;
+            self.nmi_line_level = r.bool()?;
+            self.irq_line_level = r.bool()?;
+            self.input_polled = r.bool()?;
+            self.lag_frame = r.bool()?;
+            self.lag_count = r.u64()?;
+            Ok(())
+        })?;
+        reader.section(b"PPU ", |r| self.ppu.load(r))?;
+        reader.section(b"APU ", |r| self.apu.load(r))?;
+        reader.section(b"JOYP", |r| self.joypad.load(r))?;
+        reader.section(b"MAPR", |r| r.bytes(&mut self.prg_ram))
+    }
+}
+
 impl Clock for Bus {
     type Result = bool; // 返回值表示是否到达帧末
     fn clock(&mut self) -> bool {
+        let cycle = self.cycles;
+        let scanline_before = self.ppu.scanline();
+        let frame_before = self.ppu.frame_count();
         let vblank_started_before = self.ppu.vblank_started();
+        let sprite_zero_hit_before = self.ppu.sprite_zero_hit();
         self.ppu.clock();
         let vblank_started_after = self.ppu.vblank_started();
         self.apu.clock();
 
         if let Some(addr) = self.apu.request_dma() {
             let data = self.mem_read(addr);
+            self.mark_prg(addr, PrgFlags::PCM_DATA);
             self.apu.load_dma_data(data);
         }
 
         self.nmi_line_level = self.ppu.nmi_line_level();
         self.irq_line_level = self.apu.irq_line_level();
         self.cycles += 1;
+        self.cycles_ahead = self.cycles_ahead.saturating_sub(1);
 
-        !vblank_started_before && vblank_started_after
+        let frame_end = !vblank_started_before && vblank_started_after;
+        if frame_end {
+            self.lag_frame = !self.input_polled;
+            self.lag_count += self.lag_frame as u64;
+            self.input_polled = false;
+        }
+        if frame_end && !self.cheats.ram_writes().is_empty() {
+            self.apply_ram_cheats();
+        }
+        if self.hooks.wants(HookEvents::SCANLINE | HookEvents::FRAME_END) {
+            let scanline = self.ppu.scanline();
+            if scanline != scanline_before {
+                self.hooks.emit(BusEvent::Scanline { scanline, frame: self.ppu.frame_count(), cycle });
+            }
+            if frame_end {
+                self.hooks.emit(BusEvent::FrameEnd { frame: self.ppu.frame_count(), cycle });
+            }
+        }
+        if let (false, Some(viewer)) = (self.speculative, self.event_viewer.as_mut()) {
+            let (scanline, dot) = (self.ppu.scanline(), self.ppu.cycle());
+            if self.ppu.frame_count() != frame_before {
+                viewer.end_frame();
+            }
+            if !sprite_zero_hit_before && self.ppu.sprite_zero_hit() {
+                viewer.push(scanline, dot, FrameEventKind::Sprite0Hit);
+            }
+            if frame_end {
+                viewer.push(scanline, dot, FrameEventKind::VblankStart);
+            }
+        }
+        frame_end
     }
 }
 
@@ -131,12 +498,11 @@ impl Mem for Bus {
                 self.mem_read(mirror_down_addr)
             }
             0x4000..=0x4013 | 0x4015 => self.apu.mem_read(addr),
-            0x4016 => {
-                self.joypad.read(joypad::PlayerId::P1)
-            }
-            0x4017 => {
-                self.joypad.read(joypad::PlayerId::P2)
+            0x4016 | 0x4017 => {
+                self.input_polled = true;
+                self.joypad.read(controller_port(addr), &beam(&self.ppu))
             }
+            0x6000..=0x7fff => self.prg_ram[(addr - 0x6000) as usize],
             0x8000..=0xffff => { // PRG ROM
                 self.read_prg_rom(addr)
             }
@@ -180,6 +546,7 @@ impl Mem for Bus {
             0x4016 => { // 写 0x4016 用来控制所有 joypad
                 self.joypad.write(data);
             }
+            0x6000..=0x7fff => self.prg_ram[(addr - 0x6000) as usize] = data,
             0x8000..=0xffff => { // PRG ROM
                 log::warn!("Attempt to write to read-only Cartridge ROM space address {:04x}", addr);
             }
@@ -188,4 +555,163 @@ impl Mem for Bus {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+impl Peek for Bus {
+    /// 与 mem_read 的映射相同, 但不产生任何副作用
+    fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            0..=0x1fff => self.cpu_vram[(addr & 0b0000_0111_1111_1111) as usize],
+            0x2002 => self.ppu.peek_status(),
+            0x2004 => self.ppu.read_oam_data(),
+            0x2007 => self.ppu.peek_data(),
+            0x2008..=0x3fff => self.peek(addr & 0b0010_0000_0000_0111),
+            0x4000..=0x4013 | 0x4015 => self.apu.peek(addr),
+            0x4016 | 0x4017 => self.joypad.peek(controller_port(addr), &beam(&self.ppu)),
+            0x6000..=0x7fff => self.prg_ram[(addr - 0x6000) as usize],
+            0x8000..=0xffff => self.read_prg_rom(addr),
+            _ => 0, // 只写寄存器与未使用的地址
+        }
+    }
+}
+
+impl CpuBus for Bus {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.cpu_read(addr, PrgFlags::DATA)
+    }
+
+    fn fetch(&mut self, addr: u16) -> u8 {
+        self.cpu_read(addr, PrgFlags::CODE)
+    }
+
+    fn indirect_read(&mut self, addr: u16) -> u8 {
+        self.cpu_read(addr, PrgFlags::DATA | PrgFlags::INDIRECT_DATA)
+    }
+
+    fn dummy_read(&mut self, addr: u16) -> u8 {
+        self.cpu_read(addr, PrgFlags::empty())
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        let cycle = self.access_cycle();
+        self.mem_write(addr, data);
+        if let Some(log) = self.access_log.as_mut() {
+            log.push(BusAccess { addr, data, kind: AccessKind::Write });
+        }
+        if self.hooks.wants(HookEvents::CPU_WRITE | HookEvents::PPU_REGISTER_WRITE
+            | HookEvents::APU_REGISTER_WRITE | HookEvents::MAPPER_REGISTER_WRITE) {
+            self.emit_write_events(addr, data, cycle);
+        }
+        if self.event_viewer.is_some() && !self.speculative {
+            self.record_write_event(addr, data, cycle);
+        }
+    }
+
+    fn jump_indirect(&mut self, target: u16) {
+        self.mark_prg(target, PrgFlags::INDIRECT_CODE);
+    }
+
+    fn enter_interrupt(&mut self, kind: Interrupt) {
+        let cycle = self.cycles + self.cycles_ahead;
+        if self.hooks.wants(HookEvents::INTERRUPT) {
+            self.hooks.emit(BusEvent::Interrupt { kind, cycle });
+        }
+        match kind {
+            Interrupt::Nmi => self.record_frame_event(cycle, FrameEventKind::Nmi),
+            Interrupt::Irq => self.record_frame_event(cycle, FrameEventKind::Irq),
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self) -> bool {
+        self.clock()
+    }
+
+    fn nmi_line_level(&self) -> bool {
+        self.nmi_line_level
+    }
+
+    fn irq_line_level(&self) -> bool {
+        self.irq_line_level
+    }
+}
+
+fn controller_port(addr: u16) -> ControllerPort {
+    match addr {
+        0x4016 => ControllerPort::Port1,
+        _ => ControllerPort::Port2,
+    }
+}
+
+/// 读取手柄端口时 PPU 的画面与位置
+fn beam(ppu: &Ppu) -> Beam<'_> {
+    Beam { frame: ppu.frame(), scanline: ppu.scanline(), cycle: ppu.cycle() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Bus;
+    use crate::{Cpu, Rom, Mirroring, cartridge::tests::test_rom_with_2_bank_prg};
+
+    #[test]
+    fn test_prg_rom_addresses() {
+        let bus = Bus::new(test_rom_with_2_bank_prg(vec![]));
+        assert_eq!(bus.prg_rom_offset(0xc010), Some(0x4010));
+        assert_eq!(bus.prg_rom_addresses(0x4010), vec![0xc010]);
+
+        let bus = Bus::new(Rom {
+            prg_rom: vec![],
+            chr_rom: vec![0; 0x2000],
+            mapper: 0,
+            screen_mirroring: Mirroring::HORIZONTAL,
+            expansion_device: 0,
+        });
+        assert_eq!(bus.prg_rom_offset(0x8000), None);
+        assert!(bus.prg_rom_addresses(0).is_empty());
+    }
+
+    #[test]
+    fn test_lag_frames() {
+        let mut prg = vec![0xea; 0x8000];
+        let program: &[u8] = &[
+            0xa9, 0x80, // 8000 LDA #$80
+            0x8d, 0x00, 0x20, // 8002 STA $2000
+            0x4c, 0x05, 0x80, // 8005 JMP $8005
+            // NMI: 每两帧读取一次手柄
+            0xe6, 0x10, // 8008 INC $10
+            0xa5, 0x10, // 800A LDA $10
+            0x29, 0x01, // 800C AND #$01
+            0xd0, 0x03, // 800E BNE $8013
+            0xad, 0x16, 0x40, // 8010 LDA $4016
+            0x40, // 8013 RTI
+        ];
+        prg[..program.len()].copy_from_slice(program);
+        prg[0x7ffa..0x7ffc].copy_from_slice(&[0x08, 0x80]); // NMI 向量
+        let mut cpu = Cpu::new(test_rom_with_2_bank_prg(prg));
+        cpu.reset();
+
+        // NMI 在帧末之后执行, 其中的读取属于下一帧
+        let mut lag = vec![];
+        for _ in 0..8 {
+            cpu.run_next_frame().unwrap();
+            lag.push(cpu.bus().lag_frame());
+        }
+        assert_eq!(lag, vec![true, true, false, true, false, true, false, true]);
+        assert_eq!(cpu.bus().lag_count(), 5);
+
+        // lag 帧数属于机器状态, 随存档恢复
+        let state = cpu.save_state();
+        cpu.run_next_frame().unwrap();
+        cpu.run_next_frame().unwrap();
+        assert_eq!(cpu.bus().lag_count(), 6);
+        cpu.load_state(&state).unwrap();
+        assert_eq!((cpu.bus().lag_count(), cpu.bus().lag_frame()), (5, true));
+
+        cpu.bus_mut().set_lag_count(0);
+        cpu.run_next_frame().unwrap();
+        cpu.run_next_frame().unwrap();
+        assert_eq!(cpu.bus().lag_count(), 1);
+        cpu.power_cycle();
+        assert_eq!((cpu.bus().lag_count(), cpu.bus().lag_frame()), (0, false));
+    }
+}
@@ -9,6 +9,7 @@ pub struct Rom {
     pub chr_rom: Vec<u8>, // Character ROM
     pub mapper: u8,
     pub screen_mirroring: Mirroring,
+    pub expansion_device: u8, // NES 2.0 的默认扩展设备, iNES 1.0 为 0 (未指定)
 }
 
 impl Rom {
@@ -30,6 +31,11 @@ impl Rom {
     ///     * 7, 6, 5, 4: mapper type 高四字节
     ///   - 8: 8KB RAM Bank的数目, 为了与以前的iNES格式兼容, 为0时表示RAM的第1页
     ///   - 9, 10, 11, 12, 13, 14, 15: 0
+    /// + NES 2.0 文件头的不同之处
+    ///   - 8: 3, 2, 1, 0: mapper type 第 8..=11 位; 7, 6, 5, 4: submapper
+    ///   - 9: 3, 2, 1, 0: PRG ROM 大小的高四位; 7, 6, 5, 4: CHR ROM 大小的高四位.
+    ///     高四位为 0xF 时, 低字节的 7..=2 位为指数 E, 1, 0 位为 M, 大小为 2^E * (M * 2 + 1) 字节
+    ///   - 15: 5..=0: 默认扩展设备, 见 `Joypad::for_expansion_device`
     /// + (控制字节绝对是否存在)512 字节 trainer
     /// + PRG ROM
     /// + CHR ROM
@@ -38,10 +44,27 @@ impl Rom {
         if &raw[0..4] != NES_TAG { // 4 字节: "NES^Z"
             return Err("File is not in iNES file format".to_string());
         }
-        let prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
-        let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
         let (control1, control2) = (raw[6], raw[7]);
+        let nes2 = control2 & 0b1100 == 0b1000;
+        if control2 & 0b11 != 0 {
+            return Err("VS System and PlayChoice-10 are not supported".to_string());
+        }
+        if !nes2 && control2 & 0b1100 != 0 {
+            return Err("Unknown iNES header format".to_string());
+        }
+        let too_large = || "ROM size in header is too large".to_string();
+        let (prg_rom_size, chr_rom_size) = match nes2 {
+            true => (
+                nes2_rom_size(raw[4], raw[9] & 0x0f, PRG_ROM_PAGE_SIZE).ok_or_else(too_large)?,
+                nes2_rom_size(raw[5], raw[9] >> 4, CHR_ROM_PAGE_SIZE).ok_or_else(too_large)?,
+            ),
+            false => (raw[4] as usize * PRG_ROM_PAGE_SIZE, raw[5] as usize * CHR_ROM_PAGE_SIZE),
+        };
+        if nes2 && raw[8] & 0x0f != 0 {
+            return Err(format!("Mapper {} is not supported", (raw[8] as u16 & 0x0f) << 8 | (control2 & 0xf0 | control1 >> 4) as u16));
+        }
         let mapper = (control2 & 0b1111_0000) | (control1 >> 4);
+        let expansion_device = if nes2 { raw[15] & 0b11_1111 } else { 0 };
         let vertical_mirroring = control1 & 1 == 1;
         let _sram = control1 & 0b10 == 0b10;
         let trainer = control1 & 0b100 == 0b100;
@@ -51,20 +74,30 @@ impl Rom {
             (true, false) => Mirroring::VERTICAL,
             (false, false) => Mirroring::HORIZONTAL,
         };
-        if control2 & 0b1111 != 0 {
-            return Err("NES2.0 format is not supported".to_string());
+        let prg_rom_start: usize = 16 + if trainer {512} else {0};
+        let chr_rom_start = prg_rom_start.checked_add(prg_rom_size).ok_or_else(too_large)?;
+        let chr_rom_end = chr_rom_start.checked_add(chr_rom_size).ok_or_else(too_large)?;
+        if raw.len() < chr_rom_end {
+            return Err("File is shorter than the sizes in its header".to_string());
         }
-        let prg_rom_start = 16 + if trainer {512} else {0};
-        let chr_rom_start = prg_rom_start + prg_rom_size;
         Ok(Rom {
             prg_rom: raw[prg_rom_start..(chr_rom_start)].to_vec(),
-            chr_rom: raw[chr_rom_start..(chr_rom_start + chr_rom_size)].to_vec(),
+            chr_rom: raw[chr_rom_start..chr_rom_end].to_vec(),
             mapper,
             screen_mirroring,
+            expansion_device,
         })
     }
 }
 
+/// NES 2.0 中 PRG/CHR ROM 的大小, lsb 为第 4/5 字节, msb 为第 9 字节中对应的四位, 溢出时为 None
+fn nes2_rom_size(lsb: u8, msb: u8, page_size: usize) -> Option<usize> {
+    match msb {
+        0x0f => 1usize.checked_shl((lsb >> 2) as u32)?.checked_mul((lsb & 0b11) as usize * 2 + 1),
+        _ => ((msb as usize) << 8 | lsb as usize).checked_mul(page_size),
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
 
@@ -102,7 +135,7 @@ pub mod tests {
             ],
             trainer: None,
             prg_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
-            chr_rom: vec![2; 1 * CHR_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
         });
 
         Rom::new(&test_rom).unwrap()
@@ -118,7 +151,7 @@ pub mod tests {
             ],
             trainer: None,
             prg_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
-            chr_rom: vec![2; 1 * CHR_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
         };
         test_rom.prg_rom[0..prg.len()].copy_from_slice(&prg);
         test_rom.prg_rom[0xfffc - 0x8000] = 0x00; // 程序起始地址
@@ -136,12 +169,12 @@ pub mod tests {
             ],
             trainer: None,
             prg_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
-            chr_rom: vec![2; 1 * CHR_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
         });
 
         let rom: Rom = Rom::new(&test_rom).unwrap();
 
-        assert_eq!(rom.chr_rom, vec!(2; 1 * CHR_ROM_PAGE_SIZE));
+        assert_eq!(rom.chr_rom, vec!(2; CHR_ROM_PAGE_SIZE));
         assert_eq!(rom.prg_rom, vec!(1; 2 * PRG_ROM_PAGE_SIZE));
         assert_eq!(rom.mapper, 3);
         assert_eq!(rom.screen_mirroring, Mirroring::VERTICAL);
@@ -170,31 +203,61 @@ pub mod tests {
             ],
             trainer: Some(vec![0; 512]),
             prg_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
-            chr_rom: vec![2; 1 * CHR_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
         });
 
         let rom: Rom = Rom::new(&test_rom).unwrap();
 
-        assert_eq!(rom.chr_rom, vec!(2; 1 * CHR_ROM_PAGE_SIZE));
+        assert_eq!(rom.chr_rom, vec!(2; CHR_ROM_PAGE_SIZE));
         assert_eq!(rom.prg_rom, vec!(1; 2 * PRG_ROM_PAGE_SIZE));
         assert_eq!(rom.mapper, 3);
         assert_eq!(rom.screen_mirroring, Mirroring::VERTICAL);
     }
 
     #[test]
-    fn test_nes2_is_not_supported() {
+    fn test_nes2() {
         let test_rom = create_rom(TestRom {
             header: vec![
-                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x31, 0x8, 00, 00, 00, 00, 00, 00, 00, 00,
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x31, 0x8, 00, 00, 00, 00, 00, 00, 00, 0x08,
             ],
             trainer: None,
-            prg_rom: vec![1; 1 * PRG_ROM_PAGE_SIZE],
-            chr_rom: vec![2; 1 * CHR_ROM_PAGE_SIZE],
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
         });
-        let rom = Rom::new(&test_rom);
-        match rom {
-            Result::Ok(_) => assert!(false, "should not load rom"),
-            Result::Err(str) => assert_eq!(str, "NES2.0 format is not supported"),
-        }
+        let rom = Rom::new(&test_rom).unwrap();
+        assert_eq!(rom.prg_rom, vec!(1; PRG_ROM_PAGE_SIZE));
+        assert_eq!(rom.chr_rom, vec!(2; CHR_ROM_PAGE_SIZE));
+        assert_eq!(rom.mapper, 3);
+        assert_eq!(rom.expansion_device, 0x08);
+
+        // 以指数表示的大小: 2^13 * 1 = 8KB CHR ROM
+        assert_eq!(nes2_rom_size(13 << 2, 0x0f, CHR_ROM_PAGE_SIZE), Some(CHR_ROM_PAGE_SIZE));
+        assert_eq!(nes2_rom_size(0x02, 0x01, PRG_ROM_PAGE_SIZE), Some(0x102 * PRG_ROM_PAGE_SIZE));
+        let mut huge = test_rom.clone();
+        huge[9] = 0x0f;
+        huge[4] = 63 << 2 | 0b11; // 2^63 * 7
+        assert_eq!(Rom::new(&huge).err().unwrap(), "ROM size in header is too large");
+        huge[9] = 0xff;
+        huge[4] = 63 << 2; // PRG/CHR ROM 各 2^63 字节, 大小本身不溢出, 但相加后溢出
+        huge[5] = 63 << 2;
+        assert_eq!(Rom::new(&huge).err().unwrap(), "ROM size in header is too large");
+
+        let mut large_mapper = test_rom.clone();
+        large_mapper[8] = 0x01;
+        assert_eq!(Rom::new(&large_mapper).err().unwrap(), "Mapper 259 is not supported");
+        let mut truncated = test_rom;
+        truncated[4] = 2;
+        assert!(Rom::new(&truncated).is_err());
+        let mut ines = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x31, 0x4, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+        assert_eq!(Rom::new(&ines).err().unwrap(), "Unknown iNES header format");
+        ines[7] = 0x01;
+        assert_eq!(Rom::new(&ines).err().unwrap(), "VS System and PlayChoice-10 are not supported");
     }
 }
\ No newline at end of file
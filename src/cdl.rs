@@ -0,0 +1,224 @@
+//! Code/Data Logger, 记录 PRG ROM 与 CHR ROM 中每个字节的用途, 读写 FCEUX 兼容的 `.cdl` 文件
+//!
+//! `.cdl` 文件依次为 PRG ROM 与 CHR ROM 每个字节对应的一个标志字节, 长度与 ROM 相同.
+
+use std::{fs, io, path::Path};
+
+use bitflags::bitflags;
+
+bitflags! {
+    /// PRG ROM 字节的标志
+    pub struct PrgFlags: u8 {
+        /// 作为指令执行(操作码与操作数)
+        const CODE = 0b0000_0001;
+        /// 作为数据读取
+        const DATA = 0b0000_0010;
+        /// 访问时所在的 CPU 地址: 00 为 $8000, 01 为 $A000, 10 为 $C000, 11 为 $E000
+        const BANK = 0b0000_1100;
+        /// 作为 `JMP (abs)` 的跳转目标执行
+        const INDIRECT_CODE = 0b0001_0000;
+        /// 通过 `(zp,X)` 或 `(zp),Y` 间接寻址读取
+        const INDIRECT_DATA = 0b0010_0000;
+        /// 被 DMC 作为 sample 读取
+        const PCM_DATA = 0b0100_0000;
+    }
+}
+
+bitflags! {
+    /// CHR ROM 字节的标志
+    pub struct ChrFlags: u8 {
+        /// 被 PPU 用于渲染
+        const RENDERED = 0b0000_0001;
+        /// 被 CPU 通过 $2007 读取
+        const READ = 0b0000_0010;
+    }
+}
+
+/// 各类字节所占的比例, 取值 0.0..=100.0
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CdlCoverage {
+    pub prg_code: f32,
+    pub prg_data: f32,
+    pub prg_pcm: f32,
+    /// 任意用途
+    pub prg_total: f32,
+    pub chr_rendered: f32,
+    pub chr_read: f32,
+    /// 任意用途
+    pub chr_total: f32,
+}
+
+/// 一次记录的结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeDataLog {
+    pub(crate) prg: Vec<u8>,
+    pub(crate) chr: Vec<u8>,
+}
+
+impl CodeDataLog {
+    pub fn new(prg_len: usize, chr_len: usize) -> Self {
+        CodeDataLog {
+            prg: vec![0; prg_len],
+            chr: vec![0; chr_len],
+        }
+    }
+
+    /// 从 `.cdl` 文件的内容创建, 长度必须与 ROM 一致
+    pub fn from_bytes(data: &[u8], prg_len: usize, chr_len: usize) -> io::Result<Self> {
+        if data.len() != prg_len + chr_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("CDL size {} does not match ROM size {}", data.len(), prg_len + chr_len),
+            ));
+        }
+        Ok(CodeDataLog {
+            prg: data[..prg_len].to_vec(),
+            chr: data[prg_len..].to_vec(),
+        })
+    }
+
+    /// 检查记录的长度是否与 ROM 一致
+    pub(crate) fn check_len(&self, prg_len: usize, chr_len: usize) -> io::Result<()> {
+        if self.prg.len() != prg_len || self.chr.len() != chr_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "CDL size {}+{} does not match ROM size {}+{}",
+                    self.prg.len(), self.chr.len(), prg_len, chr_len,
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [&self.prg[..], &self.chr[..]].concat()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P, prg_len: usize, chr_len: usize) -> io::Result<Self> {
+        Self::from_bytes(&fs::read(path)?, prg_len, chr_len)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+
+    pub fn prg_flags(&self, offset: usize) -> PrgFlags {
+        PrgFlags::from_bits_truncate(self.prg[offset])
+    }
+
+    pub fn chr_flags(&self, offset: usize) -> ChrFlags {
+        ChrFlags::from_bits_truncate(self.chr[offset])
+    }
+
+    pub fn coverage(&self) -> CdlCoverage {
+        let percent = |log: &[u8], mask: u8| {
+            if log.is_empty() {
+                0.0
+            } else {
+                log.iter().filter(|&&flags| flags & mask != 0).count() as f32 * 100.0 / log.len() as f32
+            }
+        };
+        let prg_used = (PrgFlags::all() - PrgFlags::BANK).bits();
+        CdlCoverage {
+            prg_code: percent(&self.prg, PrgFlags::CODE.bits()),
+            prg_data: percent(&self.prg, PrgFlags::DATA.bits()),
+            prg_pcm: percent(&self.prg, PrgFlags::PCM_DATA.bits()),
+            prg_total: percent(&self.prg, prg_used),
+            chr_rendered: percent(&self.chr, ChrFlags::RENDERED.bits()),
+            chr_read: percent(&self.chr, ChrFlags::READ.bits()),
+            chr_total: percent(&self.chr, ChrFlags::all().bits()),
+        }
+    }
+}
+
+/// 记录 CPU 地址 addr(对应 PRG ROM 偏移 offset)处的一次访问
+#[inline]
+pub(crate) fn mark_prg(prg: &mut [u8], offset: usize, addr: u16, flags: PrgFlags) {
+    let bank = ((addr >> 13) & 0b11) as u8;
+    prg[offset] |= flags.bits() | (bank << 2);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cpu, cartridge::tests::test_rom_with_2_bank_prg};
+
+    #[test]
+    fn test_record_code_and_data() {
+        let mut cpu = Cpu::new(test_rom_with_2_bank_prg(vec![
+            0xad, 0x00, 0xc0, // 8000 LDA $C000
+            0xb9, 0x01, 0xc0, // 8003 LDA $C001,Y
+            0xa9, 0x00, // 8006 LDA #$00
+            0x8d, 0x06, 0x20, // 8008 STA $2006
+            0xa9, 0x10, // 800B LDA #$10
+            0x8d, 0x06, 0x20, // 800D STA $2006
+            0xad, 0x07, 0x20, // 8010 LDA $2007
+            0x4c, 0x13, 0x80, // 8013 JMP $8013
+        ]));
+        cpu.reset();
+        cpu.bus_mut().start_code_data_log();
+        for _ in 0..8 {
+            cpu.run_next_instruction();
+        }
+        let log = cpu.bus_mut().take_code_data_log().unwrap();
+        assert!(cpu.bus_mut().take_code_data_log().is_none());
+
+        for offset in 0..0x16 {
+            assert_eq!(log.prg_flags(offset), PrgFlags::CODE, "offset {:04x}", offset);
+        }
+        assert_eq!(log.prg_flags(0x16), PrgFlags::empty());
+        // $C000 位于第 3 个 8KB 窗口
+        assert_eq!(log.prg_flags(0x4000).bits(), 0b1010);
+        assert_eq!(log.prg_flags(0x4001).bits(), 0b1010);
+        assert_eq!(log.chr_flags(0x0010), ChrFlags::READ);
+        assert_eq!(log.chr_flags(0x0011), ChrFlags::empty());
+
+        let coverage = log.coverage();
+        assert_eq!(coverage.prg_code, 0x16 as f32 * 100.0 / 0x8000 as f32);
+        assert_eq!(coverage.prg_total, 0x18 as f32 * 100.0 / 0x8000 as f32);
+        assert_eq!(coverage.chr_read, 100.0 / 0x2000 as f32);
+        assert_eq!(coverage.chr_rendered, 0.0);
+
+        let bytes = log.to_bytes();
+        assert_eq!(bytes.len(), 0xa000);
+        assert_eq!(CodeDataLog::from_bytes(&bytes, 0x8000, 0x2000).unwrap(), log);
+        assert!(CodeDataLog::from_bytes(&bytes, 0x8000, 0x1000).is_err());
+
+        assert!(cpu.bus_mut().set_code_data_log(Some(CodeDataLog::new(0x4000, 0x2000))).is_err());
+        assert!(cpu.bus().code_data_log().is_none());
+        cpu.bus_mut().set_code_data_log(Some(log.clone())).unwrap();
+        assert_eq!(cpu.bus().code_data_log(), Some(log));
+    }
+
+    #[test]
+    fn test_record_indirect_access() {
+        let mut prg = vec![
+            0xa9, 0x00, 0x85, 0x00, // 8000 LDA #$00; STA $00
+            0xa9, 0xc0, 0x85, 0x01, // 8004 LDA #$C0; STA $01
+            0xa0, 0x02, // 8008 LDY #$02
+            0xb1, 0x00, // 800A LDA ($00),Y
+            0xa2, 0x00, // 800C LDX #$00
+            0xa1, 0x00, // 800E LDA ($00,X)
+            0x6c, 0x10, 0xc0, // 8010 JMP ($C010)
+        ];
+        prg.resize(0x4020, 0xea);
+        prg[0x4010] = 0x20; // 间接跳转到 $8020
+        prg[0x4011] = 0x80;
+        let mut cpu = Cpu::new(test_rom_with_2_bank_prg(prg));
+        cpu.reset();
+        cpu.bus_mut().start_code_data_log();
+        for _ in 0..10 {
+            cpu.run_next_instruction();
+        }
+        let log = cpu.bus_mut().take_code_data_log().unwrap();
+
+        let indirect_data = PrgFlags::DATA | PrgFlags::INDIRECT_DATA;
+        assert_eq!(log.prg_flags(0x4002) - PrgFlags::BANK, indirect_data);
+        assert_eq!(log.prg_flags(0x4000) - PrgFlags::BANK, indirect_data);
+        // 跳转向量本身只是普通的数据
+        assert_eq!(log.prg_flags(0x4010) - PrgFlags::BANK, PrgFlags::DATA);
+        assert_eq!(log.prg_flags(0x0020), PrgFlags::CODE | PrgFlags::INDIRECT_CODE);
+        assert_eq!(log.prg_flags(0x0021), PrgFlags::empty());
+    }
+}
@@ -0,0 +1,305 @@
+//! 金手指: Game Genie, Pro Action Replay 与 `地址:值` 形式的 RAM 代码
+//!
+//! Game Genie 代码替换 CPU 从 PRG ROM 读到的值(8 位代码只在原值等于比较值时替换),
+//! PAR 与 RAM 代码则在每帧末(vblank 开始时)写入 CPU RAM 或 $6000 起的 PRG RAM.
+//!
+//! 金手指文件每行一个代码, `#` 开头的行为注释, 以 `*` 开头的代码默认关闭. 支持两种写法:
+//! - `代码 [名称]`, 如 `SXIOPO Infinite lives` 或 `0075:09 Infinite lives`
+//! - FCEUX 格式 `[S][C]:地址:值[:比较值]:名称`, S 表示替换 ROM, C 表示带比较值
+
+use std::{error::Error, fmt, fs, path::Path};
+
+/// Game Genie 使用的 16 个字母, 依次表示 0-15
+const GAME_GENIE_LETTERS: &[u8; 16] = b"APZLGITYEOXUKSVN";
+
+/// 解析后的代码
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheatCode {
+    /// 替换从 PRG ROM 地址 addr 读到的值, 有 compare 时只在原值等于 compare 时替换
+    Rom { addr: u16, value: u8, compare: Option<u8> },
+    /// 每帧向 RAM 地址 addr 写入 value
+    Ram { addr: u16, value: u8 },
+}
+
+impl CheatCode {
+    /// 解析 Game Genie(6 或 8 个字母), PAR(`AAAAVV` 6 位十六进制) 或 `AAAA:VV` 形式的代码
+    ///
+    /// 同时符合 Game Genie 与 PAR 格式的代码(只含 A 与 E)按 Game Genie 解析
+    pub fn parse(code: &str) -> Result<Self, CheatError> {
+        let code = code.trim().replace('-', "").to_ascii_uppercase();
+        if let Some((addr, value)) = code.split_once(':') {
+            return Ok(CheatCode::Ram { addr: parse_addr(addr)?, value: parse_hex_byte(value)? });
+        }
+        if let Some(code) = Self::parse_game_genie(&code) {
+            return Ok(code);
+        }
+        if code.len() == 6 && code.bytes().all(|c| c.is_ascii_hexdigit()) {
+            let raw = parse_hex(&code)?;
+            return Ok(CheatCode::Ram { addr: (raw >> 8) as u16, value: raw as u8 });
+        }
+        Err(CheatError::new(format!("invalid cheat code '{}'", code)))
+    }
+
+    /// Game Genie 代码, 字母 n0..n7 的各个 bit 重新排列为地址, 值与比较值
+    fn parse_game_genie(code: &str) -> Option<Self> {
+        let n = code.bytes()
+            .map(|c| GAME_GENIE_LETTERS.iter().position(|&letter| letter == c).map(|n| n as u16))
+            .collect::<Option<Vec<_>>>()?;
+        if n.len() != 6 && n.len() != 8 {
+            return None;
+        }
+        let addr = 0x8000
+            | ((n[3] & 7) << 12)
+            | ((n[5] & 7) << 8) | ((n[4] & 8) << 8)
+            | ((n[2] & 7) << 4) | ((n[1] & 8) << 4)
+            | (n[4] & 7) | (n[3] & 8);
+        let value = ((n[1] & 7) << 4) | ((n[0] & 8) << 4) | (n[0] & 7);
+        if n.len() == 6 {
+            Some(CheatCode::Rom { addr, value: (value | (n[5] & 8)) as u8, compare: None })
+        } else {
+            let compare = ((n[7] & 7) << 4) | ((n[6] & 8) << 4) | (n[6] & 7) | (n[5] & 8);
+            Some(CheatCode::Rom { addr, value: (value | (n[7] & 8)) as u8, compare: Some(compare as u8) })
+        }
+    }
+}
+
+/// PAR 代码的 24 位数值
+fn parse_hex(text: &str) -> Result<u32, CheatError> {
+    u32::from_str_radix(text.trim(), 16)
+        .ok()
+        .filter(|&value| value <= 0xffffff)
+        .ok_or_else(|| CheatError::new(format!("invalid hex number '{}'", text)))
+}
+
+fn parse_addr(text: &str) -> Result<u16, CheatError> {
+    u16::from_str_radix(text.trim(), 16).map_err(|_| CheatError::new(format!("invalid address '{}'", text)))
+}
+
+fn parse_hex_byte(text: &str) -> Result<u8, CheatError> {
+    u8::from_str_radix(text.trim(), 16).map_err(|_| CheatError::new(format!("invalid byte '{}'", text)))
+}
+
+/// 代码解析错误, line 为金手指文件中的行号(从 1 开始), 单独解析代码时为 0
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheatError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl CheatError {
+    fn new(message: String) -> Self {
+        CheatError { line: 0, message }
+    }
+}
+
+impl fmt::Display for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line > 0 {
+            write!(f, "line {}: {}", self.line, self.message)
+        } else {
+            write!(f, "{}", self.message)
+        }
+    }
+}
+
+impl Error for CheatError {}
+
+/// 一个金手指
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cheat {
+    pub code: CheatCode,
+    pub name: String,
+    pub enabled: bool,
+}
+
+/// 金手指列表, 通过 [`Bus::cheats_mut`](crate::Bus::cheats_mut) 修改
+#[derive(Debug, Clone, Default)]
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+    // 已开启的代码, 修改列表时重新生成
+    rom_patches: Vec<(u16, u8, Option<u8>)>,
+    ram_writes: Vec<(u16, u8)>,
+}
+
+impl Cheats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 解析并添加一个开启的代码, 返回其下标
+    pub fn add(&mut self, code: &str, name: &str) -> Result<usize, CheatError> {
+        let code = CheatCode::parse(code)?;
+        self.push(Cheat { code, name: name.to_string(), enabled: true });
+        Ok(self.cheats.len() - 1)
+    }
+
+    pub fn push(&mut self, cheat: Cheat) {
+        self.cheats.push(cheat);
+        self.rebuild();
+    }
+
+    pub fn remove(&mut self, index: usize) -> Cheat {
+        let cheat = self.cheats.remove(index);
+        self.rebuild();
+        cheat
+    }
+
+    pub fn clear(&mut self) {
+        self.cheats.clear();
+        self.rebuild();
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) {
+        self.cheats[index].enabled = enabled;
+        self.rebuild();
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    pub fn len(&self) -> usize {
+        self.cheats.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cheats.is_empty()
+    }
+
+    pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), CheatError> {
+        let text = fs::read_to_string(path).map_err(|err| CheatError::new(err.to_string()))?;
+        self.load(&text)
+    }
+
+    /// 从金手指文件的内容添加代码, 出错时不添加任何代码
+    pub fn load(&mut self, text: &str) -> Result<(), CheatError> {
+        let mut cheats = vec![];
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let cheat = Self::parse_line(line).map_err(|err| CheatError { line: idx + 1, ..err })?;
+            cheats.push(cheat);
+        }
+        self.cheats.extend(cheats);
+        self.rebuild();
+        Ok(())
+    }
+
+    fn parse_line(line: &str) -> Result<Cheat, CheatError> {
+        let (enabled, line) = match line.strip_prefix('*') {
+            Some(line) => (false, line.trim_start()),
+            None => (true, line),
+        };
+        let (flags, rest) = line.split_once(':').unwrap_or(("", line));
+        let is_fceux = line.contains(':') && flags.chars().all(|c| c == 'S' || c == 'C');
+        if !is_fceux {
+            let (code, name) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            return Ok(Cheat { code: CheatCode::parse(code)?, name: name.trim().to_string(), enabled });
+        }
+
+        let has_compare = flags.contains('C');
+        let fields = if has_compare { 4 } else { 3 };
+        let parts: Vec<_> = rest.splitn(fields, ':').collect();
+        if parts.len() < fields - 1 {
+            return Err(CheatError::new(format!("invalid cheat line '{}'", line)));
+        }
+        let addr = parse_addr(parts[0])?;
+        let value = parse_hex_byte(parts[1])?;
+        let compare = if has_compare { Some(parse_hex_byte(parts[2])?) } else { None };
+        let name = parts.get(fields - 1).unwrap_or(&"").trim().to_string();
+        let code = if flags.contains('S') {
+            CheatCode::Rom { addr, value, compare }
+        } else {
+            CheatCode::Ram { addr, value }
+        };
+        Ok(Cheat { code, name, enabled })
+    }
+
+    fn rebuild(&mut self) {
+        self.rom_patches.clear();
+        self.ram_writes.clear();
+        for cheat in self.cheats.iter().filter(|cheat| cheat.enabled) {
+            match cheat.code {
+                CheatCode::Rom { addr, value, compare } => self.rom_patches.push((addr, value, compare)),
+                CheatCode::Ram { addr, value } => self.ram_writes.push((addr, value)),
+            }
+        }
+    }
+
+    /// CPU 从 PRG ROM 地址 addr 读到 data 时实际得到的值
+    #[inline]
+    pub(crate) fn patch_rom(&self, addr: u16, data: u8) -> u8 {
+        if self.rom_patches.is_empty() {
+            return data;
+        }
+        self.rom_patches.iter()
+            .find(|&&(patch_addr, _, compare)| patch_addr == addr && compare.is_none_or(|compare| compare == data))
+            .map_or(data, |&(_, value, _)| value)
+    }
+
+    /// 每帧需要写入 RAM 的值
+    pub(crate) fn ram_writes(&self) -> &[(u16, u8)] {
+        &self.ram_writes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cpu, Peek, cartridge::tests::test_rom_with_2_bank_prg};
+
+    #[test]
+    fn test_parse_codes() {
+        // Super Mario Bros. 无限生命
+        assert_eq!(CheatCode::parse("SXIOPO").unwrap(), CheatCode::Rom { addr: 0x91d9, value: 0xad, compare: None });
+        assert_eq!(CheatCode::parse("yeuzugaa").unwrap(), CheatCode::Rom { addr: 0xacb3, value: 0x07, compare: Some(0x00) });
+        assert_eq!(CheatCode::parse("007509").unwrap(), CheatCode::Ram { addr: 0x0075, value: 0x09 });
+        assert_eq!(CheatCode::parse("6001:ff").unwrap(), CheatCode::Ram { addr: 0x6001, value: 0xff });
+        assert!(CheatCode::parse("SXIOP").is_err());
+        assert!(CheatCode::parse("0075:100").is_err());
+        assert!(CheatCode::parse("10075:09").is_err());
+
+        let mut cheats = Cheats::new();
+        let err = cheats.load("SXIOPO lives\nnot a code\n").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert!(cheats.is_empty());
+        assert_eq!(cheats.load("SC:18001:42:01:Patched\n").unwrap_err().line, 1);
+        cheats.load("# comment\nSXIOPO Infinite lives\n*0075:09\nSC:8001:42:01:Patched\n:0010:05:Ram\n").unwrap();
+        assert_eq!(cheats.cheats(), &[
+            Cheat { code: CheatCode::Rom { addr: 0x91d9, value: 0xad, compare: None }, name: "Infinite lives".into(), enabled: true },
+            Cheat { code: CheatCode::Ram { addr: 0x0075, value: 0x09 }, name: "".into(), enabled: false },
+            Cheat { code: CheatCode::Rom { addr: 0x8001, value: 0x42, compare: Some(0x01) }, name: "Patched".into(), enabled: true },
+            Cheat { code: CheatCode::Ram { addr: 0x0010, value: 0x05 }, name: "Ram".into(), enabled: true },
+        ]);
+    }
+
+    #[test]
+    fn test_apply_cheats() {
+        let mut cpu = Cpu::new(test_rom_with_2_bank_prg(vec![
+            0xad, 0x10, 0x80, // 8000 LDA $8010
+            0x85, 0x20, // 8003 STA $20
+            0xe6, 0x10, // 8005 INC $10
+            0xee, 0x00, 0x60, // 8007 INC $6000
+            0x4c, 0x00, 0x80, // 800A JMP $8000
+        ]));
+        cpu.reset();
+        let cheats = cpu.bus_mut().cheats_mut();
+        cheats.load("SC:8010:55:01:\nSC:8011:66:02:\n0010:80\n6000:40\n").unwrap();
+
+        cpu.run_next_frame().unwrap();
+        assert_eq!(cpu.bus().peek(0x8010), 0x55); // 比较值相同, 替换
+        assert_eq!(cpu.bus().peek(0x8011), 0x01); // 比较值不同, 不替换
+        assert_eq!(cpu.bus().peek(0x0020), 0x55);
+        // 帧末写入 RAM 与 PRG RAM, 之后的指令继续修改
+        assert!((0x80..0x90).contains(&cpu.bus().peek(0x0010)));
+        assert!((0x40..0x50).contains(&cpu.bus().peek(0x6000)));
+
+        cpu.bus_mut().cheats_mut().set_enabled(0, false);
+        assert_eq!(cpu.bus().peek(0x8010), 0x01);
+        cpu.bus_mut().cheats_mut().clear();
+        cpu.run_next_frame().unwrap();
+        assert_ne!(cpu.bus().peek(0x6000), 0x40);
+    }
+}
@@ -7,7 +7,7 @@ pub(crate) trait Mem {
     /// 按照 Little-Endian 读取 2 字节
     fn mem_read_u16(&mut self, addr: u16) -> u16 {
         let lo = self.mem_read(addr) as u16;
-        let hi = self.mem_read(addr + 1) as u16;
+        let hi = self.mem_read(addr.wrapping_add(1)) as u16;
         (hi << 8) | lo
     }
 
@@ -16,7 +16,7 @@ pub(crate) trait Mem {
         let hi = (data >> 8) as u8;
         let lo = (data & 0xff) as u8;
         self.mem_write(addr, lo);
-        self.mem_write(addr + 1, hi);
+        self.mem_write(addr.wrapping_add(1), hi);
     }
 }
 
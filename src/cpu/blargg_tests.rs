@@ -0,0 +1,85 @@
+//! blargg 的 cpu_interrupts_v2 测试 ROM 的运行器
+//!
+//! 测试 ROM 将结果写在 $6000 起的 PRG RAM 中: $6001..=$6003 为签名 `DE B0 61`, $6000 为状态
+//! (0x80 表示运行中, 0x81 表示需要按下 reset, 其余为结果码, 0 表示通过), $6004 起为以 0 结尾的文本.
+//! ROM 不随仓库提供, 且目前只支持 NROM(使用其他 mapper 的 ROM 会载入失败), 因而测试默认忽略.
+//! 将环境变量 `CNES_CPU_INTERRUPTS_TESTS` 设为 `cpu_interrupts_v2/rom_singles` 所在目录后运行
+//! `cargo test cpu_interrupts -- --ignored`
+
+use std::{fs, path::Path};
+
+use super::*;
+
+const SIGNATURE: [u8; 3] = [0xde, 0xb0, 0x61];
+const STATUS_RUNNING: u8 = 0x80;
+const STATUS_NEED_RESET: u8 = 0x81;
+/// 单个 ROM 最多运行的帧数
+const MAX_FRAMES: usize = 60 * 60;
+/// 要求 reset 后等待的帧数, ROM 要求至少等待 100ms
+const RESET_DELAY_FRAMES: usize = 10;
+
+/// 运行一个测试 ROM, 返回结果码与输出的文本
+fn run_rom(path: &Path) -> Result<(u8, String), String> {
+    let rom = Rom::new(&fs::read(path).map_err(|err| err.to_string())?)?;
+    let mut cpu = Cpu::new(rom);
+    cpu.reset();
+    let mut reset_at = None;
+    for frame in 0..MAX_FRAMES {
+        cpu.run_next_frame().map_err(|err| err.to_string())?;
+        let bus = cpu.bus();
+        if (0..3).any(|i| bus.peek(0x6001 + i) != SIGNATURE[i as usize]) {
+            continue;
+        }
+        match bus.peek(0x6000) {
+            STATUS_RUNNING => {}
+            STATUS_NEED_RESET => match reset_at {
+                None => reset_at = Some(frame + RESET_DELAY_FRAMES),
+                Some(at) if at == frame => {
+                    cpu.reset();
+                    reset_at = None;
+                }
+                Some(_) => {}
+            },
+            result => return Ok((result, read_text(bus))),
+        }
+    }
+    Err(format!("no result after {} frames", MAX_FRAMES))
+}
+
+/// $6004 起以 0 结尾的文本
+fn read_text(bus: &Bus) -> String {
+    (0x6004..0x8000u16)
+        .map(|addr| bus.peek(addr))
+        .take_while(|&byte| byte != 0)
+        .map(|byte| byte as char)
+        .collect()
+}
+
+#[test]
+#[ignore = "needs blargg's cpu_interrupts_v2 ROMs in CNES_CPU_INTERRUPTS_TESTS; only NROM is supported"]
+fn cpu_interrupts() {
+    let dir = std::env::var("CNES_CPU_INTERRUPTS_TESTS").expect("CNES_CPU_INTERRUPTS_TESTS is not set");
+
+    let mut paths = fs::read_dir(&dir)
+        .expect("failed to read CNES_CPU_INTERRUPTS_TESTS")
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "nes"))
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    let mut failed_roms = vec![];
+    for path in paths {
+        match run_rom(&path) {
+            Ok((0, _)) => eprintln!("{}: passed", path.display()),
+            Ok((result, text)) => {
+                eprintln!("{}: failed with result {}\n{}", path.display(), result, text.trim_end());
+                failed_roms.push(path.display().to_string());
+            }
+            Err(err) => {
+                eprintln!("{}: {}", path.display(), err);
+                failed_roms.push(path.display().to_string());
+            }
+        }
+    }
+    assert!(failed_roms.is_empty(), "failed: {:?}", failed_roms);
+}
@@ -0,0 +1,182 @@
+use super::Interrupt;
+
+/// 无副作用地读取内存
+///
+/// 返回在该地址读取将得到的值, 但不改变任何状态(如 PPU 的读缓冲, 手柄的移位寄存器),
+/// 用于反汇编, trace 与调试器
+pub trait Peek {
+    fn peek(&self, addr: u16) -> u8;
+}
+
+/// CPU 所连接的总线
+///
+/// CPU 的所有访存都经过总线, 并且每经过一个 CPU 周期调用一次 `tick`.
+/// 实现该 trait 便可以让 6502 核心运行在 NES 以外的机器上, 如用于测试的 64KB 平坦内存.
+/// 总线归 CPU 所有, 因而不能借用外部的数据.
+pub trait CpuBus: Peek + 'static {
+    /// 读取地址 addr 处的一个字节
+    fn read(&mut self, addr: u16) -> u8;
+
+    /// 读取指令的操作码或操作数, 默认与 `read` 相同
+    fn fetch(&mut self, addr: u16) -> u8 {
+        self.read(addr)
+    }
+
+    /// 读取经 `(zp,X)` 或 `(zp),Y` 间接寻址得到的操作数, 默认与 `read` 相同
+    fn indirect_read(&mut self, addr: u16) -> u8 {
+        self.read(addr)
+    }
+
+    /// 结果被丢弃的空读, 默认与 `read` 相同
+    fn dummy_read(&mut self, addr: u16) -> u8 {
+        self.read(addr)
+    }
+
+    /// 向地址 addr 处写入一个字节
+    fn write(&mut self, addr: u16, data: u8);
+
+    /// 经过一个 CPU 周期, 返回值表示是否到达帧末(没有视频输出的机器总是返回 false)
+    fn tick(&mut self) -> bool;
+
+    /// `JMP (abs)` 读出跳转目标 target 时调用
+    fn jump_indirect(&mut self, _target: u16) {}
+
+    /// CPU 进入中断(包括 BRK)时调用, 此时返回地址与状态寄存器已经入栈
+    fn enter_interrupt(&mut self, _kind: Interrupt) {}
+
+    /// NMI 线电平, 低电平有效(下降沿触发 NMI)
+    fn nmi_line_level(&self) -> bool {
+        true
+    }
+
+    /// IRQ 线电平, 低电平有效
+    fn irq_line_level(&self) -> bool {
+        true
+    }
+}
+
+/// 总线访问类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// 一次总线访问, 6502 的每个周期恰好进行一次
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusAccess {
+    pub addr: u16,
+    pub data: u8,
+    pub kind: AccessKind,
+}
+
+/// 64KB 平坦 RAM, 没有任何外设与镜像, 中断线由调用者控制
+///
+/// 用于单独测试 CPU(如 Klaus Dormann 的 6502 functional test),
+/// 开启记录后会按顺序保存每一次访问, 用于逐周期比对(如 SingleStepTests)
+pub struct FlatBus {
+    memory: Vec<u8>,
+    cycles: u64,
+    nmi_line_level: bool,
+    irq_line_level: bool,
+    recording: bool,
+    accesses: Vec<BusAccess>,
+}
+
+impl FlatBus {
+    pub fn new() -> Self {
+        FlatBus {
+            memory: vec![0; 0x10000],
+            cycles: 0,
+            nmi_line_level: true,
+            irq_line_level: true,
+            recording: false,
+            accesses: vec![],
+        }
+    }
+
+    /// 将 data 复制到从 addr 开始的内存中(超过 0xffff 的部分回绕至 0)
+    pub fn load(&mut self, addr: u16, data: &[u8]) {
+        for (i, byte) in data.iter().enumerate() {
+            self.memory[addr.wrapping_add(i as u16) as usize] = *byte;
+        }
+    }
+
+    /// 全部 64KB 内存
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+
+    /// 经过的 CPU 周期数
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn set_nmi_line_level(&mut self, level: bool) {
+        self.nmi_line_level = level;
+    }
+
+    pub fn set_irq_line_level(&mut self, level: bool) {
+        self.irq_line_level = level;
+    }
+
+    /// 是否记录之后的每一次访问
+    pub fn set_recording(&mut self, recording: bool) {
+        self.recording = recording;
+    }
+
+    /// 已记录的访问, 按发生顺序排列
+    pub fn accesses(&self) -> &[BusAccess] {
+        &self.accesses
+    }
+
+    pub fn clear_accesses(&mut self) {
+        self.accesses.clear();
+    }
+}
+
+impl Default for FlatBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Peek for FlatBus {
+    fn peek(&self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+}
+
+impl CpuBus for FlatBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        let data = self.memory[addr as usize];
+        if self.recording {
+            self.accesses.push(BusAccess { addr, data, kind: AccessKind::Read });
+        }
+        data
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.memory[addr as usize] = data;
+        if self.recording {
+            self.accesses.push(BusAccess { addr, data, kind: AccessKind::Write });
+        }
+    }
+
+    fn tick(&mut self) -> bool {
+        self.cycles += 1;
+        false
+    }
+
+    fn nmi_line_level(&self) -> bool {
+        self.nmi_line_level
+    }
+
+    fn irq_line_level(&self) -> bool {
+        self.irq_line_level
+    }
+}
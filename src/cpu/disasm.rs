@@ -0,0 +1,211 @@
+use std::{collections::BTreeMap, fmt, ops::RangeInclusive};
+
+use super::{opcodes::{OpCode, OPCODES_MAP}, AddressingMode, Peek};
+
+/// 反汇编得到的一条指令
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    /// 指令所在地址
+    pub addr: u16,
+    /// 操作码与操作数, 共 1 至 3 字节
+    pub bytes: Vec<u8>,
+    /// 助记符, 非官方指令不带 `*` 前缀
+    pub mnemonic: &'static str,
+    pub mode: AddressingMode,
+    /// 操作数(1 或 2 字节), 隐式寻址与累加器寻址时为 None
+    pub operand: Option<u16>,
+    /// 分支, JMP 与 JSR 的目标地址; JMP 间接寻址时为当前指针处的值
+    pub target: Option<u16>,
+    /// 基本周期数, 不含跨页与分支跳转带来的额外周期
+    pub cycles: u8,
+    pub is_unofficial: bool,
+}
+
+impl Instruction {
+    /// 下一条指令的地址
+    pub fn next_addr(&self) -> u16 {
+        self.addr.wrapping_add(self.bytes.len() as u16)
+    }
+
+    /// 执行之后是否可能顺序执行下一条指令(JMP, RTS, RTI, BRK 与 JAM 则不会)
+    pub fn falls_through(&self) -> bool {
+        !matches!(
+            self.bytes[0],
+            0x4c | 0x6c | 0x60 | 0x40 | 0x00 | 0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xb2 | 0xd2 | 0xf2
+        )
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_unofficial {
+            write!(f, "*")?;
+        }
+        write!(f, "{}", self.mnemonic)?;
+        let operand = self.operand.unwrap_or(0);
+        match self.mode {
+            AddressingMode::Implied => Ok(()),
+            AddressingMode::Accumulator => write!(f, " A"),
+            AddressingMode::Immediate => write!(f, " #${:02X}", operand),
+            AddressingMode::ZeroPage => write!(f, " ${:02X}", operand),
+            AddressingMode::ZeroPage_X => write!(f, " ${:02X},X", operand),
+            AddressingMode::ZeroPage_Y => write!(f, " ${:02X},Y", operand),
+            AddressingMode::Relative => write!(f, " ${:04X}", self.target.unwrap_or(0)),
+            AddressingMode::Absolute => write!(f, " ${:04X}", operand),
+            AddressingMode::Absolute_X => write!(f, " ${:04X},X", operand),
+            AddressingMode::Absolute_Y => write!(f, " ${:04X},Y", operand),
+            AddressingMode::Indirect => write!(f, " (${:04X})", operand),
+            AddressingMode::Indirect_X => write!(f, " (${:02X},X)", operand),
+            AddressingMode::Indirect_Y => write!(f, " (${:02X}),Y", operand),
+        }
+    }
+}
+
+/// 反汇编 addr 处的一条指令, 只通过 [`Peek`] 读取内存, 不会产生副作用
+pub fn disassemble<M: Peek + ?Sized>(mem: &M, addr: u16) -> Instruction {
+    decode(mem, addr, OPCODES_MAP[&mem.peek(addr)])
+}
+
+/// 由已经取得的操作码构造 addr 处的指令, 操作数等仍通过 peek 读取
+pub(super) fn decode<M: Peek + ?Sized>(mem: &M, addr: u16, opcode: &OpCode) -> Instruction {
+    let code = opcode.code;
+    let bytes = std::iter::once(code)
+        .chain((1..opcode.len as u16).map(|i| mem.peek(addr.wrapping_add(i))))
+        .collect::<Vec<u8>>();
+    let operand = match bytes.len() {
+        2 => Some(bytes[1] as u16),
+        3 => Some(u16::from_le_bytes([bytes[1], bytes[2]])),
+        _ => None,
+    };
+
+    let target = match (opcode.mode, operand) {
+        (AddressingMode::Relative, Some(offset)) => {
+            Some(addr.wrapping_add(2).wrapping_add(offset as u8 as i8 as u16))
+        }
+        (AddressingMode::Absolute, Some(operand)) if matches!(code, 0x4c | 0x20) => Some(operand),
+        (AddressingMode::Indirect, Some(ptr)) => { // 间接寻址不会跨页, 而是回环
+            let lo = mem.peek(ptr) as u16;
+            let hi = mem.peek((ptr & 0xff00) | (ptr.wrapping_add(1) & 0x00ff)) as u16;
+            Some((hi << 8) | lo)
+        }
+        _ => None,
+    };
+
+    let is_unofficial = opcode.mnemonic.starts_with('*');
+    Instruction {
+        addr,
+        bytes,
+        mnemonic: opcode.mnemonic.trim_start_matches('*'),
+        mode: opcode.mode,
+        operand,
+        target,
+        cycles: opcode.cycles,
+        is_unofficial,
+    }
+}
+
+/// 从入口地址出发, 沿着顺序执行, 分支, JMP 与 JSR 反汇编 range 内可达的指令
+///
+/// 不跟随 JMP 间接寻址(目标由运行时的内存决定), 超出 range 的指令与目标会被忽略
+pub fn disassemble_range<M: Peek + ?Sized>(
+    mem: &M,
+    range: RangeInclusive<u16>,
+    entries: &[u16],
+) -> BTreeMap<u16, Instruction> {
+    let mut instructions = BTreeMap::new();
+    let mut pending = entries
+        .iter()
+        .copied()
+        .filter(|addr| range.contains(addr))
+        .collect::<Vec<u16>>();
+
+    while let Some(addr) = pending.pop() {
+        if instructions.contains_key(&addr) {
+            continue;
+        }
+        let instruction = disassemble(mem, addr);
+        let end = addr as u32 + instruction.bytes.len() as u32; // 下一条指令的地址, 可能超过 0xffff
+        if end - 1 > *range.end() as u32 {
+            continue;
+        }
+        if let Some(target) = instruction.target {
+            if instruction.mode != AddressingMode::Indirect && range.contains(&target) {
+                pending.push(target);
+            }
+        }
+        if instruction.falls_through() && end <= *range.end() as u32 {
+            pending.push(end as u16);
+        }
+        instructions.insert(addr, instruction);
+    }
+    instructions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::FlatBus;
+
+    #[test]
+    fn test_disassemble() {
+        let mut bus = FlatBus::new();
+        bus.load(0x8000, &[
+            0xbd, 0x10, 0x80, // LDA $8010,X
+            0xd0, 0xfb, // BNE $8000
+            0x6c, 0xff, 0x02, // JMP ($02FF)
+            0x04, 0x10, // *NOP $10
+            0x0a, // ASL A
+        ]);
+        bus.load(0x02ff, &[0x34]);
+        bus.load(0x0200, &[0x12]);
+
+        let lda = disassemble(&bus, 0x8000);
+        assert_eq!(lda.bytes, vec![0xbd, 0x10, 0x80]);
+        assert_eq!(lda.mnemonic, "LDA");
+        assert_eq!(lda.mode, AddressingMode::Absolute_X);
+        assert_eq!(lda.operand, Some(0x8010));
+        assert_eq!(lda.target, None);
+        assert_eq!(lda.cycles, 4);
+        assert_eq!(lda.to_string(), "LDA $8010,X");
+
+        let bne = disassemble(&bus, 0x8003);
+        assert_eq!(bne.target, Some(0x8000));
+        assert_eq!(bne.to_string(), "BNE $8000");
+
+        let jmp = disassemble(&bus, 0x8005);
+        assert_eq!(jmp.target, Some(0x1234)); // 指针不跨页
+        assert!(!jmp.falls_through());
+        assert_eq!(jmp.to_string(), "JMP ($02FF)");
+
+        let nop = disassemble(&bus, 0x8008);
+        assert!(nop.is_unofficial);
+        assert_eq!(nop.mnemonic, "NOP");
+        assert_eq!(nop.to_string(), "*NOP $10");
+
+        assert_eq!(disassemble(&bus, 0x800a).to_string(), "ASL A");
+    }
+
+    #[test]
+    fn test_disassemble_range() {
+        let mut bus = FlatBus::new();
+        bus.load(0x8000, &[
+            0xa2, 0x00, // 8000 LDX #$00
+            0xbd, 0x10, 0x80, // 8002 LDA $8010,X
+            0xf0, 0x04, // 8005 BEQ $800b
+            0xe8, // 8007 INX
+            0x4c, 0x02, 0x80, // 8008 JMP $8002
+            0x60, // 800b RTS
+            0xff, 0xff, 0xff, 0xff, // 数据
+            0x20, 0x00, 0x90, // 8010 JSR $9000, 超出范围
+        ]);
+        let instructions = disassemble_range(&bus, 0x8000..=0x8012, &[0x8000]);
+        assert_eq!(
+            instructions.keys().copied().collect::<Vec<u16>>(),
+            vec![0x8000, 0x8002, 0x8005, 0x8007, 0x8008, 0x800b]
+        );
+
+        let instructions = disassemble_range(&bus, 0x8000..=0x8012, &[0x8000, 0x8010]);
+        assert!(instructions.contains_key(&0x8010));
+        assert!(!instructions.contains_key(&0x8013));
+    }
+}
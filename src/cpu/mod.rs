@@ -1,22 +1,33 @@
 mod opcodes;
+mod cpu_bus;
+mod disasm;
 pub(crate) mod trace;
+#[cfg(test)]
+mod single_step_tests;
+#[cfg(test)]
+mod blargg_tests;
+
+use std::any::Any;
 
 use bitflags::bitflags;
-use crate::{bus::Bus, common::{Mem, Clock}, joypad::Joypad, apu::Samples, ppu::Frame, Rom};
+use crate::{bus::Bus, common::{Mem, Clock}, joypad::Joypad, apu::Samples, ppu::Frame, Rom, save_state::{self, Snapshot, StateWriter, StateReader, SaveStateError}};
+
+pub use cpu_bus::{CpuBus, Peek, FlatBus, BusAccess, AccessKind};
+pub use disasm::{disassemble, disassemble_range, Instruction};
 
 use self::opcodes::OPCODES_MAP;
 
 /// # 寻址模式
-/// 6502 有 <del>15</del> 13 种寻址模式, 不实现的寻址模式在相应的指令实现处实现
+/// 6502 有 <del>15</del> 13 种寻址模式
 /// ## 非存储器, 非索引的寻址
-/// + 隐式寻址(Implied)(**不实现**): 操作数的地址隐含于操作码, 且不是存储器地址
-/// + 累加器寻址(Accumulator)(**不实现**): 操作数为 A(the accumulator)
+/// + 隐式寻址(Implied): 操作数的地址隐含于操作码, 且不是存储器地址
+/// + 累加器寻址(Accumulator): 操作数为 A(the accumulator)
 /// + 直接寻址(Immediate): 操作数在指令第二个字节
 /// ## 非索引的存储器寻址
 /// + 绝对寻址(Absolute): 指令第二三个字节为操作数地址, 小端序
 /// + 0 页面寻址(ZeroPage): 指令第二个字节为操作数地址, 只能寻址 0x00..=0xfe (0 页): `LDA $35`
-/// + 相对寻址(Relative)(**不实现**): branch 指令使用, 指令的第二个字节为操作数, 加到下一指令的 PC 上
-/// + 间接寻址(Indirect)(**不实现**): jmp (三字节指令)使用, 二三字节储存一个地址, 将该地址处的值(16bit)加载到 PC 中, 即该地址处的值是操作数地址: `JMP  ($1000)`
+/// + 相对寻址(Relative): branch 指令使用, 指令的第二个字节为操作数, 加到下一指令的 PC 上
+/// + 间接寻址(Indirect): jmp (三字节指令)使用, 二三字节储存一个地址, 将该地址处的值(16bit)加载到 PC 中, 即该地址处的值是操作数地址: `JMP  ($1000)`
 /// + <del>0 页面间接寻址(**不实现**): jmp 使用, 第二字节是 0 页面的一个地址, 该地址处的值(16bit)为操作数地址</del>
 /// ## 基于索引(X, Y)的存储器寻址
 /// + 绝对变址寻址(Absolute_X, Absolute_Y): 指令第二三个字节加上 X 或 Y 为操作数地址: `STA $1000,Y`
@@ -24,19 +35,24 @@ use self::opcodes::OPCODES_MAP;
 /// + Indexed Indirect(Indirect_X): 第二个字节的值(8bit)加上 X(不进位) 是一个地址, 该地址处的值(16bit)是操作数的地址: `LDA ($20,X)`
 /// + Indirect Indexed(Indirect_Y): 第二个字节的值是一个地址, 该地址处的值(16bit)加上 Y 是操作数的地址: `LDA ($86),Y`
 /// + <del>Indexed Indirect 非 0 页面形式(**不实现**): 指令的二三字节(16bit)加上 X, 后续相同</del>
-#[derive(Debug)]
+///
+/// JMP 与 JSR 的绝对寻址直接使用操作数作为目标地址
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(non_camel_case_types)]
-enum AddressingMode {
+pub enum AddressingMode {
+   Implied,
+   Accumulator,
    Immediate,
    ZeroPage,
    ZeroPage_X,
    ZeroPage_Y,
+   Relative,
    Absolute,
    Absolute_X,
    Absolute_Y,
+   Indirect,
    Indirect_X,
    Indirect_Y,
-   NoneAddressing,
 }
 
 bitflags! {
@@ -67,7 +83,10 @@ bitflags! {
     }
 }
 
-pub struct Cpu {
+/// 6502 CPU, 通过总线 B 访存, 默认连接 NES 的总线
+///
+/// NES 使用的 2A03 忽略 D 标志, 因而十进制模式默认关闭, 见 [`Cpu::set_decimal_mode_enabled`]
+pub struct Cpu<B: CpuBus = Bus> {
     // 组成
     register_a: u8,
     register_x: u8,
@@ -75,13 +94,69 @@ pub struct Cpu {
     status: CpuFlags,
     program_counter: u16,
     stack_pointer: u8,  // 指向空位置
-    bus: Bus, // 总线(连接CPU RAM, PPU, Rom 等)
+    bus: B, // 总线(NES 中连接CPU RAM, PPU, Rom 等)
+    // 配置
+    decimal_mode_enabled: bool, // 是否支持十进制模式(2A03 不支持)
+    tick_while_halted: bool, // 停机时总线是否继续经过周期(PPU, APU 照常运行)
     // 状态信息
-    brk_flag: bool,
     prev_nmi_line_level: bool, // 上个周期的 nmi 线电平
-    nmi_pending: bool, // nmi 是否正在 pending
-    irq_pending: bool, // irq 是否正在 pending
+    nmi_pending: bool, // nmi 是否正在 pending(边沿触发, 直到被响应才清除)
+    irq_pending: bool, // irq 是否正在 pending(电平触发, 每周期更新)
+    nmi_polled: bool, // 本条指令倒数第二个周期轮询到的 nmi
+    irq_polled: bool, // 本条指令倒数第二个周期轮询到的 irq(已考虑 I 标志)
     frame_end: bool, // 是否到达了帧末尾(直到下一条指令才会重置)
+    cycles: u8, // 当前指令已经过的周期数(6502 每个周期恰好访存一次)
+    halted: Option<CpuHalted>, // 执行 JAM 后停机, 直到 reset
+    last_interrupt: Option<Interrupt>, // 上一次 run_next_instruction 进入的中断
+    fetch_start: u16, // 当前指令所在地址, 对 fetch_start 起 fetch_len 字节的读取为取指
+    fetch_len: u16,
+    indirect_operand: Option<u16>, // 当前指令经 (zp,X) 或 (zp),Y 间接寻址得到的操作数地址, 对它的读取为间接读取
+    // 调试
+    instruction_hook: Option<Box<dyn InstructionHook<B>>>,
+}
+
+/// 每条指令执行前调用的回调, 如指令日志 [`TraceLogger`](crate::TraceLogger)
+///
+/// 调用时操作码已经取得, 但寄存器与 PPU, APU 的状态仍是指令执行前的状态
+pub trait InstructionHook<B: CpuBus = Bus>: Any {
+    /// instruction 为即将执行的指令
+    fn before_instruction(&mut self, cpu: &Cpu<B>, instruction: &Instruction);
+}
+
+/// CPU 寄存器的快照
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    /// 状态寄存器 NV-BDIZC
+    pub p: u8,
+    pub sp: u8,
+    pub pc: u16,
+}
+
+/// CPU 因执行 JAM(KIL) 指令而停机, 只有 reset 才能恢复
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuHalted {
+    /// JAM 指令所在地址
+    pub pc: u16,
+    pub opcode: u8,
+}
+
+impl std::fmt::Display for CpuHalted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CPU halted by opcode {:02x} at {:04x}", self.opcode, self.pc)
+    }
+}
+
+impl std::error::Error for CpuHalted {}
+
+/// 中断类型, 三者共用同一个 7 周期的中断序列
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    Brk,
+    Nmi,
+    Irq,
 }
 
 const STACK: u16 = 0x0100; // stack pointer + STACK 即为真正的栈指针
@@ -90,27 +165,152 @@ const INTERRUPT_RESET_VECTOR: u16 = 0xfffc;
 const INTERRUPT_NMI_VECTOR: u16 = 0xfffa;
 const INTERRUPT_IRQ_BRK_VECTOR: u16 = 0xfffe;
 
-impl Mem for Cpu {
+impl<B: CpuBus> Mem for Cpu<B> {
     fn mem_read(&mut self, addr: u16) -> u8 {
-        self.bus.mem_read(addr)
+        self.cycles = self.cycles.wrapping_add(1);
+        if addr.wrapping_sub(self.fetch_start) < self.fetch_len {
+            self.bus.fetch(addr)
+        } else if self.indirect_operand == Some(addr) {
+            self.bus.indirect_read(addr)
+        } else {
+            self.bus.read(addr)
+        }
     }
 
     fn mem_write(&mut self, addr: u16, data: u8) {
-        self.bus.mem_write(addr, data);
+        self.cycles = self.cycles.wrapping_add(1);
+        self.bus.write(addr, data);
+    }
+}
+
+impl Cpu {
+    /// create a new Cpu with a Rom
+    pub fn new(rom: Rom) -> Self {
+        Cpu::with_bus(Bus::new(rom))
+    }
+
+    /// returns frame(video output), joypad(controller input) and samples(audio output)
+    pub fn io_interface(&mut self) -> (&Frame, &mut Joypad, &mut Samples) {
+        self.bus.io_interface()
     }
 
-    fn mem_read_u16(&mut self, addr: u16) -> u16 {
-        self.bus.mem_read_u16(addr)
+    /// 保存整台机器的状态, 格式见 `save_state` 模块
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut data = vec![];
+        self.save_state_into(&mut data);
+        data
     }
 
-    fn mem_write_u16(&mut self, addr: u16, data: u16) {
-        self.bus.mem_write_u16(addr, data);
+    /// 与 `save_state` 相同, 但复用 data 的空间, 用于每帧都要存档的场合
+    pub fn save_state_into(&self, data: &mut Vec<u8>) {
+        data.clear();
+        let mut writer = StateWriter::with_buffer(std::mem::take(data));
+        save_state::write_header(&mut writer, self.bus.rom_crc32());
+        self.save(&mut writer);
+        *data = writer.into_bytes();
+    }
+
+    /// 恢复 `save_state` 保存的状态, 存档属于其他 ROM, 版本不同或内容错误时返回错误且状态不变
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        let mut reader = save_state::read_header(data, self.bus.rom_crc32())?;
+        let backup = self.save_state();
+        if let Err(err) = self.load(&mut reader).and_then(|_| reader.finish()) {
+            let mut reader = save_state::read_header(&backup, self.bus.rom_crc32()).unwrap();
+            self.load(&mut reader).expect("restoring a state saved just now");
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// 恢复本机之前由 `save_state` 保存的状态, 不做失败时的备份, 用于预运行与倒带这类每帧都要读档的场合
+    pub(crate) fn restore_own_state(&mut self, data: &[u8]) {
+        let mut reader = save_state::read_header(data, self.bus.rom_crc32()).expect("state saved by this machine");
+        self.load(&mut reader).and_then(|_| reader.finish()).expect("state saved by this machine");
+    }
+
+    /// 重新上电, 整台机器恢复到 `Cpu::new` 后 `reset` 的状态, 金手指, 钩子与调试设置保留
+    pub fn power_cycle(&mut self) {
+        self.bus.power_cycle();
+        self.prev_nmi_line_level = true;
+        self.irq_pending = false;
+        self.frame_end = false;
+        self.cycles = 0;
+        self.last_interrupt = None;
+        self.fetch_start = 0;
+        self.fetch_len = 0;
+        self.indirect_operand = None;
+        self.reset();
     }
 }
 
-impl Cpu {
-    /// create a new Cpu with a Rom
-    pub fn new(rom: Rom) -> Self {
+impl<B: CpuBus + Snapshot> Snapshot for Cpu<B> {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.section(b"CPU ", |w| {
+            w.u8(self.register_a);
+            w.u8(self.register_x);
+            w.u8(self.register_y);
+            w.u8(self.status.bits());
+            w.u16(self.program_counter);
+            w.u8(self.stack_pointer);
+            w.bool(self.prev_nmi_line_level);
+            w.bool(self.nmi_pending);
+            w.bool(self.irq_pending);
+            w.bool(self.nmi_polled);
+            w.bool(self.irq_polled);
+            w.bool(self.frame_end);
+            w.u8(self.cycles);
+            w.bool(self.halted.is_some());
+            let halted = self.halted.unwrap_or(CpuHalted { pc: 0, opcode: 0 });
+            w.u16(halted.pc);
+            w.u8(halted.opcode);
+            w.u8(match self.last_interrupt {
+                None => 0,
+                Some(Interrupt::Brk) => 1,
+                Some(Interrupt::Nmi) => 2,
+                Some(Interrupt::Irq) => 3,
+            });
+            w.u16(self.fetch_start);
+            w.u16(self.fetch_len);
+        });
+        self.bus.save(writer);
+    }
+
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        reader.section(b"CPU ", |r| {
+            self.register_a = r.u8()?;
+            self.register_x = r.u8()?;
+            self.register_y = r.u8()?;
+            self.status = CpuFlags::from_bits_truncate(r.u8()?);
+            self.program_counter = r.u16()?;
+            self.stack_pointer = r.u8()?;
+            self.prev_nmi_line_level = r.bool()?;
+            self.nmi_pending = r.bool()?;
+            self.irq_pending = r.bool()?;
+            self.nmi_polled = r.bool()?;
+            self.irq_polled = r.bool()?;
+            self.frame_end = r.bool()?;
+            self.cycles = r.u8()?;
+            let halted = r.bool()?;
+            let (pc, opcode) = (r.u16()?, r.u8()?);
+            self.halted = halted.then_some(CpuHalted { pc, opcode });
+            self.last_interrupt = match r.u8_max(3)? {
+                1 => Some(Interrupt::Brk),
+                2 => Some(Interrupt::Nmi),
+                3 => Some(Interrupt::Irq),
+                _ => None,
+            };
+            self.fetch_start = r.u16()?;
+            self.fetch_len = r.u16()?;
+            self.indirect_operand = None;
+            Ok(())
+        })?;
+        self.bus.load(reader)
+    }
+}
+
+impl<B: CpuBus> Cpu<B> {
+    /// create a new Cpu connected to the given bus
+    pub fn with_bus(bus: B) -> Self {
         Cpu {
             register_a: 0,
             register_x: 0,
@@ -118,55 +318,157 @@ impl Cpu {
             status: CpuFlags::from_bits_truncate(0b100100),
             program_counter: 0,
             stack_pointer: STACK_RESET,
-            bus: Bus::new(rom),
-            brk_flag: false,
+            bus,
+            decimal_mode_enabled: false,
+            tick_while_halted: false,
             prev_nmi_line_level: true,
             nmi_pending: false,
             irq_pending: false,
+            nmi_polled: false,
+            irq_polled: false,
             frame_end: false,
+            cycles: 0,
+            halted: None,
+            last_interrupt: None,
+            fetch_start: 0,
+            fetch_len: 0,
+            indirect_operand: None,
+            instruction_hook: None,
         }
     }
 
-    /// returns frame(video output), joypad(controller input) and samples(audio output)
-    pub fn io_interface(&mut self) -> (&Frame, &mut Joypad, &mut Samples) {
-        self.bus.io_interface()
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
+    }
+
+    /// 是否在 D 标志置 1 时按 BCD 进行 ADC/SBC, 通用 6502 需要开启, NES 的 2A03 则没有该功能
+    pub fn set_decimal_mode_enabled(&mut self, enabled: bool) {
+        self.decimal_mode_enabled = enabled;
+    }
+
+    /// 停机时是否让总线继续经过周期, 开启后 PPU 与 APU 照常运行, 帧仍会结束
+    pub fn set_tick_while_halted(&mut self, tick: bool) {
+        self.tick_while_halted = tick;
+    }
+
+    /// 设置每条指令执行前调用的回调, None 表示移除; 返回原来的回调
+    pub fn set_instruction_hook(&mut self, hook: Option<Box<dyn InstructionHook<B>>>) -> Option<Box<dyn InstructionHook<B>>> {
+        std::mem::replace(&mut self.instruction_hook, hook)
+    }
+
+    /// 当前的指令回调, 类型不是 T 时为 None
+    pub fn instruction_hook<T: InstructionHook<B>>(&self) -> Option<&T> {
+        let hook: &dyn Any = self.instruction_hook.as_deref()?;
+        hook.downcast_ref()
     }
 
-    /// run next frame
-    pub fn run_next_frame(&mut self) {
-        while !self.run_next_instruction() {}
+    pub fn instruction_hook_mut<T: InstructionHook<B>>(&mut self) -> Option<&mut T> {
+        let hook: &mut dyn Any = self.instruction_hook.as_deref_mut()?;
+        hook.downcast_mut()
+    }
+
+    /// 移除并取出指令回调, 类型不是 T 时为 None 且不移除
+    pub fn take_instruction_hook<T: InstructionHook<B>>(&mut self) -> Option<T> {
+        self.instruction_hook::<T>()?;
+        let hook: Box<dyn Any> = self.instruction_hook.take()?;
+        hook.downcast().ok().map(|hook| *hook)
+    }
+
+    /// CPU 是否因 JAM 指令停机
+    pub fn halted(&self) -> Option<CpuHalted> {
+        self.halted
+    }
+
+    pub fn registers(&self) -> Registers {
+        Registers {
+            a: self.register_a,
+            x: self.register_x,
+            y: self.register_y,
+            p: self.status.bits(),
+            sp: self.stack_pointer,
+            pc: self.program_counter,
+        }
+    }
+
+    /// 直接修改寄存器, 供调试器使用
+    pub fn set_registers(&mut self, registers: Registers) {
+        self.register_a = registers.a;
+        self.register_x = registers.x;
+        self.register_y = registers.y;
+        self.status = CpuFlags::from_bits_truncate(registers.p);
+        self.stack_pointer = registers.sp;
+        self.program_counter = registers.pc;
+    }
+
+    /// 上一次 run_next_instruction 是否进入了中断处理程序(BRK 也算在内), 此时 PC 指向处理程序
+    ///
+    /// 被 NMI 劫持的 BRK 与 IRQ 视作 NMI
+    pub fn last_interrupt(&self) -> Option<Interrupt> {
+        self.last_interrupt
+    }
+
+    /// run next frame, returns error if the CPU is halted
+    ///
+    /// 停机时若开启了 [`Cpu::set_tick_while_halted`], 则仍会运行完这一帧再返回错误
+    pub fn run_next_frame(&mut self) -> Result<(), CpuHalted> {
+        self.run_next_frame_with_trace(|_| {})
     }
 
     /// run next instruction, returns true if this frame is end
+    ///
+    /// 停机后每次调用只经过一个周期(开启了 [`Cpu::set_tick_while_halted`] 时), 否则什么也不做
     pub fn run_next_instruction(&mut self) -> bool {
         self.run_next_instruction_with_trace(|_| {})
     }
 
     /// run next frame, with a trace function called every instruction cycle
-    pub fn run_next_frame_with_trace<F>(&mut self, mut trace: F)
+    pub fn run_next_frame_with_trace<F>(&mut self, mut trace: F) -> Result<(), CpuHalted>
     where
-        F: FnMut(&mut Cpu)
+        F: FnMut(&mut Self)
     {
-        while !self.run_next_instruction_with_trace(|cpu| trace(cpu)) { }
+        loop {
+            if let Some(halted) = self.halted {
+                if !self.tick_while_halted {
+                    return Err(halted);
+                }
+            }
+            if self.run_next_instruction_with_trace(|cpu| trace(cpu)) {
+                break;
+            }
+        }
+        match self.halted {
+            Some(halted) => Err(halted),
+            None => Ok(()),
+        }
     }
 
     /// run next instruction, with a trace funtion called before execution, returns true if this frame is end
     pub fn run_next_instruction_with_trace<F>(&mut self, mut trace: F) -> bool 
     where
-        F: FnMut(&mut Cpu)
+        F: FnMut(&mut Self)
     {
         self.frame_end = false;
+        self.last_interrupt = None;
+        if self.halted.is_some() {
+            // 停机时不再取指, 也不响应中断
+            if self.tick_while_halted {
+                self.clock();
+            }
+            return self.frame_end;
+        }
         // trace
         trace(self);
         // 执行
         self.execute_instruction();
-        // 处理中断
-        if self.nmi_pending {
-            self.nmi_pending = false;
-            self.nmi();
-        } else if self.irq_pending && !self.status.contains(CpuFlags::INTERRUPT_DISABLE) {
-            self.irq_pending = false;
-            self.irq();
+        // 处理中断, 是否响应由指令倒数第二个周期的轮询结果决定
+        if self.nmi_polled {
+            self.interrupt(Interrupt::Nmi);
+        } else if self.irq_polled {
+            self.interrupt(Interrupt::Irq);
         }
         self.frame_end
     } 
@@ -174,58 +476,103 @@ impl Cpu {
     /// 模拟 NES 插入卡带时的动作(RESET 中断)
     /// 1. 状态重置(寄存器与状态寄存器)
     /// 2. 将 PC 寄存器值设为地址 0xFFFC 处的 16 bit 数值
+    /// 3. 与其他中断相同, 共经过 7 个周期
+    ///
+    /// 同时解除 JAM 造成的停机
     pub fn reset(&mut self) {
         self.register_a = 0;
         self.register_x = 0;
         self.register_y = 0;
         self.status = CpuFlags::from_bits_truncate(0b100100);
         self.stack_pointer = STACK_RESET;
+        self.nmi_pending = false;
+        self.nmi_polled = false;
+        self.irq_polled = false;
+        self.halted = None;
 
         self.program_counter = self.mem_read_u16(INTERRUPT_RESET_VECTOR);
+        for _ in 0..7 {
+            self.clock();
+        }
     }
 
-    /// NMI 中断
-    /// 1. 下一条指令地址入栈
-    /// 2. 状态寄存器入栈(UB=10)
-    /// 3. 状态寄存器 I 置 1
-    /// 4. 将 PC 寄存器值设为地址 0xFFFA 处的 16 bit 数值
-    fn nmi(&mut self) {
-        self.stack_push_u16(self.program_counter); // 下一条指令地址
-        let mut flag = self.status.clone();
-        flag.insert(CpuFlags::BREAK2);
-        flag.remove(CpuFlags::BREAK);
-        self.stack_push(flag.bits);
-        self.status.insert(CpuFlags::INTERRUPT_DISABLE);
+    /// BRK/NMI/IRQ 中断序列, 共 7 个周期
+    /// 1. 周期 1, 2: 读取操作码与下一字节(BRK 的 padding 字节, 硬件中断则是无用的读取, 且 PC 不变)
+    /// 2. 周期 3, 4: 下一条指令地址入栈(BRK 为 BRK 地址 + 2)
+    /// 3. 周期 5: 状态寄存器入栈(BRK 为 UB=11, 硬件中断为 UB=10)
+    /// 4. 周期 6, 7: 读取中断向量, 状态寄存器 I 置 1
+    ///
+    /// 若在周期 4 结束前 NMI 已经 pending, 则 BRK/IRQ 会被 NMI 劫持:
+    /// 入栈的 B 标志不变, 但读取的是 NMI 向量 0xFFFA, 并且该 NMI 视为已被响应
+    fn interrupt(&mut self, kind: Interrupt) {
+        if kind != Interrupt::Brk { // BRK 的前两个周期已在 execute_instruction 中访存
+            self.dummy_read(self.program_counter);
+            self.dummy_read(self.program_counter);
+        }
+        self.clock();
+        self.clock();
 
+        self.stack_push((self.program_counter >> 8) as u8);
         self.clock();
+        self.stack_push((self.program_counter & 0xff) as u8);
         self.clock();
-        self.program_counter = self.mem_read_u16(INTERRUPT_NMI_VECTOR);
-    }
 
-    /// IRQ 中断
-    /// 1. 下一条指令地址入栈
-    /// 2. 状态寄存器入栈(UB=10)
-    /// 3. 状态寄存器 I 置 1
-    /// 4. 将 PC 寄存器值设为地址 0xFFFE 处的 16 bit 数值
-    fn irq(&mut self) {
-        self.stack_push_u16(self.program_counter); // 下一条指令地址
-        let mut flag = self.status.clone();
+        let vector = if kind == Interrupt::Nmi || self.nmi_pending {
+            self.nmi_pending = false;
+            self.last_interrupt = Some(Interrupt::Nmi);
+            INTERRUPT_NMI_VECTOR
+        } else {
+            self.last_interrupt = Some(kind);
+            INTERRUPT_IRQ_BRK_VECTOR
+        };
+
+        let mut flag = self.status;
         flag.insert(CpuFlags::BREAK2);
-        flag.remove(CpuFlags::BREAK);
+        flag.set(CpuFlags::BREAK, kind == Interrupt::Brk);
         self.stack_push(flag.bits);
         self.status.insert(CpuFlags::INTERRUPT_DISABLE);
+        if let Some(entered) = self.last_interrupt {
+            self.bus.enter_interrupt(entered);
+        }
+        self.clock();
 
+        self.program_counter = self.mem_read_u16(vector);
         self.clock();
         self.clock();
-        self.program_counter = self.mem_read_u16(INTERRUPT_IRQ_BRK_VECTOR);
+
+        // 中断序列本身不轮询中断, 序列中到来的 NMI 在下一条指令后才会响应
+        self.nmi_polled = false;
+        self.irq_polled = false;
+    }
+
+    /// JAM 使 CPU 停机, 此时 PC 指向 JAM 指令
+    fn halt(&mut self, opcode: u8) {
+        log::warn!("CPU halted by opcode {:02x} at {:04x}", opcode, self.program_counter);
+        self.halted = Some(CpuHalted { pc: self.program_counter, opcode });
+        self.nmi_polled = false;
+        self.irq_polled = false;
+        self.fetch_len = 0;
+        self.indirect_operand = None;
+    }
+
+    /// 结果被丢弃的读取, 同样占用一个周期
+    fn dummy_read(&mut self, addr: u16) {
+        self.cycles = self.cycles.wrapping_add(1);
+        self.bus.dummy_read(addr);
+    }
+
+    /// 在指令倒数第二个周期结束时轮询中断, interrupt_disable 为此时的 I 标志
+    fn poll_interrupts(&mut self, interrupt_disable: bool) {
+        self.nmi_polled = self.nmi_pending;
+        self.irq_polled = self.irq_pending && !interrupt_disable;
     }
 }
 
-impl Clock for Cpu {
+impl<B: CpuBus> Clock for Cpu<B> {
     type Result = ();
 
     fn clock(&mut self) -> Self::Result {
-        if self.bus.clock() {
+        if self.bus.tick() {
             self.frame_end = true;
         }
         if self.prev_nmi_line_level && !self.bus.nmi_line_level() {
@@ -236,14 +583,34 @@ impl Clock for Cpu {
     }
 }
 
-impl Cpu{
+impl<B: CpuBus> Cpu<B> {
     /// CPU 执行一条指令
     fn execute_instruction(&mut self) {
         // 操作码解码
+        self.cycles = 0;
+        self.fetch_start = self.program_counter;
+        self.fetch_len = 1;
+        self.indirect_operand = None;
         let code = self.mem_read(self.program_counter);
-        self.program_counter += 1;
-        let program_counter_before = self.program_counter; // 用来标记是否发生了跳转
-        let opcode = OPCODES_MAP.get(&code).expect(&format!("OpCode {:02x} is not recognized", code));
+        let opcode = match OPCODES_MAP.get(&code) {
+            Some(opcode) => opcode,
+            None => { // 不会发生, 256 个操作码均已定义; 保险起见视作 JAM
+                self.halt(code);
+                return;
+            }
+        };
+        if let Some(mut hook) = self.instruction_hook.take() {
+            let instruction = disasm::decode(&self.bus, self.program_counter, opcode);
+            hook.before_instruction(self, &instruction);
+            self.instruction_hook = Some(hook);
+        }
+        self.fetch_len = opcode.len as u16;
+        self.program_counter = self.program_counter.wrapping_add(1);
+        let interrupt_disable_before = self.status.contains(CpuFlags::INTERRUPT_DISABLE);
+        let mut extra_cycles = 0u8; // 分支指令额外的周期
+        if opcode.len == 1 { // 单字节指令的第二个周期读取下一字节并丢弃(BRK 则将其作为 padding 字节)
+            self.dummy_read(self.program_counter);
+        }
 
         match code {
             // load/store
@@ -371,44 +738,28 @@ impl Cpu{
             }
             // 分支
             0x90 => { // BCC
-                if !self.status.contains(CpuFlags::CARRY) {
-                    self.branch();
-                }
+                extra_cycles = self.branch(!self.status.contains(CpuFlags::CARRY));
             }
             0xb0 => { // BCS
-                if self.status.contains(CpuFlags::CARRY) {
-                    self.branch();
-                }
+                extra_cycles = self.branch(self.status.contains(CpuFlags::CARRY));
             }
             0xf0 => { // BEQ
-                if self.status.contains(CpuFlags::ZERO) {
-                    self.branch();
-                }
+                extra_cycles = self.branch(self.status.contains(CpuFlags::ZERO));
             }
             0x30 => { // BMI
-                if self.status.contains(CpuFlags::NEGATIVE) {
-                    self.branch();
-                }
+                extra_cycles = self.branch(self.status.contains(CpuFlags::NEGATIVE));
             }
             0xd0 => { // BNE
-                if !self.status.contains(CpuFlags::ZERO) {
-                    self.branch();
-                }
+                extra_cycles = self.branch(!self.status.contains(CpuFlags::ZERO));
             }
             0x10 => { // BPL
-                if !self.status.contains(CpuFlags::NEGATIVE) {
-                    self.branch();
-                }
+                extra_cycles = self.branch(!self.status.contains(CpuFlags::NEGATIVE));
             }
             0x50 => { // BVC
-                if !self.status.contains(CpuFlags::OVERFLOW) {
-                    self.branch();
-                }
+                extra_cycles = self.branch(!self.status.contains(CpuFlags::OVERFLOW));
             }
             0x70 => { // BVS
-                if self.status.contains(CpuFlags::OVERFLOW) {
-                    self.branch();
-                }
+                extra_cycles = self.branch(self.status.contains(CpuFlags::OVERFLOW));
             }
             // 状态寄存器
             0x18 => {
@@ -455,7 +806,9 @@ impl Cpu{
                 // nothing
             }
             0x00 => { // BRK
-                self.brk_flag = true;  // TODO: 软中断还未实现
+                self.program_counter = self.program_counter.wrapping_add(1); // 跳过 padding 字节
+                self.interrupt(Interrupt::Brk); // 中断序列自行驱动 7 个周期
+                return;
             }
             // unofficial
             0x07 | 0x17 | 0x0f | 0x1f | 0x1b | 0x03 | 0x13 => {
@@ -495,7 +848,7 @@ impl Cpu{
                 self.xaa(&opcode.mode);
             }
             0xab => {
-                self.lax(&opcode.mode);
+                self.lxa(&opcode.mode);
             }
             0xcb => {
                 self.axs(&opcode.mode);
@@ -504,13 +857,13 @@ impl Cpu{
                 self.sbc(&opcode.mode);
             }
             0x9f | 0x93 => {
-                self.ahx(&opcode.mode);
+                self.store_and_high_byte(&opcode.mode, self.register_a & self.register_x); // AHX
             }
             0x9c => {
-                self.shy(&opcode.mode);
+                self.store_and_high_byte(&opcode.mode, self.register_y); // SHY
             }
             0x9e => {
-                self.shx(&opcode.mode);
+                self.store_and_high_byte(&opcode.mode, self.register_x); // SHX
             }
             0x9b => {
                 self.tas(&opcode.mode);
@@ -519,60 +872,171 @@ impl Cpu{
                 self.las(&opcode.mode);
             }
             0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xb2 | 0xd2 | 0xf2 => { // KIL
-                todo!("KIL todo");
+                self.program_counter = self.program_counter.wrapping_sub(1);
+                self.halt(code);
+                return;
             }
             _ => { // NOP, DOP, TOP
-                // nothing
+                if opcode.len > 1 { // DOP, TOP 照常读取操作数
+                    let addr = self.get_operand_address(&opcode.mode);
+                    self.mem_read(addr);
+                }
             }
         }
 
-        if program_counter_before == self.program_counter { // 没有进行跳转则转至下一条指令
-            self.program_counter += (opcode.len - 1) as u16;
+        self.fetch_len = 0;
+        self.indirect_operand = None;
+
+        // 跳转, 返回与分支指令自行设置 PC, 其余指令跳过操作数
+        if !matches!(code, 0x4c | 0x6c | 0x20 | 0x40 | 0x60 | 0x90 | 0xb0 | 0xf0 | 0x30 | 0xd0 | 0x10 | 0x50 | 0x70) {
+            self.program_counter = self.program_counter.wrapping_add((opcode.len - 1) as u16);
         }
 
-        for _ in 0..opcode.cycles {
+        // 中断在倒数第二个周期轮询.
+        // CLI, SEI, PLP 在最后一个周期才改变 I 标志, 因而轮询时使用的是指令执行前的 I 标志;
+        // 跳转且未跨页的分支指令在第 3 个周期不轮询, 即与不跳转时一样在第 1 个周期轮询
+        let interrupt_disable = match code {
+            0x58 | 0x78 | 0x28 => interrupt_disable_before,
+            _ => self.status.contains(CpuFlags::INTERRUPT_DISABLE),
+        };
+        let cycles = self.cycles;
+        debug_assert!(cycles >= opcode.cycles, "OpCode {:02x} takes {} cycles, expected at least {}", code, cycles, opcode.cycles);
+        let poll_cycle = if extra_cycles == 1 {
+            cycles - 2
+        } else {
+            cycles - 1
+        };
+        for cycle in 1..=cycles {
             self.clock();
+            if cycle == poll_cycle {
+                self.poll_interrupts(interrupt_disable);
+            }
         }
     }
 
+    /// 读指令的寻址, 变址跨页时才会多一个周期(先读取未修正高字节的地址)
     fn get_operand_address(&mut self, mode: &AddressingMode) -> u16 {
+        self.get_operand_address_with_dummy_read(mode, false)
+    }
+
+    /// 写指令与读-改-写指令的寻址, 变址时总是先读取未修正高字节的地址
+    fn get_operand_address_for_write(&mut self, mode: &AddressingMode) -> u16 {
+        self.get_operand_address_with_dummy_read(mode, true)
+    }
+
+    /// 按实际的总线时序寻址, 包括各个空读周期
+    fn get_operand_address_with_dummy_read(&mut self, mode: &AddressingMode, always: bool) -> u16 {
         match mode {
             AddressingMode::Immediate => self.program_counter,
-            _ => self.get_absolute_address(mode, self.program_counter),
+            AddressingMode::ZeroPage => self.mem_read(self.program_counter) as u16,
+            AddressingMode::ZeroPage_X | AddressingMode::ZeroPage_Y => {
+                let base = self.mem_read(self.program_counter);
+                self.dummy_read(base as u16); // 加变址前先读一次
+                let index = match mode {
+                    AddressingMode::ZeroPage_X => self.register_x,
+                    _ => self.register_y,
+                };
+                base.wrapping_add(index) as u16
+            }
+            AddressingMode::Absolute => self.mem_read_u16(self.program_counter),
+            AddressingMode::Absolute_X | AddressingMode::Absolute_Y => {
+                let (base, index) = self.get_indexed_base(mode);
+                self.add_index_with_dummy_read(base, index, always)
+            }
+            AddressingMode::Indirect_Y => {
+                let (base, index) = self.get_indexed_base(mode);
+                let addr = self.add_index_with_dummy_read(base, index, always);
+                self.indirect_operand = Some(addr);
+                addr
+            }
+            AddressingMode::Indirect_X => {
+                let base = self.mem_read(self.program_counter);
+                self.dummy_read(base as u16); // 加变址前先读一次
+                let ptr = base.wrapping_add(self.register_x);
+                let lo = self.mem_read(ptr as u16) as u16;
+                let hi = self.mem_read(ptr.wrapping_add(1) as u16) as u16; // 不能超过 ZeroPage
+                let addr = (hi << 8) | lo;
+                self.indirect_operand = Some(addr);
+                addr
+            }
+            _ => {
+                panic!("mode {:?} is not supported", mode);
+            }
+        }
+    }
+
+    /// Absolute_X, Absolute_Y, Indirect_Y 寻址中加变址之前的基地址与变址
+    fn get_indexed_base(&mut self, mode: &AddressingMode) -> (u16, u8) {
+        match mode {
+            AddressingMode::Absolute_X => (self.mem_read_u16(self.program_counter), self.register_x),
+            AddressingMode::Absolute_Y => (self.mem_read_u16(self.program_counter), self.register_y),
+            AddressingMode::Indirect_Y => {
+                let ptr = self.mem_read(self.program_counter);
+                let lo = self.mem_read(ptr as u16) as u16;
+                let hi = self.mem_read(ptr.wrapping_add(1) as u16) as u16; // 不能超过 ZeroPage
+                ((hi << 8) | lo, self.register_y)
+            }
+            _ => {
+                panic!("mode {:?} is not indexed", mode);
+            }
         }
     }
 
-    fn get_absolute_address(&mut self, mode: &AddressingMode, addr: u16) -> u16 {
+    /// 基地址加变址, 6502 先只加低字节并读取该地址, 跨页时再修正高字节
+    fn add_index_with_dummy_read(&mut self, base: u16, index: u8, always: bool) -> u16 {
+        let addr = base.wrapping_add(index as u16);
+        let uncorrected = (base & 0xff00) | (addr & 0x00ff);
+        if always || uncorrected != addr {
+            self.dummy_read(uncorrected);
+        }
+        addr
+    }
+
+    /// 读-改-写指令: 读出操作数后先将原值写回, 下一个周期才写入新值, 返回新值
+    fn read_modify_write<F>(&mut self, mode: &AddressingMode, modify: F) -> u8
+    where
+        F: FnOnce(&mut Self, u8) -> u8
+    {
+        let addr = self.get_operand_address_for_write(mode);
+        let data = self.mem_read(addr);
+        self.mem_write(addr, data);
+        let data = modify(self, data);
+        self.mem_write(addr, data);
+        data
+    }
+
+    /// 通过 peek 无副作用地计算操作数地址(用于 trace), addr 为操作数所在地址
+    fn get_absolute_address(&self, mode: &AddressingMode, addr: u16) -> u16 {
         match mode {
-            AddressingMode::ZeroPage => self.mem_read(addr) as u16,
+            AddressingMode::ZeroPage => self.bus.peek(addr) as u16,
             AddressingMode::ZeroPage_X => {
-                let pos = self.mem_read(addr);
+                let pos = self.bus.peek(addr);
                 pos.wrapping_add(self.register_x) as u16
             }
             AddressingMode::ZeroPage_Y => {
-                let pos = self.mem_read(addr);
+                let pos = self.bus.peek(addr);
                 pos.wrapping_add(self.register_y) as u16
             }
-            AddressingMode::Absolute => self.mem_read_u16(addr),
+            AddressingMode::Absolute => self.peek_u16(addr),
             AddressingMode::Absolute_X => {
-                let pos = self.mem_read_u16(addr);
+                let pos = self.peek_u16(addr);
                 pos.wrapping_add(self.register_x as u16)
             }
             AddressingMode::Absolute_Y => {
-                let pos = self.mem_read_u16(addr);
+                let pos = self.peek_u16(addr);
                 pos.wrapping_add(self.register_y as u16)
             }
             AddressingMode::Indirect_X => {
-                let base = self.mem_read(addr);
+                let base = self.bus.peek(addr);
                 let ptr = base.wrapping_add(self.register_x);
-                let lo = self.mem_read(ptr as u16) as u16;
-                let hi = self.mem_read(ptr.wrapping_add(1) as u16) as u16; // 不能超过 ZeroPage
+                let lo = self.bus.peek(ptr as u16) as u16;
+                let hi = self.bus.peek(ptr.wrapping_add(1) as u16) as u16; // 不能超过 ZeroPage
                 (hi << 8) | lo
             }
             AddressingMode::Indirect_Y => {
-                let ptr = self.mem_read(addr);
-                let lo = self.mem_read(ptr as u16) as u16;
-                let hi = self.mem_read(ptr.wrapping_add(1) as u16) as u16; // 不能超过 ZeroPage
+                let ptr = self.bus.peek(addr);
+                let lo = self.bus.peek(ptr as u16) as u16;
+                let hi = self.bus.peek(ptr.wrapping_add(1) as u16) as u16; // 不能超过 ZeroPage
                 let addr_base = (hi << 8) | lo;
                 addr_base.wrapping_add(self.register_y as u16)
             }
@@ -582,6 +1046,13 @@ impl Cpu{
         }
     }
 
+    /// 按照 Little-Endian 无副作用地读取 2 字节
+    fn peek_u16(&self, addr: u16) -> u16 {
+        let lo = self.bus.peek(addr) as u16;
+        let hi = self.bus.peek(addr.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    }
+
     fn lda(&mut self, mode: &AddressingMode) {
         let addr = self.get_operand_address(mode);
         let value = self.mem_read(addr);
@@ -607,17 +1078,17 @@ impl Cpu{
     }
 
     fn sta(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
+        let addr = self.get_operand_address_for_write(mode);
         self.mem_write(addr, self.register_a);
     }
 
     fn stx(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
+        let addr = self.get_operand_address_for_write(mode);
         self.mem_write(addr, self.register_x);
     }
 
     fn sty(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
+        let addr = self.get_operand_address_for_write(mode);
         self.mem_write(addr, self.register_y);
     }
 
@@ -626,18 +1097,20 @@ impl Cpu{
     }
 
     fn php(&mut self) {
-        let mut status = self.status.clone();
+        let mut status = self.status;
         status.insert(CpuFlags::BREAK2);
         status.insert(CpuFlags::BREAK);  // UB = 11 if PHP
         self.stack_push(status.bits());
     }
 
     fn pla(&mut self) {
+        self.dummy_read(STACK + self.stack_pointer as u16); // S 加一前先读一次
         self.register_a = self.stack_pop();
         self.update_zero_and_negative_flags(self.register_a);
     }
 
     fn plp(&mut self) {
+        self.dummy_read(STACK + self.stack_pointer as u16); // S 加一前先读一次
         self.status.bits = self.stack_pop();
         self.status.insert(CpuFlags::BREAK2);
         self.status.remove(CpuFlags::BREAK);
@@ -667,9 +1140,7 @@ impl Cpu{
     }
 
     fn dec(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr).wrapping_sub(1);
-        self.mem_write(addr, value);
+        let value = self.read_modify_write(mode, |_, data| data.wrapping_sub(1));
 
         self.update_zero_and_negative_flags(value);
     }
@@ -687,9 +1158,7 @@ impl Cpu{
     }
 
     fn inc(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr).wrapping_add(1);
-        self.mem_write(addr, value);
+        let value = self.read_modify_write(mode, |_, data| data.wrapping_add(1));
 
         self.update_zero_and_negative_flags(value);
     }
@@ -711,10 +1180,7 @@ impl Cpu{
     }
 
     fn asl(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let data = self.mem_read(addr);
-        let data = self.arithmetic_shift_left_update_nzc(data);
-        self.mem_write(addr, data);
+        self.read_modify_write(mode, Self::arithmetic_shift_left_update_nzc);
     }
 
     fn arithmetic_shift_left_update_nzc(&mut self, data: u8) -> u8 {
@@ -733,10 +1199,7 @@ impl Cpu{
     }
 
     fn lsr(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let data = self.mem_read(addr);
-        let data = self.logical_shift_right_update_nzc(data);
-        self.mem_write(addr, data);
+        self.read_modify_write(mode, Self::logical_shift_right_update_nzc);
     }
 
     fn logical_shift_right_update_nzc(&mut self, data:u8) -> u8 {
@@ -755,10 +1218,7 @@ impl Cpu{
     }
 
     fn rol(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let data = self.mem_read(addr);
-        let data = self.rotate_left_through_carry_update_nzc(data);
-        self.mem_write(addr, data);
+        self.read_modify_write(mode, Self::rotate_left_through_carry_update_nzc);
     }
 
     fn rotate_left_through_carry_update_nzc(&mut self, data: u8) -> u8 {
@@ -782,10 +1242,7 @@ impl Cpu{
     }
 
     fn ror(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let data = self.mem_read(addr);
-        let data = self.rotate_right_through_carry_update_nzc(data);
-        self.mem_write(addr, data);
+        self.read_modify_write(mode, Self::rotate_right_through_carry_update_nzc);
     }
 
     fn rotate_right_through_carry_update_nzc(&mut self, data: u8) -> u8 {
@@ -874,7 +1331,7 @@ impl Cpu{
         let addr = self.get_operand_address(mode);
         let value = self.mem_read(addr);
 
-        self.add_to_a_with_carry_update_nvzc(value);
+        self.add_with_carry(value);
     }
 
     fn sbc(&mut self, mode: &AddressingMode) {
@@ -884,7 +1341,25 @@ impl Cpu{
         // A 寄存器 A, M 操作数, B borrow bit, C carry bit
         // A <- A - M - B = A - M - !C = A - M - 1 + C
         //   = A + (!M + 1) - 1 + C = A + !M + C (若和大于 255, 则不需要借位, Carry 为 1, 与加法处相同)
-        self.add_to_a_with_carry_update_nvzc(!value); // 取负数并变补码
+        self.subtract_with_borrow(value);
+    }
+
+    /// A <- A + M + C, 开启十进制模式且 D 为 1 时按 BCD 计算
+    fn add_with_carry(&mut self, value: u8) {
+        if self.decimal_mode_enabled && self.status.contains(CpuFlags::DECIMAL) {
+            self.decimal_add_to_a_with_carry_update_nvzc(value);
+        } else {
+            self.add_to_a_with_carry_update_nvzc(value);
+        }
+    }
+
+    /// A <- A - M - !C, 开启十进制模式且 D 为 1 时按 BCD 计算
+    fn subtract_with_borrow(&mut self, value: u8) {
+        if self.decimal_mode_enabled && self.status.contains(CpuFlags::DECIMAL) {
+            self.decimal_subtract_from_a_with_borrow_update_nvzc(value);
+        } else {
+            self.add_to_a_with_carry_update_nvzc(!value); // 取负数并变补码
+        }
     }
 
     fn add_to_a_with_carry_update_nvzc(&mut self, value: u8) {
@@ -910,6 +1385,50 @@ impl Cpu{
         self.register_a = result;
     }
 
+    /// NMOS 6502 的 BCD 加法, 其中 Z 由二进制结果决定, N 与 V 由高 4 位调整前的结果决定
+    fn decimal_add_to_a_with_carry_update_nvzc(&mut self, value: u8) {
+        let a = self.register_a as u16;
+        let m = value as u16;
+        let carry = self.status.contains(CpuFlags::CARRY) as u16;
+        let binary = (a + m + carry) as u8;
+
+        let mut lo = (a & 0x0f) + (m & 0x0f) + carry;
+        if lo > 0x09 {
+            lo += 0x06;
+        }
+        let mut result = (a & 0xf0) + (m & 0xf0) + if lo > 0x0f { 0x10 } else { 0 } + (lo & 0x0f);
+
+        self.status.set(CpuFlags::ZERO, binary == 0);
+        self.status.set(CpuFlags::NEGATIVE, result & 0x80 != 0);
+        self.status.set(CpuFlags::OVERFLOW, (a ^ result) & 0x80 != 0 && (a ^ m) & 0x80 == 0);
+        if result > 0x9f {
+            result += 0x60;
+        }
+        self.status.set(CpuFlags::CARRY, result > 0xff);
+        self.register_a = result as u8;
+    }
+
+    /// NMOS 6502 的 BCD 减法, 标志位与二进制减法相同
+    fn decimal_subtract_from_a_with_borrow_update_nvzc(&mut self, value: u8) {
+        let a = self.register_a as i16;
+        let m = value as i16;
+        let borrow = !self.status.contains(CpuFlags::CARRY) as i16;
+
+        let mut lo = (a & 0x0f) - (m & 0x0f) - borrow;
+        let mut hi = (a >> 4) - (m >> 4);
+        if lo < 0 {
+            lo -= 0x06;
+            hi -= 1;
+        }
+        if hi < 0 {
+            hi -= 0x06;
+        }
+        let result = (((hi << 4) | (lo & 0x0f)) & 0xff) as u8;
+
+        self.add_to_a_with_carry_update_nvzc(!value); // 只用来更新 NVZC
+        self.register_a = result;
+    }
+
     fn jmp_absolute(&mut self) {
         self.program_counter = self.mem_read_u16(self.program_counter);
     }
@@ -924,33 +1443,53 @@ impl Cpu{
         } else {
             self.mem_read_u16(addr)
         };
+        self.bus.jump_indirect(target);
         self.program_counter = target;
     }
 
     fn jsr(&mut self) {
+        // 先读目标地址低字节, 入栈之后才读高字节
+        let lo = self.mem_read(self.program_counter) as u16;
+        self.dummy_read(STACK + self.stack_pointer as u16); // 空读栈顶
         // pushes the address-1 of the next operation on to the stack
         let next_minus_1 = self.program_counter.wrapping_add(1);
         self.stack_push_u16(next_minus_1);
-        self.program_counter = self.mem_read_u16(self.program_counter);
+        let hi = self.mem_read(next_minus_1) as u16;
+        self.program_counter = (hi << 8) | lo;
     }
 
     fn rts(&mut self) {
+        self.dummy_read(STACK + self.stack_pointer as u16); // S 加一前先读一次
         let next_minus_1 = self.stack_pop_u16();
+        self.dummy_read(next_minus_1); // PC 加一前先读一次
         self.program_counter = next_minus_1.wrapping_add(1);
     }
 
     fn rti(&mut self) {
+        self.dummy_read(STACK + self.stack_pointer as u16); // S 加一前先读一次
         self.status.bits = self.stack_pop();
         self.status.insert(CpuFlags::BREAK2);
         self.status.remove(CpuFlags::BREAK);
         self.program_counter = self.stack_pop_u16();
     }
 
-    fn branch(&mut self) {
+    /// 条件成立时跳转, 返回额外的周期数: 跳转加 1, 跨页再加 1
+    fn branch(&mut self, condition: bool) -> u8 {
         let offset = self.mem_read(self.program_counter) as i8; // branch 有符号
-        self.program_counter = self.program_counter
-            .wrapping_add(1)
-            .wrapping_add(offset as u16);
+        let next = self.program_counter.wrapping_add(1);
+        self.program_counter = next;
+        if !condition {
+            return 0;
+        }
+        self.dummy_read(next); // 读取下一条指令的操作码并丢弃
+        let target = next.wrapping_add(offset as u16);
+        self.program_counter = target;
+        if next & 0xff00 == target & 0xff00 {
+            1
+        } else {
+            self.dummy_read((next & 0xff00) | (target & 0x00ff)); // 先只修改低字节
+            2
+        }
     }
 
     fn clc(&mut self) {
@@ -1033,46 +1572,34 @@ impl Cpu{
 
     // Shift left one bit in memory, then OR accumulator with memory.
     fn slo(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let data = self.mem_read(addr);
-        let data = self.arithmetic_shift_left_update_nzc(data);
-        self.mem_write(addr, data);
+        let data = self.read_modify_write(mode, Self::arithmetic_shift_left_update_nzc);
         self.register_a = self.register_a | data;
         self.update_zero_and_negative_flags(self.register_a);
     }
 
     // Rotate one bit left in memory, then AND accumulator with memory
     fn rla(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let data = self.mem_read(addr);
-        let data = self.rotate_left_through_carry_update_nzc(data);
-        self.mem_write(addr, data);
+        let data = self.read_modify_write(mode, Self::rotate_left_through_carry_update_nzc);
         self.register_a = self.register_a & data;
         self.update_zero_and_negative_flags(self.register_a);
     }
 
     // Shift right one bit in memory, then EOR accumulator with memory.
     fn sre(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let data = self.mem_read(addr);
-        let data = self.logical_shift_right_update_nzc(data);
-        self.mem_write(addr, data);
+        let data = self.read_modify_write(mode, Self::logical_shift_right_update_nzc);
         self.register_a = self.register_a ^ data;
         self.update_zero_and_negative_flags(self.register_a);
     }
 
     // Rotate one bit right in memory, then add memory to accumulator (with carry).
     fn rra(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let data = self.mem_read(addr);
-        let data = self.rotate_right_through_carry_update_nzc(data);
-        self.mem_write(addr, data);
-        self.add_to_a_with_carry_update_nvzc(data);
+        let data = self.read_modify_write(mode, Self::rotate_right_through_carry_update_nzc);
+        self.add_with_carry(data);
     }
 
     // AND X register with accumulator and store result in memory.
     fn sax(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
+        let addr = self.get_operand_address_for_write(mode);
         let result = self.register_a & self.register_x;
         self.mem_write(addr, result);
     }
@@ -1086,9 +1613,7 @@ impl Cpu{
     // Subtract 1 from memory (without borrow).
     // 通过 A - result 的结果改变 NZC
     fn dcp(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let result = self.mem_read(addr).wrapping_sub(1);
-        self.mem_write(addr, result);
+        let result = self.read_modify_write(mode, |_, data| data.wrapping_sub(1));
 
         if self.register_a >= result {
             self.status.insert(CpuFlags::CARRY);
@@ -1101,12 +1626,10 @@ impl Cpu{
 
     // Increase memory by one, then subtract memory from accu-mulator (with borrow).
     fn isc(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let result = self.mem_read(addr).wrapping_add(1);
-        self.mem_write(addr, result);
+        let result = self.read_modify_write(mode, |_, data| data.wrapping_add(1));
 
         // 原理见 fn sbc 注释
-        self.add_to_a_with_carry_update_nvzc(!result);
+        self.subtract_with_borrow(result);
     }
 
     // AND byte with accumulator. If result is negative then carry is set.
@@ -1156,10 +1679,23 @@ impl Cpu{
         }
     }
 
-    // 	A := X & #{imm}
+    // A := (A | MAGIC) & X & #{imm}
+    // 结果与芯片有关, MAGIC 取常见的 0xee
     fn xaa(&mut self, mode: &AddressingMode) {
-        self.txa();
-        self.and(mode);
+        let addr = self.get_operand_address(mode);
+        let data = self.mem_read(addr);
+        self.register_a = (self.register_a | 0xee) & self.register_x & data;
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+
+    // A, X := (A | MAGIC) & #{imm}
+    // 结果与芯片有关, MAGIC 取常见的 0xee
+    fn lxa(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        let data = self.mem_read(addr);
+        self.register_a = (self.register_a | 0xee) & data;
+        self.register_x = self.register_a;
+        self.update_zero_and_negative_flags(self.register_a);
     }
 
     // AND X register with accumulator and store result in X register, then subtract byte from X register (without borrow).
@@ -1178,33 +1714,24 @@ impl Cpu{
         self.update_zero_and_negative_flags(self.register_x);
     }
 
-    // {adr} := A & X & High(adr)
-    fn ahx(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let result = self.register_a & self.register_x & (addr >> 8) as u8;
-        self.mem_write(addr, result);
-    }
-
-    // {adr} := Y & H
-    fn shy(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let result = self.register_y & (addr >> 8) as u8;
-        self.mem_write(addr, result);
-    }
-
-    // {adr} := X & H
-    fn shx(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let result = self.register_x & (addr >> 8) as u8;
+    // AHX, SHY, SHX, TAS 的写入: {adr} := value & (H + 1), H 为基地址的高字节
+    // 变址跨页时写入的值同时替换了目标地址的高字节
+    fn store_and_high_byte(&mut self, mode: &AddressingMode, value: u8) {
+        let (base, index) = self.get_indexed_base(mode);
+        let addr = self.add_index_with_dummy_read(base, index, true);
+        let result = value & ((base >> 8) as u8).wrapping_add(1);
+        let addr = if addr & 0xff00 != base & 0xff00 {
+            ((result as u16) << 8) | (addr & 0x00ff)
+        } else {
+            addr
+        };
         self.mem_write(addr, result);
     }
 
     // AND X register with accumulator and store result in stack pointer, then AND stack pointer with the high byte of the target address of the argument + 1. Store result in memory.
     fn tas(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
         self.stack_pointer = self.register_x & self.register_a;
-        let result = self.stack_pointer & ((addr >> 8) as u8 + 1);
-        self.mem_write(addr, result);
+        self.store_and_high_byte(mode, self.stack_pointer);
     }
 
     // A,X,S:={adr}&S
@@ -1225,9 +1752,10 @@ mod tests {
     use super::*;
     use crate::cartridge::tests::*;
 
-    impl Cpu {
+    impl<B: CpuBus> Cpu<B> {
+        /// 运行直到下一条指令为 BRK(不执行该 BRK)
         fn run_until_brk(&mut self) {
-            while !self.brk_flag {
+            while self.mem_read(self.program_counter) != 0x00 {
                 self.run_next_instruction();
             }
         }
@@ -1642,4 +2170,190 @@ mod tests {
         cpu.run_until_brk();
         assert!(cpu.status.contains(CpuFlags::INTERRUPT_DISABLE));
     }
+
+    /// 生成 32KB PRG, 代码位于 0x8000, 0x9000 处为 BRK, 并设置 NMI 与 IRQ/BRK 向量
+    fn prg_with_vectors(code: &[u8], nmi: u16, irq: u16) -> Vec<u8> {
+        let mut prg = vec![0xea; 0x8000]; // NOP
+        prg[..code.len()].copy_from_slice(code);
+        prg[0x1000] = 0x00;
+        prg[0x7ffa] = (nmi & 0xff) as u8;
+        prg[0x7ffb] = (nmi >> 8) as u8;
+        prg[0x7ffe] = (irq & 0xff) as u8;
+        prg[0x7fff] = (irq >> 8) as u8;
+        prg
+    }
+
+    #[test]
+    fn test_brk() {
+        let mut cpu = Cpu::new(test_rom_with_2_bank_prg(prg_with_vectors(
+            &[0x00, 0xff], // BRK; padding
+            0xa000,
+            0x9000,
+        )));
+        cpu.reset();
+        let cycles = cpu.bus.cycles();
+        cpu.run_next_instruction();
+        assert_eq!(cpu.bus.cycles() - cycles, 7);
+        assert_eq!(cpu.program_counter, 0x9000);
+        assert_eq!(cpu.stack_pointer, STACK_RESET - 3);
+        assert_eq!(cpu.mem_read_u16(STACK + STACK_RESET as u16 - 1), 0x8002); // BRK 地址 + 2
+        assert_eq!(cpu.mem_read(STACK + STACK_RESET as u16 - 2), 0b0011_0100); // UB=11
+        assert!(cpu.status.contains(CpuFlags::INTERRUPT_DISABLE));
+    }
+
+    #[test]
+    fn test_nmi_hijacks_brk() {
+        let mut cpu = Cpu::new(test_rom_with_2_bank_prg(prg_with_vectors(
+            &[0x00, 0xff], // BRK; padding
+            0xa000,
+            0x9000,
+        )));
+        cpu.reset();
+        cpu.nmi_pending = true;
+        cpu.run_next_instruction();
+        assert_eq!(cpu.program_counter, 0xa000); // 使用 NMI 向量
+        assert!(!cpu.nmi_pending); // NMI 视为已响应
+        assert_eq!(cpu.mem_read(STACK + STACK_RESET as u16 - 2), 0b0011_0100); // B 标志仍为 BRK 的
+    }
+
+    #[test]
+    fn test_branch_cycles() {
+        let mut cpu = Cpu::new(test_rom_with_2_bank_prg(vec![
+            0x18, // CLC
+            0xb0, 0x10, // BCS +$10, 不跳转
+            0x90, 0x00, // BCC +$00, 跳转且不跨页
+            0x90, 0x80, // BCC -$80, 跳转且跨页
+        ]));
+        cpu.reset();
+        cpu.run_next_instruction();
+        let mut cycles = cpu.bus.cycles();
+        for expected in [2, 3, 4] {
+            cpu.run_next_instruction();
+            assert_eq!(cpu.bus.cycles() - cycles, expected);
+            cycles = cpu.bus.cycles();
+        }
+        assert_eq!(cpu.program_counter, 0x8007u16.wrapping_sub(0x80));
+    }
+
+    #[test]
+    fn test_cli_delays_irq_by_one_instruction() {
+        // 等待 APU frame counter 产生 IRQ, 之后 CLI, 下一条指令执行后才响应 IRQ
+        let mut cpu = Cpu::new(test_rom_with_2_bank_prg(prg_with_vectors(
+            &[
+                0x78, // SEI
+                0xa0, 0x20, // LDY #$20
+                0xa2, 0x00, // LDX #$00
+                0xca, // DEX
+                0xd0, 0xfd, // BNE $8005
+                0x88, // DEY
+                0xd0, 0xfa, // BNE $8005
+                0x58, // CLI
+                0xe8, // INX
+                0x4c, 0x0d, 0x80, // JMP $800d
+            ],
+            0xa000,
+            0x9000,
+        )));
+        cpu.reset();
+        cpu.run_until_brk();
+        assert_eq!(cpu.program_counter, 0x9000);
+        assert_eq!(cpu.register_x, 1); // CLI 之后的 INX 已执行
+        assert_eq!(cpu.mem_read_u16(STACK + STACK_RESET as u16 - 1), 0x800d);
+        assert_eq!(cpu.mem_read(STACK + STACK_RESET as u16 - 2) & 0b0011_0000, 0b0010_0000); // UB=10
+    }
+
+    fn flat_cpu(code: &[u8]) -> Cpu<FlatBus> {
+        let mut bus = FlatBus::new();
+        bus.load(0x0200, code);
+        bus.load(0xfffc, &[0x00, 0x02]);
+        let mut cpu = Cpu::with_bus(bus);
+        cpu.reset();
+        cpu
+    }
+
+    #[test]
+    fn test_flat_bus() {
+        let mut cpu = flat_cpu(&[
+            0xa9, 0x42, // LDA #$42
+            0x8d, 0x00, 0x30, // STA $3000
+            0x00,
+        ]);
+        cpu.run_until_brk();
+        assert_eq!(cpu.bus().memory()[0x3000], 0x42);
+        assert_eq!(cpu.bus().memory()[0x0800], 0x00); // 没有 RAM 镜像
+        assert_eq!(cpu.bus().cycles(), 7 + 2 + 4);
+    }
+
+    #[test]
+    fn test_decimal_mode() {
+        let code = [
+            0xf8, // SED
+            0x18, // CLC
+            0xa9, 0x19, // LDA #$19
+            0x69, 0x28, // ADC #$28
+            0x00,
+        ];
+        let mut cpu = flat_cpu(&code);
+        cpu.run_until_brk();
+        assert_eq!(cpu.register_a, 0x41); // 默认(2A03)忽略 D 标志
+
+        let mut cpu = flat_cpu(&code);
+        cpu.set_decimal_mode_enabled(true);
+        cpu.run_until_brk();
+        assert_eq!(cpu.register_a, 0x47);
+        assert!(!cpu.status.contains(CpuFlags::CARRY));
+
+        let mut cpu = flat_cpu(&[
+            0xf8, // SED
+            0x38, // SEC
+            0xa9, 0x50, // LDA #$50
+            0xe9, 0x01, // SBC #$01
+            0x00,
+        ]);
+        cpu.set_decimal_mode_enabled(true);
+        cpu.run_until_brk();
+        assert_eq!(cpu.register_a, 0x49);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+
+        let mut cpu = flat_cpu(&[
+            0xf8, // SED
+            0x18, // CLC
+            0xa9, 0x99, // LDA #$99
+            0x69, 0x01, // ADC #$01
+            0x00,
+        ]);
+        cpu.set_decimal_mode_enabled(true);
+        cpu.run_until_brk();
+        assert_eq!(cpu.register_a, 0x00);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+    }
+
+    #[test]
+    fn test_jam_halts_until_reset() {
+        let mut cpu = Cpu::new(test_rom_with_2_bank_prg(vec![
+            0xe8, // INX
+            0x02, // JAM
+            0xe8, // INX
+        ]));
+        cpu.reset();
+        cpu.run_next_instruction();
+        cpu.run_next_instruction();
+        let halted = CpuHalted { pc: 0x8001, opcode: 0x02 };
+        assert_eq!(cpu.halted(), Some(halted));
+        assert_eq!(cpu.run_next_frame(), Err(halted));
+        assert_eq!(cpu.register_x, 1);
+
+        // 停机时总线照常运行, 帧会结束
+        cpu.set_tick_while_halted(true);
+        let cycles = cpu.bus.cycles();
+        assert_eq!(cpu.run_next_frame(), Err(halted));
+        assert!(cpu.bus.cycles() > cycles);
+        assert_eq!(cpu.program_counter, 0x8001);
+        assert_eq!(cpu.register_x, 1);
+
+        cpu.reset();
+        assert_eq!(cpu.halted(), None);
+        cpu.run_next_instruction();
+        assert_eq!(cpu.register_x, 1);
+    }
 }
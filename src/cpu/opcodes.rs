@@ -44,32 +44,32 @@ lazy_static! {
         OpCode::new(0x21, "AND", 2, 6, AddressingMode::Indirect_X),
         OpCode::new(0x31, "AND", 2, 5, AddressingMode::Indirect_Y), // 5+
         // ASL, NZC
-        OpCode::new(0x0a, "ASL", 1, 2, AddressingMode::NoneAddressing), // Accumulator mode
+        OpCode::new(0x0a, "ASL", 1, 2, AddressingMode::Accumulator),
         OpCode::new(0x06, "ASL", 2, 5, AddressingMode::ZeroPage),
         OpCode::new(0x16, "ASL", 2, 6, AddressingMode::ZeroPage_X),
         OpCode::new(0x0e, "ASL", 3, 6, AddressingMode::Absolute),
         OpCode::new(0x1e, "ASL", 3, 7, AddressingMode::Absolute_X),
         // branch(A branch not taken requires two machine cycles. Add one if the branch is taken and add one more if the branch crosses a page boundary.)
-        OpCode::new(0x90, "BCC", 2, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xb0, "BCS", 2, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xf0, "BEQ", 2, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x90, "BCC", 2, 2, AddressingMode::Relative),
+        OpCode::new(0xb0, "BCS", 2, 2, AddressingMode::Relative),
+        OpCode::new(0xf0, "BEQ", 2, 2, AddressingMode::Relative),
         // BIT, NVZ
         OpCode::new(0x24, "BIT", 2, 3, AddressingMode::ZeroPage),
         OpCode::new(0x2c, "BIT", 3, 4, AddressingMode::Absolute),
         // branch
-        OpCode::new(0x30, "BMI", 2, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xd0, "BNE", 2, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x10, "BPL", 2, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x30, "BMI", 2, 2, AddressingMode::Relative),
+        OpCode::new(0xd0, "BNE", 2, 2, AddressingMode::Relative),
+        OpCode::new(0x10, "BPL", 2, 2, AddressingMode::Relative),
         // BRK
-        OpCode::new(0x00, "BRK", 1, 7, AddressingMode::NoneAddressing),
+        OpCode::new(0x00, "BRK", 1, 7, AddressingMode::Implied),
         // branch
-        OpCode::new(0x50, "BVC", 2, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x70, "BVS", 2, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x50, "BVC", 2, 2, AddressingMode::Relative),
+        OpCode::new(0x70, "BVS", 2, 2, AddressingMode::Relative),
         // Clear
-        OpCode::new(0x18, "CLC", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xd8, "CLD", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x58, "CLI", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xb8, "CLV", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x18, "CLC", 1, 2, AddressingMode::Implied),
+        OpCode::new(0xd8, "CLD", 1, 2, AddressingMode::Implied),
+        OpCode::new(0x58, "CLI", 1, 2, AddressingMode::Implied),
+        OpCode::new(0xb8, "CLV", 1, 2, AddressingMode::Implied),
         // CMP(+:add 1 cycle if page boundary crossed), NZC
         OpCode::new(0xc9, "CMP", 2, 2, AddressingMode::Immediate),
         OpCode::new(0xc5, "CMP", 2, 3, AddressingMode::ZeroPage),
@@ -92,8 +92,8 @@ lazy_static! {
         OpCode::new(0xd6, "DEC", 2, 6, AddressingMode::ZeroPage_X),
         OpCode::new(0xce, "DEC", 3, 6, AddressingMode::Absolute),
         OpCode::new(0xde, "DEC", 3, 7, AddressingMode::Absolute_X),
-        OpCode::new(0xca, "DEX", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x88, "DEY", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0xca, "DEX", 1, 2, AddressingMode::Implied),
+        OpCode::new(0x88, "DEY", 1, 2, AddressingMode::Implied),
         // EOR(+:add 1 cycle if page boundary crossed), NZ
         OpCode::new(0x49, "EOR", 2, 2, AddressingMode::Immediate),
         OpCode::new(0x45, "EOR", 2, 3, AddressingMode::ZeroPage),
@@ -108,13 +108,13 @@ lazy_static! {
         OpCode::new(0xf6, "INC", 2, 6, AddressingMode::ZeroPage_X),
         OpCode::new(0xee, "INC", 3, 6, AddressingMode::Absolute),
         OpCode::new(0xfe, "INC", 3, 7, AddressingMode::Absolute_X),
-        OpCode::new(0xe8, "INX", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xc8, "INY", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0xe8, "INX", 1, 2, AddressingMode::Implied),
+        OpCode::new(0xc8, "INY", 1, 2, AddressingMode::Implied),
         // JMP, none
-        OpCode::new(0x4c, "JMP", 3, 3, AddressingMode::NoneAddressing), // Absolute
-        OpCode::new(0x6c, "JMP", 3, 5, AddressingMode::NoneAddressing), // Indirect
+        OpCode::new(0x4c, "JMP", 3, 3, AddressingMode::Absolute),
+        OpCode::new(0x6c, "JMP", 3, 5, AddressingMode::Indirect),
         // JSR(jump to subroutine), none
-        OpCode::new(0x20, "JSR", 3, 6, AddressingMode::NoneAddressing), // Absolute
+        OpCode::new(0x20, "JSR", 3, 6, AddressingMode::Absolute),
         // LDA(+:add 1 cycle if page boundary crossed), NZ
         OpCode::new(0xa9, "LDA", 2, 2, AddressingMode::Immediate),
         OpCode::new(0xa5, "LDA", 2, 3, AddressingMode::ZeroPage),
@@ -137,13 +137,13 @@ lazy_static! {
         OpCode::new(0xac, "LDY", 3, 4, AddressingMode::Absolute),
         OpCode::new(0xbc, "LDY", 3, 4, AddressingMode::Absolute_X), // 4+
         // LSR, NZC
-        OpCode::new(0x4a, "LSR", 1, 2, AddressingMode::NoneAddressing), // Accumulator mode
+        OpCode::new(0x4a, "LSR", 1, 2, AddressingMode::Accumulator),
         OpCode::new(0x46, "LSR", 2, 5, AddressingMode::ZeroPage),
         OpCode::new(0x56, "LSR", 2, 6, AddressingMode::ZeroPage_X),
         OpCode::new(0x4e, "LSR", 3, 6, AddressingMode::Absolute),
         OpCode::new(0x5e, "LSR", 3, 7, AddressingMode::Absolute_X),
         // NOP, none
-        OpCode::new(0xea, "NOP", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0xea, "NOP", 1, 2, AddressingMode::Implied),
         // ORA(+:add 1 cycle if page boundary crossed), NZ
         OpCode::new(0x09, "ORA", 2, 2, AddressingMode::Immediate),
         OpCode::new(0x05, "ORA", 2, 3, AddressingMode::ZeroPage),
@@ -154,27 +154,27 @@ lazy_static! {
         OpCode::new(0x01, "ORA", 2, 6, AddressingMode::Indirect_X),
         OpCode::new(0x11, "ORA", 2, 5, AddressingMode::Indirect_Y), // 5+
         // stack push, none
-        OpCode::new(0x48, "PHA", 1, 3, AddressingMode::NoneAddressing),
-        OpCode::new(0x08, "PHP", 1, 3, AddressingMode::NoneAddressing),
+        OpCode::new(0x48, "PHA", 1, 3, AddressingMode::Implied),
+        OpCode::new(0x08, "PHP", 1, 3, AddressingMode::Implied),
         // stack pop(pull), NZ(PLP all)
-        OpCode::new(0x68, "PLA", 1, 4, AddressingMode::NoneAddressing),
-        OpCode::new(0x28, "PLP", 1, 4, AddressingMode::NoneAddressing),
+        OpCode::new(0x68, "PLA", 1, 4, AddressingMode::Implied),
+        OpCode::new(0x28, "PLP", 1, 4, AddressingMode::Implied),
         // ROL, NZC
-        OpCode::new(0x2a, "ROL", 1, 2, AddressingMode::NoneAddressing), // Accumulator mode
+        OpCode::new(0x2a, "ROL", 1, 2, AddressingMode::Accumulator),
         OpCode::new(0x26, "ROL", 2, 5, AddressingMode::ZeroPage),
         OpCode::new(0x36, "ROL", 2, 6, AddressingMode::ZeroPage_X),
         OpCode::new(0x2e, "ROL", 3, 6, AddressingMode::Absolute),
         OpCode::new(0x3e, "ROL", 3, 7, AddressingMode::Absolute_X),
         // ROR, NZC
-        OpCode::new(0x6a, "ROR", 1, 2, AddressingMode::NoneAddressing), // Accumulator mode
+        OpCode::new(0x6a, "ROR", 1, 2, AddressingMode::Accumulator),
         OpCode::new(0x66, "ROR", 2, 5, AddressingMode::ZeroPage),
         OpCode::new(0x76, "ROR", 2, 6, AddressingMode::ZeroPage_X),
         OpCode::new(0x6e, "ROR", 3, 6, AddressingMode::Absolute),
         OpCode::new(0x7e, "ROR", 3, 7, AddressingMode::Absolute_X),
         // RTI(return from interrupt), all
-        OpCode::new(0x40, "RTI", 1, 6, AddressingMode::NoneAddressing),
+        OpCode::new(0x40, "RTI", 1, 6, AddressingMode::Implied),
         // RTS(return from subroutine), None
-        OpCode::new(0x60, "RTS", 1, 6, AddressingMode::NoneAddressing),
+        OpCode::new(0x60, "RTS", 1, 6, AddressingMode::Implied),
         // SBC(+:add 1 cycle if page boundary crossed), NVZC
         OpCode::new(0xe9, "SBC", 2, 2, AddressingMode::Immediate),
         OpCode::new(0xe5, "SBC", 2, 3, AddressingMode::ZeroPage),
@@ -185,9 +185,9 @@ lazy_static! {
         OpCode::new(0xe1, "SBC", 2, 6, AddressingMode::Indirect_X),
         OpCode::new(0xf1, "SBC", 2, 5, AddressingMode::Indirect_Y), // 5+
         // Set
-        OpCode::new(0x38, "SEC", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xf8, "SED", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x78, "SEI", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x38, "SEC", 1, 2, AddressingMode::Implied),
+        OpCode::new(0xf8, "SED", 1, 2, AddressingMode::Implied),
+        OpCode::new(0x78, "SEI", 1, 2, AddressingMode::Implied),
         // STA, none flag
         OpCode::new(0x85, "STA", 2, 3, AddressingMode::ZeroPage),
         OpCode::new(0x95, "STA", 2, 4, AddressingMode::ZeroPage_X),
@@ -205,12 +205,12 @@ lazy_static! {
         OpCode::new(0x94, "STY", 2, 4, AddressingMode::ZeroPage_X),
         OpCode::new(0x8c, "STY", 3, 4, AddressingMode::Absolute),
         // Transfer, NZ(TXS none)
-        OpCode::new(0xaa, "TAX", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xa8, "TAY", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xba, "TSX", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x8a, "TXA", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x9a, "TXS", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x98, "TYA", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0xaa, "TAX", 1, 2, AddressingMode::Implied),
+        OpCode::new(0xa8, "TAY", 1, 2, AddressingMode::Implied),
+        OpCode::new(0xba, "TSX", 1, 2, AddressingMode::Implied),
+        OpCode::new(0x8a, "TXA", 1, 2, AddressingMode::Implied),
+        OpCode::new(0x9a, "TXS", 1, 2, AddressingMode::Implied),
+        OpCode::new(0x98, "TYA", 1, 2, AddressingMode::Implied),
 
         // unofficial
 
@@ -301,25 +301,25 @@ lazy_static! {
         // LAS, NZ
         OpCode::new(0xbb, "*LAS", 3, 4, AddressingMode::Absolute_Y), // 4+
         // KIL, None
-        OpCode::new(0x02, "*KIL", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x12, "*KIL", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x22, "*KIL", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x32, "*KIL", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x42, "*KIL", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x52, "*KIL", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x62, "*KIL", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x72, "*KIL", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x92, "*KIL", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xb2, "*KIL", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xd2, "*KIL", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xf2, "*KIL", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x02, "*KIL", 1, 2, AddressingMode::Implied),
+        OpCode::new(0x12, "*KIL", 1, 2, AddressingMode::Implied),
+        OpCode::new(0x22, "*KIL", 1, 2, AddressingMode::Implied),
+        OpCode::new(0x32, "*KIL", 1, 2, AddressingMode::Implied),
+        OpCode::new(0x42, "*KIL", 1, 2, AddressingMode::Implied),
+        OpCode::new(0x52, "*KIL", 1, 2, AddressingMode::Implied),
+        OpCode::new(0x62, "*KIL", 1, 2, AddressingMode::Implied),
+        OpCode::new(0x72, "*KIL", 1, 2, AddressingMode::Implied),
+        OpCode::new(0x92, "*KIL", 1, 2, AddressingMode::Implied),
+        OpCode::new(0xb2, "*KIL", 1, 2, AddressingMode::Implied),
+        OpCode::new(0xd2, "*KIL", 1, 2, AddressingMode::Implied),
+        OpCode::new(0xf2, "*KIL", 1, 2, AddressingMode::Implied),
         // NOP, None
-        OpCode::new(0x1a, "*NOP", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x3a, "*NOP", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x5a, "*NOP", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x7a, "*NOP", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xda, "*NOP", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xfa, "*NOP", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x1a, "*NOP", 1, 2, AddressingMode::Implied),
+        OpCode::new(0x3a, "*NOP", 1, 2, AddressingMode::Implied),
+        OpCode::new(0x5a, "*NOP", 1, 2, AddressingMode::Implied),
+        OpCode::new(0x7a, "*NOP", 1, 2, AddressingMode::Implied),
+        OpCode::new(0xda, "*NOP", 1, 2, AddressingMode::Implied),
+        OpCode::new(0xfa, "*NOP", 1, 2, AddressingMode::Implied),
         // DOP, None, double NOP, argument has no signifi-cance
         OpCode::new(0x04, "*NOP", 2, 3, AddressingMode::ZeroPage),
         OpCode::new(0x14, "*NOP", 2, 4, AddressingMode::ZeroPage_X),
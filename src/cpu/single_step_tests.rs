@@ -0,0 +1,240 @@
+//! 逐指令 JSON 测试集(SingleStepTests/65x02, 即原 ProcessorTests)的运行器
+//!
+//! 每个用例给出初始的寄存器与 RAM, 执行一条指令后比对最终状态与逐周期的总线访问.
+//! 测试集不随仓库提供, 因而测试默认忽略. 将环境变量 `CNES_SINGLE_STEP_TESTS` 设为测试文件所在目录
+//! (如 `65x02/nes6502/v1`)后运行 `cargo test single_step -- --ignored`; 通用 6502 的测试集
+//! (`65x02/6502/v1`)还需设置 `CNES_SINGLE_STEP_DECIMAL=1` 以开启十进制模式
+
+use std::{fs, path::Path};
+
+use serde_json::Value;
+
+use super::*;
+use super::opcodes::CPU_OPCODES;
+
+/// JAM 指令使 CPU 停机, 不在测试范围内
+const JAM_OPCODES: [u8; 12] = [0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xb2, 0xd2, 0xf2];
+
+struct CpuState {
+    pc: u16,
+    s: u8,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    ram: Vec<(u16, u8)>,
+}
+
+fn parse_number(value: &Value, what: &str) -> Result<u64, String> {
+    value.as_u64().ok_or_else(|| format!("{} is not a number: {}", what, value))
+}
+
+fn parse_state(value: &Value) -> Result<CpuState, String> {
+    let ram = value["ram"]
+        .as_array()
+        .ok_or("ram is not an array")?
+        .iter()
+        .map(|pair| Ok((
+            parse_number(&pair[0], "ram address")? as u16,
+            parse_number(&pair[1], "ram value")? as u8,
+        )))
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok(CpuState {
+        pc: parse_number(&value["pc"], "pc")? as u16,
+        s: parse_number(&value["s"], "s")? as u8,
+        a: parse_number(&value["a"], "a")? as u8,
+        x: parse_number(&value["x"], "x")? as u8,
+        y: parse_number(&value["y"], "y")? as u8,
+        p: parse_number(&value["p"], "p")? as u8,
+        ram,
+    })
+}
+
+fn parse_accesses(value: &Value) -> Result<Vec<BusAccess>, String> {
+    value
+        .as_array()
+        .ok_or("cycles is not an array")?
+        .iter()
+        .map(|cycle| {
+            let kind = match cycle[2].as_str() {
+                Some("read") => AccessKind::Read,
+                Some("write") => AccessKind::Write,
+                _ => return Err(format!("unknown access kind: {}", cycle[2])),
+            };
+            Ok(BusAccess {
+                addr: parse_number(&cycle[0], "cycle address")? as u16,
+                data: parse_number(&cycle[1], "cycle value")? as u8,
+                kind,
+            })
+        })
+        .collect()
+}
+
+/// 运行一个用例, 失败时返回所有不一致之处
+fn run_case(case: &Value, decimal_mode: bool) -> Result<(), String> {
+    let name = case["name"].as_str().unwrap_or("?");
+    let initial = parse_state(&case["initial"])?;
+    let expected = parse_state(&case["final"])?;
+    let expected_accesses = parse_accesses(&case["cycles"])?;
+
+    let mut bus = FlatBus::new();
+    for &(addr, data) in initial.ram.iter() {
+        bus.memory_mut()[addr as usize] = data;
+    }
+    bus.set_recording(true);
+    let mut cpu = Cpu::with_bus(bus);
+    cpu.set_decimal_mode_enabled(decimal_mode);
+    cpu.program_counter = initial.pc;
+    cpu.stack_pointer = initial.s;
+    cpu.register_a = initial.a;
+    cpu.register_x = initial.x;
+    cpu.register_y = initial.y;
+    cpu.status = CpuFlags::from_bits_truncate(initial.p);
+
+    cpu.run_next_instruction();
+
+    let mut errors = vec![];
+    let mut check = |what: &str, actual: u16, expected: u16| {
+        if actual != expected {
+            errors.push(format!("{} = {:04x}, expected {:04x}", what, actual, expected));
+        }
+    };
+    check("pc", cpu.program_counter, expected.pc);
+    check("s", cpu.stack_pointer as u16, expected.s as u16);
+    check("a", cpu.register_a as u16, expected.a as u16);
+    check("x", cpu.register_x as u16, expected.x as u16);
+    check("y", cpu.register_y as u16, expected.y as u16);
+    // B 与 U 不是实际存在的位, 只在入栈时体现
+    check("p", (cpu.status.bits() & 0xcf) as u16, (expected.p & 0xcf) as u16);
+    for &(addr, data) in expected.ram.iter() {
+        check(&format!("[{:04x}]", addr), cpu.bus().memory()[addr as usize] as u16, data as u16);
+    }
+    check("cycles", cpu.bus().cycles() as u16, expected_accesses.len() as u16);
+    if cpu.bus().accesses() != expected_accesses.as_slice() {
+        errors.push(format!("bus accesses {:?}, expected {:?}", cpu.bus().accesses(), expected_accesses));
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("{}: {}", name, errors.join(", ")))
+    }
+}
+
+/// 运行一个测试文件, 返回用例总数与失败信息
+fn run_file(path: &Path, decimal_mode: bool) -> Result<(usize, Vec<String>), String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let cases: Value = serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let cases = cases.as_array().ok_or(format!("{}: not an array", path.display()))?;
+    let failures = cases
+        .iter()
+        .filter_map(|case| run_case(case, decimal_mode).err())
+        .collect();
+    Ok((cases.len(), failures))
+}
+
+#[test]
+#[ignore = "needs the SingleStepTests JSON files in CNES_SINGLE_STEP_TESTS"]
+fn single_step_tests() {
+    let dir = std::env::var("CNES_SINGLE_STEP_TESTS").expect("CNES_SINGLE_STEP_TESTS is not set");
+    let decimal_mode = std::env::var("CNES_SINGLE_STEP_DECIMAL").is_ok_and(|v| v == "1");
+
+    let mut paths = fs::read_dir(&dir)
+        .expect("failed to read CNES_SINGLE_STEP_TESTS")
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    let mut failed_files = vec![];
+    for path in paths {
+        let code = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| u8::from_str_radix(stem, 16).ok());
+        match code {
+            Some(code) if !JAM_OPCODES.contains(&code) => {}
+            _ => continue,
+        }
+        let (total, failures) = run_file(&path, decimal_mode).unwrap();
+        if !failures.is_empty() {
+            eprintln!("{}: {}/{} failed, first: {}", path.display(), failures.len(), total, failures[0]);
+            failed_files.push(path.display().to_string());
+        }
+    }
+    assert!(failed_files.is_empty(), "failed: {:?}", failed_files);
+}
+
+/// 与测试集格式相同的几个手写用例, 覆盖跨页空读, 读-改-写的空写, JSR 与跨页分支
+const SAMPLE_CASES: &str = r#"[
+    {
+        "name": "bd ff 10",
+        "initial": { "pc": 512, "s": 253, "a": 0, "x": 1, "y": 0, "p": 36,
+            "ram": [[512, 189], [513, 255], [514, 16], [4096, 7], [4352, 66]] },
+        "final": { "pc": 515, "s": 253, "a": 66, "x": 1, "y": 0, "p": 36,
+            "ram": [[512, 189], [513, 255], [514, 16], [4096, 7], [4352, 66]] },
+        "cycles": [[512, 189, "read"], [513, 255, "read"], [514, 16, "read"], [4096, 7, "read"], [4352, 66, "read"]]
+    },
+    {
+        "name": "f6 80",
+        "initial": { "pc": 768, "s": 253, "a": 0, "x": 144, "y": 0, "p": 36,
+            "ram": [[768, 246], [769, 128], [128, 3], [16, 127]] },
+        "final": { "pc": 770, "s": 253, "a": 0, "x": 144, "y": 0, "p": 164,
+            "ram": [[768, 246], [769, 128], [128, 3], [16, 128]] },
+        "cycles": [[768, 246, "read"], [769, 128, "read"], [128, 3, "read"], [16, 127, "read"],
+            [16, 127, "write"], [16, 128, "write"]]
+    },
+    {
+        "name": "20 34 12",
+        "initial": { "pc": 1024, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36,
+            "ram": [[1024, 32], [1025, 52], [1026, 18], [509, 0], [508, 0]] },
+        "final": { "pc": 4660, "s": 251, "a": 0, "x": 0, "y": 0, "p": 36,
+            "ram": [[1024, 32], [1025, 52], [1026, 18], [509, 4], [508, 2]] },
+        "cycles": [[1024, 32, "read"], [1025, 52, "read"], [509, 0, "read"], [509, 4, "write"],
+            [508, 2, "write"], [1026, 18, "read"]]
+    },
+    {
+        "name": "d0 20",
+        "initial": { "pc": 1520, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36,
+            "ram": [[1520, 208], [1521, 32], [1522, 234], [1298, 0]] },
+        "final": { "pc": 1554, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36,
+            "ram": [[1520, 208], [1521, 32], [1522, 234], [1298, 0]] },
+        "cycles": [[1520, 208, "read"], [1521, 32, "read"], [1522, 234, "read"], [1298, 0, "read"]]
+    }
+]"#;
+
+#[test]
+fn test_sample_cases() {
+    let cases: Value = serde_json::from_str(SAMPLE_CASES).unwrap();
+    for case in cases.as_array().unwrap() {
+        run_case(case, false).unwrap();
+    }
+
+    // 不一致时能够发现
+    let mut case = cases[0].clone();
+    case["final"]["a"] = Value::from(0);
+    case["cycles"].as_array_mut().unwrap().remove(3); // 没有跨页空读
+    let error = run_case(&case, false).unwrap_err();
+    assert!(error.contains("a = 0042, expected 0000"));
+    assert!(error.contains("cycles = 0005, expected 0004"));
+    assert!(error.contains("bus accesses"));
+}
+
+#[test]
+fn test_cycles_match_opcode_table() {
+    // 内存全为 0 时既不跨页, 分支也不跨页, 而 C, Z, N, V 为 0 时 BCC, BNE, BPL, BVC 会跳转
+    for opcode in CPU_OPCODES.iter().filter(|op| !JAM_OPCODES.contains(&op.code)) {
+        let mut bus = FlatBus::new();
+        bus.load(0x0200, &[opcode.code]);
+        let mut cpu = Cpu::with_bus(bus);
+        cpu.program_counter = 0x0200;
+        cpu.run_next_instruction();
+
+        let taken = matches!(opcode.code, 0x90 | 0xd0 | 0x10 | 0x50);
+        assert_eq!(
+            cpu.bus().cycles(),
+            opcode.cycles as u64 + taken as u64,
+            "OpCode {:02x} {}", opcode.code, opcode.mnemonic
+        );
+    }
+}
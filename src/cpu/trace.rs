@@ -1,119 +1,42 @@
-use std::collections::HashMap;
+use super::{disassemble, AddressingMode, Cpu, CpuBus};
 
-use super::{opcodes, Cpu, Mem, AddressingMode};
-
-/// 得到 cpu 下一条要执行的指令信息, 在该指令执行前调用
+/// 得到 cpu 下一条要执行的指令信息, 在该指令执行前调用, 只通过 peek 读取内存
 #[cfg(test)]
-fn trace(cpu: &mut Cpu) -> String {
-    let ref opcodes: HashMap<u8, &'static opcodes::OpCode> = *opcodes::OPCODES_MAP;
-    let code = cpu.mem_read(cpu.program_counter);
-    let opcode = opcodes.get(&code).expect(&format!("OpCode {:02x} is not recognized", code));
+fn trace<B: CpuBus>(cpu: &Cpu<B>) -> String {
+    format_trace(cpu, true)
+}
 
-    let mut hex_dump = vec![code];
+// 不显示 mem_val
+/// a trace function, returns information of next instruction to be executed
+///
+/// 只通过 peek 读取内存, 不会改变模拟状态
+pub fn trace_readonly<B: CpuBus>(cpu: &Cpu<B>) -> String {
+    format_trace(cpu, false)
+}
 
-    let (mem_addr, mem_val) = match opcode.mode {
-        AddressingMode::Immediate | AddressingMode::NoneAddressing => (0, 0),
-        _ => {
-            let addr = cpu.get_absolute_address(&opcode.mode, cpu.program_counter + 1);
-            (addr, cpu.mem_read(addr))
-        }
+/// 以 nestest.log 的格式输出 `disassemble` 得到的指令与寄存器, show_value 为是否显示操作数地址处的值
+fn format_trace<B: CpuBus>(cpu: &Cpu<B>, show_value: bool) -> String {
+    let pc = cpu.program_counter;
+    let instruction = disassemble(&cpu.bus, pc);
+    let operand = instruction.operand.unwrap_or(0);
+    let asm_operand_and_addr_val = match (instruction.mode, instruction.target) {
+        // 分支, JMP 与 JSR
+        (AddressingMode::Relative | AddressingMode::Absolute, Some(target)) => format!("${:04x}", target),
+        (AddressingMode::Indirect, Some(target)) => format!("(${:04x}) = {:04x}", operand, target),
+        (mode, _) => format_operand(cpu, mode, operand, show_value),
     };
-    let asm_operand_and_addr_val = match opcode.len {
-        1 => match opcode.code {
-            0x0a | 0x4a | 0x2a | 0x6a => format!("A "), // ASL, LSR, ROL, ROR, Accumulator mode
-            _ => format!(""),
-        }
-        2 => {
-            let operand = cpu.mem_read(cpu.program_counter + 1);
-            hex_dump.push(operand);
-
-            match opcode.mode {
-                AddressingMode::Immediate => format!("#${:02x}", operand),
-                AddressingMode::ZeroPage => format!("${:02x} = {:02x}", mem_addr, mem_val),
-                AddressingMode::ZeroPage_X => format!(
-                    "${:02x},X @ {:02x} = {:02x}",
-                    operand, mem_addr, mem_val
-                ),
-                AddressingMode::ZeroPage_Y => format!(
-                    "${:02x},Y @ {:02x} = {:02x}",
-                    operand, mem_addr, mem_val
-                ),
-                AddressingMode::Indirect_X => format!(
-                    "(${:02x},X) @ {:02x} = {:04x} = {:02x}",
-                    operand,
-                    operand.wrapping_add(cpu.register_x),
-                    mem_addr,
-                    mem_val
-                ),
-                AddressingMode::Indirect_Y => format!(
-                    "(${:02x}),Y = {:04x} @ {:04x} = {:02x}",
-                    operand,
-                    mem_addr.wrapping_sub(cpu.register_y as u16),
-                    mem_addr,
-                    mem_val
-                ),
-                AddressingMode::NoneAddressing => { // branch 指令
-                    let addr = cpu.program_counter
-                        .wrapping_add(2)
-                        .wrapping_add((operand as i8) as u16);
-                    format!("${:04x}", addr)
-                }
-                _ => panic!(
-                    "unexpected addressing mode {:?} has ops-len 2. code {:02x}",
-                    opcode.mode, opcode.code
-                ),
-            }
-        }
-        3 => {
-            let lo = cpu.mem_read(cpu.program_counter + 1);
-            let hi = cpu.mem_read(cpu.program_counter + 2);
-            hex_dump.push(lo);
-            hex_dump.push(hi);
-
-            let operand = cpu.mem_read_u16(cpu.program_counter + 1);
-
-            match opcode.mode {
-                AddressingMode::Absolute => format!("${:04x} = {:02x}", mem_addr, mem_val),
-                AddressingMode::Absolute_X => format!(
-                    "${:04x},X @ {:04x} = {:02x}",
-                    operand, mem_addr, mem_val
-                ),
-                AddressingMode::Absolute_Y => format!(
-                    "${:04x},Y @ {:04x} = {:02x}",
-                    operand, mem_addr, mem_val
-                ),
-                AddressingMode::NoneAddressing => { // jump 指令
-                    if opcode.code == 0x6c { // jump indirect
-                        let target = if operand & 0x00ff == 0x00ff {
-                            let lo = cpu.mem_read(operand) as u16;
-                            let hi = cpu.mem_read(operand & 0xff00) as u16;
-                            (hi << 8) | lo
-                        } else {
-                            cpu.mem_read_u16(operand)
-                        };
-                        format!("(${:04x}) = {:04x}", operand, target)
-                    } else { // jmp absolute or jsr(absolute)
-                        format!("${:04x}", operand)
-                    }
-                }
-                _ => panic!(
-                    "unexpected addressing mode {:?} has ops-len 3. code {:02x}",
-                    opcode.mode, opcode.code
-                )
-            }
-        }
-        _ => { // 目前暂无
-            format!("")
-        }
-    };
-    let hex_str = hex_dump
+    let hex_str = instruction.bytes
         .iter()
         .map(|num| format!("{:02x}", num))
         .collect::<Vec<String>>()
         .join(" ");
+    let mnemonic = match instruction.is_unofficial {
+        true => format!("*{}", instruction.mnemonic),
+        false => instruction.mnemonic.to_string(),
+    };
     let asm_str = format!(
         "{:04x}  {:8} {: >4} {}",
-        cpu.program_counter, hex_str, opcode.mnemonic, asm_operand_and_addr_val
+        pc, hex_str, mnemonic, asm_operand_and_addr_val
     ).trim().to_string();
 
     format!(
@@ -122,132 +45,55 @@ fn trace(cpu: &mut Cpu) -> String {
     ).to_ascii_uppercase()
 }
 
-// 不显示 mem_val (可以避免读 PPU 寄存器导致状态改变)
-/// a trace function, returns information of next instruction to be executed
-pub fn trace_readonly(cpu: &mut Cpu) -> String {
-    let ref opcodes: HashMap<u8, &'static opcodes::OpCode> = *opcodes::OPCODES_MAP;
-    let code = cpu.mem_read(cpu.program_counter);
-    let opcode = opcodes.get(&code).expect(&format!("OpCode {:02x} is not recognized", code));
-
-    let mut hex_dump = vec![code];
-
-    let mem_addr = match opcode.mode {
-        AddressingMode::Immediate | AddressingMode::NoneAddressing => 0,
-        _ => cpu.get_absolute_address(&opcode.mode, cpu.program_counter + 1),
+/// 跳转目标以外的操作数, 访存的寻址方式同时给出实际地址
+fn format_operand<B: CpuBus>(cpu: &Cpu<B>, mode: AddressingMode, operand: u16, show_value: bool) -> String {
+    let mem_addr = match mode {
+        AddressingMode::Implied | AddressingMode::Accumulator | AddressingMode::Immediate
+        | AddressingMode::Relative | AddressingMode::Indirect => 0,
+        _ => cpu.get_absolute_address(&mode, cpu.program_counter.wrapping_add(1)),
     };
-    let asm_operand_and_addr_val = match opcode.len {
-        1 => match opcode.code {
-            0x0a | 0x4a | 0x2a | 0x6a => format!("A "), // ASL, LSR, ROL, ROR, Accumulator mode
-            _ => format!(""),
-        }
-        2 => {
-            let operand = cpu.mem_read(cpu.program_counter + 1);
-            hex_dump.push(operand);
-
-            match opcode.mode {
-                AddressingMode::Immediate => format!("#${:02x}", operand),
-                AddressingMode::ZeroPage => format!("${:02x}", mem_addr),
-                AddressingMode::ZeroPage_X => format!(
-                    "${:02x},X @ {:02x}",
-                    operand, mem_addr
-                ),
-                AddressingMode::ZeroPage_Y => format!(
-                    "${:02x},Y @ {:02x}",
-                    operand, mem_addr
-                ),
-                AddressingMode::Indirect_X => format!(
-                    "(${:02x},X) @ {:02x} = {:04x}",
-                    operand,
-                    operand.wrapping_add(cpu.register_x),
-                    mem_addr
-                ),
-                AddressingMode::Indirect_Y => format!(
-                    "(${:02x}),Y = {:04x} @ {:04x}",
-                    operand,
-                    mem_addr.wrapping_sub(cpu.register_y as u16),
-                    mem_addr
-                ),
-                AddressingMode::NoneAddressing => { // branch 指令
-                    let addr = cpu.program_counter
-                        .wrapping_add(2)
-                        .wrapping_add((operand as i8) as u16);
-                    format!("${:04x}", addr)
-                }
-                _ => panic!(
-                    "unexpected addressing mode {:?} has ops-len 2. code {:02x}",
-                    opcode.mode, opcode.code
-                ),
-            }
-        }
-        3 => {
-            let lo = cpu.mem_read(cpu.program_counter + 1);
-            let hi = cpu.mem_read(cpu.program_counter + 2);
-            hex_dump.push(lo);
-            hex_dump.push(hi);
-
-            let operand = cpu.mem_read_u16(cpu.program_counter + 1);
-
-            match opcode.mode {
-                AddressingMode::Absolute => format!("${:04x}", mem_addr),
-                AddressingMode::Absolute_X => format!(
-                    "${:04x},X @ {:04x}",
-                    operand, mem_addr
-                ),
-                AddressingMode::Absolute_Y => format!(
-                    "${:04x},Y @ {:04x}",
-                    operand, mem_addr
-                ),
-                AddressingMode::NoneAddressing => { // jump 指令
-                    if opcode.code == 0x6c { // jump indirect
-                        let target = if operand & 0x00ff == 0x00ff {
-                            let lo = cpu.mem_read(operand) as u16;
-                            let hi = cpu.mem_read(operand & 0xff00) as u16;
-                            (hi << 8) | lo
-                        } else {
-                            cpu.mem_read_u16(operand)
-                        };
-                        format!("(${:04x}) = {:04x}", operand, target)
-                    } else { // jmp absolute or jsr(absolute)
-                        format!("${:04x}", operand)
-                    }
-                }
-                _ => panic!(
-                    "unexpected addressing mode {:?} has ops-len 3. code {:02x}",
-                    opcode.mode, opcode.code
-                )
-            }
-        }
-        _ => { // 目前暂无
-            format!("")
-        }
+    let value = match show_value {
+        true => format!(" = {:02x}", cpu.bus.peek(mem_addr)),
+        false => String::new(),
     };
-    let hex_str = hex_dump
-        .iter()
-        .map(|num| format!("{:02x}", num))
-        .collect::<Vec<String>>()
-        .join(" ");
-    let asm_str = format!(
-        "{:04x}  {:8} {: >4} {}",
-        cpu.program_counter, hex_str, opcode.mnemonic, asm_operand_and_addr_val
-    ).trim().to_string();
-
-    format!(
-        "{:47} A:{:02x} X:{:02x} Y:{:02x} P:{:02x} SP:{:02x}",
-        asm_str, cpu.register_a, cpu.register_x, cpu.register_y, cpu.status, cpu.stack_pointer
-    ).to_ascii_uppercase()
+    match mode {
+        AddressingMode::Implied | AddressingMode::Relative | AddressingMode::Indirect => String::new(),
+        AddressingMode::Accumulator => "A".to_string(),
+        AddressingMode::Immediate => format!("#${:02x}", operand),
+        AddressingMode::ZeroPage => format!("${:02x}{}", mem_addr, value),
+        AddressingMode::ZeroPage_X => format!("${:02x},X @ {:02x}{}", operand, mem_addr, value),
+        AddressingMode::ZeroPage_Y => format!("${:02x},Y @ {:02x}{}", operand, mem_addr, value),
+        AddressingMode::Indirect_X => format!(
+            "(${:02x},X) @ {:02x} = {:04x}{}",
+            operand,
+            (operand as u8).wrapping_add(cpu.register_x),
+            mem_addr,
+            value
+        ),
+        AddressingMode::Indirect_Y => format!(
+            "(${:02x}),Y = {:04x} @ {:04x}{}",
+            operand,
+            mem_addr.wrapping_sub(cpu.register_y as u16),
+            mem_addr,
+            value
+        ),
+        AddressingMode::Absolute => format!("${:04x}{}", mem_addr, value),
+        AddressingMode::Absolute_X => format!("${:04x},X @ {:04x}{}", operand, mem_addr, value),
+        AddressingMode::Absolute_Y => format!("${:04x},Y @ {:04x}{}", operand, mem_addr, value),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::cartridge::tests::test_rom;
+    use crate::{cartridge::tests::{test_rom, test_rom_with_2_bank_prg}, common::Mem, cpu::Peek};
 
     impl Cpu {
         pub fn run_with_trace_until_brk<F>(&mut self, mut trace: F)
         where
             F: FnMut(&mut Cpu)
         {
-            while !self.brk_flag {
+            while self.mem_read(self.program_counter) != 0x00 {
                 self.run_next_instruction_with_trace(|cpu| trace(cpu));
             }
         }
@@ -292,8 +138,8 @@ mod tests {
         cpu.mem_write(101, 0x33);
 
         //data
-        cpu.mem_write(0x33, 00);
-        cpu.mem_write(0x34, 04);
+        cpu.mem_write(0x33, 0x00);
+        cpu.mem_write(0x34, 0x04);
 
         //target cell
         cpu.mem_write(0x400, 0xAA);
@@ -309,4 +155,50 @@ mod tests {
             result[0]
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_trace_has_no_side_effects() {
+        let mut cpu = Cpu::new(test_rom_with_2_bank_prg(vec![
+            0x4c, 0x00, 0x80, // JMP $8000
+            0xad, 0x02, 0x20, // LDA $2002
+        ]));
+        cpu.reset();
+        cpu.run_next_frame().unwrap(); // 进入 vblank
+        cpu.program_counter = 0x8003;
+
+        assert!(trace(&cpu).starts_with("8003  AD 02 20  LDA $2002 = A0"));
+        assert!(trace_readonly(&cpu).starts_with("8003  AD 02 20  LDA $2002"));
+        assert_eq!(cpu.bus.peek(0x2002) & 0x80, 0x80); // vblank 标志未被清除
+
+        cpu.run_next_instruction();
+        assert_eq!(cpu.register_a & 0x80, 0x80);
+        assert_eq!(cpu.bus.peek(0x2002) & 0x80, 0);
+    }
+
+    #[test]
+    fn test_format_jumps_and_unofficial() {
+        let mut cpu = Cpu::new(test_rom_with_2_bank_prg(vec![
+            0x20, 0x10, 0x80, // 8000 JSR $8010
+            0x6c, 0xff, 0x02, // 8003 JMP ($02FF)
+            0xd0, 0xfe, // 8006 BNE $8006
+            0x04, 0x10, // 8008 *NOP $10
+            0x0a, // 800A ASL A
+        ]));
+        cpu.reset();
+        cpu.mem_write(0x02ff, 0x34);
+        cpu.mem_write(0x0200, 0x12);
+        cpu.mem_write(0x0010, 0x55);
+        let mut lines = vec![];
+        for pc in [0x8000, 0x8003, 0x8006, 0x8008, 0x800a] {
+            cpu.program_counter = pc;
+            lines.push(trace(&cpu)[..47].trim_end().to_string());
+        }
+        assert_eq!(lines, [
+            "8000  20 10 80  JSR $8010",
+            "8003  6C FF 02  JMP ($02FF) = 1234",
+            "8006  D0 FE     BNE $8006",
+            "8008  04 10    *NOP $10 = 55",
+            "800A  0A        ASL A",
+        ]);
+    }
+}
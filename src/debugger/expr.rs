@@ -0,0 +1,457 @@
+//! 断点条件使用的表达式
+//!
+//! 语法与 C 相近, 例如 `A == $3F && [$00FE] > 2 && scanline < 20`:
+//! - 数字: `20`(十进制), `$3F` 或 `0x3F`(十六进制), `%1010`(二进制)
+//! - 变量(不区分大小写): `A`, `X`, `Y`, `P`, `SP`, `PC`, `scanline`, `cycle`(PPU 周期), `frame`,
+//!   `value` 与 `address`(触发断点的访问的值与地址, 执行断点时为操作码与 PC)
+//! - 符号: 使用 [`Expr::parse_with_symbols`] 解析时, 不是变量的名字按符号解析为地址
+//! - 内存: `[addr]` 读取一个字节, `{addr}` 读取一个小端序的字
+//! - 运算符, 优先级从低到高: `||`, `&&`, `|`, `^`, `&`, `== !=`, `< <= > >=`, `<< >>`, `+ -`, `* / %`,
+//!   以及一元的 `! - ~`
+//!
+//! 比较与逻辑运算的结果为 1 或 0, 非 0 即为真; 除以 0 的结果为 0. 读取内存不会产生副作用.
+
+use std::{fmt, str::FromStr};
+
+use crate::{Cpu, cpu::{BusAccess, Peek}};
+
+/// 表达式解析错误
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExprError {
+    /// 出错位置(字节偏移)
+    pub pos: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.pos)
+    }
+}
+
+impl std::error::Error for ExprError {}
+
+/// 表达式中的变量
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variable {
+    A,
+    X,
+    Y,
+    P,
+    Sp,
+    Pc,
+    Scanline,
+    Cycle,
+    Frame,
+    Value,
+    Address,
+}
+
+impl Variable {
+    fn from_name(name: &str) -> Option<Self> {
+        let variable = match name.to_ascii_lowercase().as_str() {
+            "a" => Variable::A,
+            "x" => Variable::X,
+            "y" => Variable::Y,
+            "p" => Variable::P,
+            "sp" => Variable::Sp,
+            "pc" => Variable::Pc,
+            "scanline" => Variable::Scanline,
+            "cycle" => Variable::Cycle,
+            "frame" => Variable::Frame,
+            "value" => Variable::Value,
+            "address" => Variable::Address,
+            _ => return None,
+        };
+        Some(variable)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Not,
+    Neg,
+    BitNot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+/// 二元运算符按优先级从低到高分组
+const BINARY_OPS: [&[(&str, BinaryOp)]; 10] = [
+    &[("||", BinaryOp::Or)],
+    &[("&&", BinaryOp::And)],
+    &[("|", BinaryOp::BitOr)],
+    &[("^", BinaryOp::BitXor)],
+    &[("&", BinaryOp::BitAnd)],
+    &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne)],
+    &[("<=", BinaryOp::Le), (">=", BinaryOp::Ge), ("<", BinaryOp::Lt), (">", BinaryOp::Gt)],
+    &[("<<", BinaryOp::Shl), (">>", BinaryOp::Shr)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+    &[("*", BinaryOp::Mul), ("/", BinaryOp::Div), ("%", BinaryOp::Rem)],
+];
+
+/// 解析后的表达式
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Number(i64),
+    Variable(Variable),
+    /// `[addr]`
+    Byte(Box<Expr>),
+    /// `{addr}`
+    Word(Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+/// 求值时可见的状态
+pub struct EvalContext<'a> {
+    pub cpu: &'a Cpu,
+    /// 触发断点的访问, 执行断点时为 None
+    pub access: Option<BusAccess>,
+}
+
+impl EvalContext<'_> {
+    fn variable(&self, variable: Variable) -> i64 {
+        let registers = self.cpu.registers();
+        let bus = self.cpu.bus();
+        match variable {
+            Variable::A => registers.a as i64,
+            Variable::X => registers.x as i64,
+            Variable::Y => registers.y as i64,
+            Variable::P => registers.p as i64,
+            Variable::Sp => registers.sp as i64,
+            Variable::Pc => registers.pc as i64,
+            Variable::Scanline => bus.scanline() as i64,
+            Variable::Cycle => bus.ppu_cycle() as i64,
+            Variable::Frame => bus.frame_count() as i64,
+            Variable::Value => match self.access {
+                Some(access) => access.data as i64,
+                None => bus.peek(registers.pc) as i64,
+            },
+            Variable::Address => match self.access {
+                Some(access) => access.addr as i64,
+                None => registers.pc as i64,
+            },
+        }
+    }
+}
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Self, ExprError> {
+        Self::parse_with_symbols(source, &|_| None)
+    }
+
+    /// 解析表达式, 不是变量的名字由 resolve 解析为数值(通常是符号的地址)
+    pub fn parse_with_symbols(source: &str, resolve: &dyn Fn(&str) -> Option<i64>) -> Result<Self, ExprError> {
+        let mut parser = Parser { source, pos: 0, resolve };
+        let expr = parser.expr(0)?;
+        parser.skip_whitespace();
+        if parser.pos < source.len() {
+            return Err(parser.error("unexpected character"));
+        }
+        Ok(expr)
+    }
+
+    pub fn eval(&self, ctx: &EvalContext) -> i64 {
+        match self {
+            Expr::Number(n) => *n,
+            Expr::Variable(variable) => ctx.variable(*variable),
+            Expr::Byte(addr) => ctx.cpu.bus().peek(addr.eval(ctx) as u16) as i64,
+            Expr::Word(addr) => {
+                let addr = addr.eval(ctx) as u16;
+                let lo = ctx.cpu.bus().peek(addr) as i64;
+                let hi = ctx.cpu.bus().peek(addr.wrapping_add(1)) as i64;
+                (hi << 8) | lo
+            }
+            Expr::Unary(op, operand) => {
+                let operand = operand.eval(ctx);
+                match op {
+                    UnaryOp::Not => (operand == 0) as i64,
+                    UnaryOp::Neg => operand.wrapping_neg(),
+                    UnaryOp::BitNot => !operand,
+                }
+            }
+            Expr::Binary(BinaryOp::Or, lhs, rhs) => (lhs.eval(ctx) != 0 || rhs.eval(ctx) != 0) as i64,
+            Expr::Binary(BinaryOp::And, lhs, rhs) => (lhs.eval(ctx) != 0 && rhs.eval(ctx) != 0) as i64,
+            Expr::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.eval(ctx), rhs.eval(ctx));
+                match op {
+                    BinaryOp::BitOr => lhs | rhs,
+                    BinaryOp::BitXor => lhs ^ rhs,
+                    BinaryOp::BitAnd => lhs & rhs,
+                    BinaryOp::Eq => (lhs == rhs) as i64,
+                    BinaryOp::Ne => (lhs != rhs) as i64,
+                    BinaryOp::Lt => (lhs < rhs) as i64,
+                    BinaryOp::Le => (lhs <= rhs) as i64,
+                    BinaryOp::Gt => (lhs > rhs) as i64,
+                    BinaryOp::Ge => (lhs >= rhs) as i64,
+                    BinaryOp::Shl => lhs.wrapping_shl(rhs as u32),
+                    BinaryOp::Shr => lhs.wrapping_shr(rhs as u32),
+                    BinaryOp::Add => lhs.wrapping_add(rhs),
+                    BinaryOp::Sub => lhs.wrapping_sub(rhs),
+                    BinaryOp::Mul => lhs.wrapping_mul(rhs),
+                    BinaryOp::Div => lhs.checked_div(rhs).unwrap_or(0),
+                    BinaryOp::Rem => lhs.checked_rem(rhs).unwrap_or(0),
+                    BinaryOp::Or | BinaryOp::And => unreachable!(),
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = ExprError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Expr::parse(s)
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    pos: usize,
+    resolve: &'a dyn Fn(&str) -> Option<i64>,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> ExprError {
+        ExprError {
+            pos: self.pos,
+            message: message.to_string(),
+        }
+    }
+
+    fn rest(&self) -> &str {
+        &self.source[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), ExprError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", token)))
+        }
+    }
+
+    /// 解析优先级不低于 level 的二元运算
+    fn expr(&mut self, level: usize) -> Result<Expr, ExprError> {
+        if level == BINARY_OPS.len() {
+            return self.unary();
+        }
+        let mut lhs = self.expr(level + 1)?;
+        'outer: loop {
+            self.skip_whitespace();
+            for &(token, op) in BINARY_OPS[level] {
+                // `|` 与 `&` 不能是 `||` 与 `&&` 的前缀, `<` 与 `>` 不能是 `<<` 与 `>>` 的前缀
+                let longer = matches!(op, BinaryOp::BitOr | BinaryOp::BitAnd | BinaryOp::Lt | BinaryOp::Gt)
+                    && self.rest().get(token.len()..).is_some_and(|rest| rest.starts_with(token));
+                if !longer && self.eat(token) {
+                    let rhs = self.expr(level + 1)?;
+                    lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+                    continue 'outer;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, ExprError> {
+        let op = if self.eat("!") {
+            UnaryOp::Not
+        } else if self.eat("-") {
+            UnaryOp::Neg
+        } else if self.eat("~") {
+            UnaryOp::BitNot
+        } else {
+            return self.primary();
+        };
+        Ok(Expr::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Expr, ExprError> {
+        if self.eat("(") {
+            let expr = self.expr(0)?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+        if self.eat("[") {
+            let expr = self.expr(0)?;
+            self.expect("]")?;
+            return Ok(Expr::Byte(Box::new(expr)));
+        }
+        if self.eat("{") {
+            let expr = self.expr(0)?;
+            self.expect("}")?;
+            return Ok(Expr::Word(Box::new(expr)));
+        }
+
+        self.skip_whitespace();
+        let start = self.pos;
+        let (radix, digits_start) = if self.eat("$") || self.eat("0x") || self.eat("0X") {
+            (16, self.pos)
+        } else if self.eat("%") {
+            (2, self.pos)
+        } else {
+            (10, self.pos)
+        };
+        let len = self.rest()
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(self.rest().len());
+        let word = &self.source[digits_start..digits_start + len];
+        if word.is_empty() {
+            self.pos = start;
+            return Err(self.error("expected an expression"));
+        }
+
+        if radix == 10 && !word.starts_with(|c: char| c.is_ascii_digit()) {
+            let expr = match (Variable::from_name(word), (self.resolve)(word)) {
+                (Some(variable), _) => Expr::Variable(variable),
+                (None, Some(n)) => Expr::Number(n),
+                (None, None) => return Err(self.error(&format!("unknown variable '{}'", word))),
+            };
+            self.pos += len;
+            return Ok(expr);
+        }
+        let n = i64::from_str_radix(word, radix).map_err(|_| {
+            ExprError { pos: start, message: format!("invalid number '{}'", &self.source[start..digits_start + len]) }
+        })?;
+        self.pos += len;
+        Ok(Expr::Number(n))
+    }
+}
+
+/// 日志断点的消息模板, 其中 `{{...}}` 内为表达式, 输出时替换为十六进制的值, 如 `A={{A}} at {{PC}}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogTemplate {
+    parts: Vec<LogPart>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum LogPart {
+    Text(String),
+    Expr(Expr),
+}
+
+impl LogTemplate {
+    pub fn parse(source: &str) -> Result<Self, ExprError> {
+        let mut parts = vec![];
+        let mut pos = 0;
+        while let Some(start) = source[pos..].find("{{") {
+            let start = pos + start;
+            if start > pos {
+                parts.push(LogPart::Text(source[pos..start].to_string()));
+            }
+            let end = source[start..].find("}}").map(|end| start + end).ok_or(ExprError {
+                pos: start,
+                message: "unclosed '{{'".to_string(),
+            })?;
+            let expr = Expr::parse(&source[start + 2..end]).map_err(|e| ExprError {
+                pos: start + 2 + e.pos,
+                message: e.message,
+            })?;
+            parts.push(LogPart::Expr(expr));
+            pos = end + 2;
+        }
+        if pos < source.len() {
+            parts.push(LogPart::Text(source[pos..].to_string()));
+        }
+        Ok(LogTemplate { parts })
+    }
+
+    pub fn format(&self, ctx: &EvalContext) -> String {
+        let mut message = String::new();
+        for part in self.parts.iter() {
+            match part {
+                LogPart::Text(text) => message.push_str(text),
+                LogPart::Expr(expr) => message.push_str(&format!("${:02X}", expr.eval(ctx))),
+            }
+        }
+        message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cartridge::tests::test_rom_with_2_bank_prg, cpu::CpuBus};
+
+    fn eval(source: &str, cpu: &Cpu) -> i64 {
+        let ctx = EvalContext { cpu, access: None };
+        Expr::parse(source).unwrap().eval(&ctx)
+    }
+
+    #[test]
+    fn test_eval() {
+        let mut cpu = Cpu::new(test_rom_with_2_bank_prg(vec![0xa9, 0x3f])); // LDA #$3F
+        cpu.reset();
+        cpu.run_next_instruction();
+        cpu.bus_mut().write(0x00fe, 3);
+        cpu.bus_mut().write(0x00ff, 0x12);
+
+        assert_eq!(eval("A == $3F && [$00FE] > 2 && scanline < 20", &cpu), 1);
+        assert_eq!(eval("a == 0x3f && [$fe] > 3", &cpu), 0);
+        assert_eq!(eval("{$FE}", &cpu), 0x1203);
+        assert_eq!(eval("1 + 2 * 3 - -1", &cpu), 8);
+        assert_eq!(eval("(1 + 2) * 3 % 5", &cpu), 4);
+        assert_eq!(eval("%1010 | 1 << 4 & ~0", &cpu), 0x1a);
+        assert_eq!(eval("!(PC != $8002) || 1 / 0", &cpu), 1);
+        assert_eq!(eval("5 / 0 + frame", &cpu), 0);
+        assert_eq!(eval("1 < 2 == 1 >= 1", &cpu), 1);
+        assert_eq!(eval("value == 1 && address == pc", &cpu), 1); // 执行断点时为 PC 处的操作码
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Expr::parse("A == ").unwrap_err().pos, 5);
+        assert_eq!(Expr::parse("A == Q").unwrap_err().message, "unknown variable 'Q'");
+        assert_eq!(Expr::parse("[$10").unwrap_err().message, "expected ']'");
+        assert_eq!(Expr::parse("$1G").unwrap_err().message, "invalid number '$1G'");
+        assert_eq!(Expr::parse("1 2").unwrap_err().pos, 2);
+        assert_eq!(LogTemplate::parse("x={{X").unwrap_err().pos, 2);
+        assert_eq!(LogTemplate::parse("x={{X +}}").unwrap_err().pos, 7);
+    }
+
+    #[test]
+    fn test_log_template() {
+        let mut cpu = Cpu::new(test_rom_with_2_bank_prg(vec![0xa9, 0x3f]));
+        cpu.reset();
+        cpu.run_next_instruction();
+        let ctx = EvalContext { cpu: &cpu, access: None };
+        assert_eq!(LogTemplate::parse("A={{A}} pc={{PC}}!").unwrap().format(&ctx), "A=$3F pc=$8002!");
+    }
+}
@@ -0,0 +1,452 @@
+//! GDB 远程串行协议(RSP)调试服务器, 只监听本机回环地址
+//!
+//! 支持的包:
+//! - `?`, `c`, `s`: 查询停止原因, 继续运行, 单步; 继续运行时可以发送 `0x03` 中断
+//! - `g`, `G`, `p`, `P`: 读写寄存器, 依次为 A, X, Y, P, SP(各 1 字节)与 PC(2 字节, 小端序)
+//! - `m`, `M`: 读写内存, 读取不会产生副作用, 写入经过总线
+//! - `Z0`/`z0` 执行断点, `Z2`/`z2` 写断点, `Z3`/`z3` 读断点, `Z4`/`z4` 读写断点
+//! - `D`, `k`: 断开连接
+//!
+//! 停止时回复 `S05`, 读写断点回复 `T05watch:addr;`, `T05rwatch:addr;`, CPU 停机回复 `S04`,
+//! 被 `0x03` 中断时回复 `S02`.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, Read, Write},
+    net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+};
+
+use crate::{
+    cpu::{AccessKind, CpuBus, Peek, Registers},
+    debugger::{BreakOn, BreakTarget, Breakpoint, BreakpointId, Debugger, StopReason},
+};
+
+/// GDB 调试服务器
+pub struct GdbServer {
+    listener: TcpListener,
+}
+
+impl GdbServer {
+    /// 在 127.0.0.1:port 上监听, port 为 0 时由系统分配
+    pub fn bind(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        Ok(GdbServer { listener })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// 等待一个连接并为其服务, 直到对方断开(`D`, `k` 或关闭连接)
+    pub fn accept_and_serve(&self, debugger: &mut Debugger) -> io::Result<()> {
+        let (stream, peer) = self.listener.accept()?;
+        log::info!("GDB client connected from {}", peer);
+        let mut session = Session {
+            conn: Connection { stream, pending: VecDeque::new() },
+            debugger,
+            breakpoints: HashMap::new(),
+            last_stop: "S05".to_string(),
+        };
+        let result = session.serve();
+        for (_, id) in session.breakpoints.drain() {
+            session.debugger.remove_breakpoint(id);
+        }
+        log::info!("GDB client disconnected");
+        result
+    }
+}
+
+struct Connection {
+    stream: TcpStream,
+    pending: VecDeque<u8>,
+}
+
+impl Connection {
+    /// 读取一个字节, 连接关闭时返回 None
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        if let Some(byte) = self.pending.pop_front() {
+            return Ok(Some(byte));
+        }
+        let mut buf = [0u8; 1024];
+        let n = self.stream.read(&mut buf)?;
+        if n == 0 {
+            return Ok(None);
+        }
+        self.pending.extend(&buf[1..n]);
+        Ok(Some(buf[0]))
+    }
+
+    /// 读取下一个包, 校验和正确时回复 `+`; 返回 None 表示连接已关闭, `"\x03"` 表示中断请求
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(0x03) => return Ok(Some("\x03".to_string())),
+                Some(b'$') => {}
+                Some(_) => continue, // `+`, `-` 以及其他杂散字节
+            }
+            let mut data = vec![];
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                }
+            }
+            let mut checksum = [0u8; 2];
+            for byte in checksum.iter_mut() {
+                *byte = self.read_byte()?.unwrap_or(0);
+            }
+            let expected = std::str::from_utf8(&checksum).ok().and_then(|s| u8::from_str_radix(s, 16).ok());
+            if expected != Some(checksum_of(&data)) {
+                self.stream.write_all(b"-")?;
+                continue;
+            }
+            self.stream.write_all(b"+")?;
+            return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+        }
+    }
+
+    fn write_packet(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.stream.write_all(packet.as_bytes())
+    }
+
+    /// 继续运行时检查是否收到了中断请求, 其他数据留待之后处理
+    fn interrupt_requested(&mut self) -> io::Result<bool> {
+        if self.pending.is_empty() {
+            self.stream.set_nonblocking(true)?;
+            let mut buf = [0u8; 1024];
+            let result = self.stream.read(&mut buf);
+            self.stream.set_nonblocking(false)?;
+            match result {
+                Ok(n) => self.pending.extend(&buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if self.pending.front() == Some(&0x03) {
+            self.pending.pop_front();
+            return Ok(true);
+        }
+        Ok(false)
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|byte| match byte {
+            [hi, lo] => Some(((*hi as char).to_digit(16)? << 4 | (*lo as char).to_digit(16)?) as u8),
+            _ => None,
+        })
+        .collect()
+}
+
+fn parse_u16(hex: &str) -> Option<u16> {
+    u16::from_str_radix(hex, 16).ok()
+}
+
+/// 寄存器编号依次为 A, X, Y, P, SP, PC
+fn registers_to_bytes(registers: &Registers) -> [u8; 7] {
+    let [pc_lo, pc_hi] = registers.pc.to_le_bytes();
+    [registers.a, registers.x, registers.y, registers.p, registers.sp, pc_lo, pc_hi]
+}
+
+fn registers_from_bytes(bytes: &[u8]) -> Registers {
+    Registers {
+        a: bytes[0],
+        x: bytes[1],
+        y: bytes[2],
+        p: bytes[3],
+        sp: bytes[4],
+        pc: u16::from_le_bytes([bytes[5], bytes[6]]),
+    }
+}
+
+struct Session<'a> {
+    conn: Connection,
+    debugger: &'a mut Debugger,
+    breakpoints: HashMap<(u8, u16, u16), BreakpointId>, // (类型, 地址, 长度) -> 断点编号
+    last_stop: String,
+}
+
+impl Session<'_> {
+    fn serve(&mut self) -> io::Result<()> {
+        while let Some(packet) = self.conn.read_packet()? {
+            let reply = match packet.as_bytes().first() {
+                Some(b'D') => {
+                    self.conn.write_packet("OK")?;
+                    return Ok(());
+                }
+                Some(b'k') => return Ok(()),
+                _ => self.handle(&packet)?,
+            };
+            self.conn.write_packet(&reply)?;
+        }
+        Ok(())
+    }
+
+    /// 处理一个包, 返回回复的内容
+    fn handle(&mut self, packet: &str) -> io::Result<String> {
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, |c| c.len_utf8()));
+        let reply = match command {
+            "\x03" => "S02".to_string(),
+            "?" => self.last_stop.clone(),
+            "c" => self.resume(false)?,
+            "s" => self.resume(true)?,
+            "g" => to_hex(&registers_to_bytes(&self.debugger.cpu().registers())),
+            "G" => self.write_registers(args),
+            "p" => self.read_register(args),
+            "P" => self.write_register(args),
+            "m" => self.read_memory(args),
+            "M" => self.write_memory(args),
+            "Z" => self.set_breakpoint(args, true),
+            "z" => self.set_breakpoint(args, false),
+            "q" if args.starts_with("Supported") => "PacketSize=1000".to_string(),
+            "q" if args == "Attached" => "1".to_string(),
+            "H" => "OK".to_string(),
+            _ => String::new(), // 不支持的包回复空包
+        };
+        Ok(reply)
+    }
+
+    fn resume(&mut self, step: bool) -> io::Result<String> {
+        let reason = if step {
+            Some(self.debugger.step_into())
+        } else {
+            loop {
+                match self.debugger.run_frame() {
+                    StopReason::FrameEnd => {
+                        if self.conn.interrupt_requested()? {
+                            break None;
+                        }
+                    }
+                    reason => break Some(reason),
+                }
+            }
+        };
+        self.last_stop = match reason {
+            None => "S02".to_string(),
+            Some(StopReason::Watchpoint { access, .. }) => match access.kind {
+                AccessKind::Write => format!("T05watch:{:04x};", access.addr),
+                AccessKind::Read => format!("T05rwatch:{:04x};", access.addr),
+            },
+            Some(StopReason::Halted(_)) => "S04".to_string(),
+            Some(_) => "S05".to_string(),
+        };
+        Ok(self.last_stop.clone())
+    }
+
+    fn write_registers(&mut self, args: &str) -> String {
+        match from_hex(args) {
+            Some(bytes) if bytes.len() == 7 => {
+                self.debugger.cpu_mut().set_registers(registers_from_bytes(&bytes));
+                "OK".to_string()
+            }
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn read_register(&mut self, args: &str) -> String {
+        let bytes = registers_to_bytes(&self.debugger.cpu().registers());
+        match usize::from_str_radix(args, 16) {
+            Ok(n @ 0..=4) => to_hex(&bytes[n..n + 1]),
+            Ok(5) => to_hex(&bytes[5..7]),
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn write_register(&mut self, args: &str) -> String {
+        let (n, value) = match args.split_once('=') {
+            Some((n, value)) => (usize::from_str_radix(n, 16).ok(), from_hex(value)),
+            None => return "E01".to_string(),
+        };
+        let mut bytes = registers_to_bytes(&self.debugger.cpu().registers());
+        match (n, value) {
+            (Some(n @ 0..=4), Some(value)) if value.len() == 1 => bytes[n] = value[0],
+            (Some(5), Some(value)) if value.len() == 2 => bytes[5..7].copy_from_slice(&value),
+            _ => return "E01".to_string(),
+        }
+        self.debugger.cpu_mut().set_registers(registers_from_bytes(&bytes));
+        "OK".to_string()
+    }
+
+    /// `addr,length`
+    fn parse_range(args: &str) -> Option<(u16, u16)> {
+        let (addr, len) = args.split_once(',')?;
+        Some((parse_u16(addr)?, parse_u16(len)?))
+    }
+
+    fn read_memory(&mut self, args: &str) -> String {
+        match Self::parse_range(args) {
+            Some((addr, len)) => {
+                let bus = self.debugger.cpu().bus();
+                let bytes = (0..len).map(|i| bus.peek(addr.wrapping_add(i))).collect::<Vec<u8>>();
+                to_hex(&bytes)
+            }
+            None => "E01".to_string(),
+        }
+    }
+
+    fn write_memory(&mut self, args: &str) -> String {
+        let (range, data) = match args.split_once(':') {
+            Some((range, data)) => (Self::parse_range(range), from_hex(data)),
+            None => return "E01".to_string(),
+        };
+        match (range, data) {
+            (Some((addr, len)), Some(data)) if data.len() == len as usize => {
+                let bus = self.debugger.cpu_mut().bus_mut();
+                for (i, &byte) in data.iter().enumerate() {
+                    bus.write(addr.wrapping_add(i as u16), byte);
+                }
+                "OK".to_string()
+            }
+            _ => "E01".to_string(),
+        }
+    }
+
+    /// `type,addr,kind`
+    fn set_breakpoint(&mut self, args: &str, insert: bool) -> String {
+        let mut fields = args.split(',');
+        let (kind, addr, len) = match (fields.next(), fields.next().and_then(parse_u16), fields.next().and_then(parse_u16)) {
+            (Some(kind), Some(addr), Some(len)) => (kind, addr, len),
+            _ => return "E01".to_string(),
+        };
+        let (kind, on) = match kind {
+            "0" | "1" => (0, BreakOn::EXEC),
+            "2" => (2, BreakOn::WRITE),
+            "3" => (3, BreakOn::READ),
+            "4" => (4, BreakOn::READ | BreakOn::WRITE),
+            _ => return String::new(),
+        };
+        let key = (kind, addr, len);
+        if insert {
+            if !self.breakpoints.contains_key(&key) {
+                let end = if on == BreakOn::EXEC { addr } else { addr.saturating_add(len.max(1) - 1) };
+                let breakpoint = Breakpoint::new(BreakTarget::Cpu(addr..=end), on);
+                let id = self.debugger.add_breakpoint(breakpoint);
+                self.breakpoints.insert(key, id);
+            }
+        } else if let Some(id) = self.breakpoints.remove(&key) {
+            self.debugger.remove_breakpoint(id);
+        }
+        "OK".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cartridge::tests::test_rom_with_2_bank_prg, Cpu};
+    use std::thread;
+
+    struct Client {
+        stream: TcpStream,
+    }
+
+    impl Client {
+        fn send(&mut self, data: &str) {
+            let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+            self.stream.write_all(packet.as_bytes()).unwrap();
+        }
+
+        fn recv(&mut self) -> String {
+            let mut packet = vec![];
+            let mut byte = [0u8; 1];
+            loop {
+                self.stream.read_exact(&mut byte).unwrap();
+                match byte[0] {
+                    b'+' if packet.is_empty() => continue,
+                    b'#' => break,
+                    b'$' => packet.clear(),
+                    c => packet.push(c),
+                }
+            }
+            let mut checksum = [0u8; 2];
+            self.stream.read_exact(&mut checksum).unwrap();
+            self.stream.write_all(b"+").unwrap();
+            String::from_utf8(packet).unwrap()
+        }
+
+        fn request(&mut self, data: &str) -> String {
+            self.send(data);
+            self.recv()
+        }
+    }
+
+    #[test]
+    fn test_gdb_session() {
+        let server = GdbServer::bind(0).unwrap();
+        let addr = server.local_addr().unwrap();
+        assert!(addr.ip().is_loopback());
+
+        let client = thread::spawn(move || {
+            let mut client = Client { stream: TcpStream::connect(addr).unwrap() };
+            let mut replies = vec![
+                client.request("qSupported:swbreak+"),
+                client.request("?"),
+                client.request("g"),
+                client.request("Z0,8004,1"),
+                client.request("c"),
+                client.request("p5"),
+                client.request("m0010,2"),
+                client.request("Z2,0011,1"),
+                client.request("z0,8004,1"),
+                client.request("M0010,1:41"),
+                client.request("c"),
+                client.request("P0=7f"),
+                client.request("s"),
+                client.request("g"),
+            ];
+            // 校验和错误
+            client.stream.write_all(b"$g#00").unwrap();
+            let mut nak = [0u8; 1];
+            client.stream.read_exact(&mut nak).unwrap();
+            replies.push(String::from_utf8(nak.to_vec()).unwrap());
+            replies.push(client.request("D"));
+            replies
+        });
+
+        let mut cpu = Cpu::new(test_rom_with_2_bank_prg(vec![
+            0xa9, 0x05, // 8000 LDA #$05
+            0x85, 0x10, // 8002 STA $10
+            0xa5, 0x10, // 8004 LDA $10
+            0x85, 0x11, // 8006 STA $11
+            0xaa, // 8008 TAX
+            0x4c, 0x09, 0x80, // 8009 JMP $8009
+        ]));
+        cpu.reset();
+        let mut debugger = Debugger::new(cpu);
+        server.accept_and_serve(&mut debugger).unwrap();
+
+        let replies = client.join().unwrap();
+        assert_eq!(replies, vec![
+            "PacketSize=1000",
+            "S05",
+            "00000024fd0080",
+            "OK",
+            "S05",
+            "0480",
+            "0500",
+            "OK",
+            "OK",
+            "OK",
+            "T05watch:0011;",
+            "OK",
+            "S05",
+            "7f7f0024fd0980",
+            "-",
+            "OK",
+        ]);
+        assert_eq!(debugger.breakpoints().count(), 0);
+    }
+}
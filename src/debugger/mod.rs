@@ -0,0 +1,554 @@
+//! 调试器: 断点, 读写断点与单步执行, 以及指令日志, 符号与性能分析等调试工具
+//!
+//! [`Debugger`] 包装 [`Cpu`], 每次只执行一条指令并在指令边界检查是否需要停下.
+//! 执行断点停在指令执行之前, 读写断点停在进行该访问的指令执行之后.
+//! 断点可以带有条件表达式(见 [`Expr`]), 也可以只输出日志而不停下.
+
+mod expr;
+mod gdb;
+mod profiler;
+mod symbols;
+mod trace_logger;
+
+use std::ops::RangeInclusive;
+
+use bitflags::bitflags;
+
+use crate::cpu::{AccessKind, BusAccess, Cpu, CpuHalted, Interrupt, Peek, disassemble};
+
+pub use expr::{Expr, ExprError, EvalContext, LogTemplate, Variable, UnaryOp, BinaryOp};
+pub use gdb::GdbServer;
+pub use profiler::{Profiler, Routine, RoutineStats};
+pub use symbols::{Symbols, SymbolLocation, SymbolError, trace, trace_with_symbols};
+pub use trace_logger::{TraceFormat, TraceLogger};
+
+bitflags! {
+    /// 断点关心的访问类型
+    pub struct BreakOn: u8 {
+        const READ = 0b001;
+        const WRITE = 0b010;
+        const EXEC = 0b100;
+    }
+}
+
+/// 断点监视的地址
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BreakTarget {
+    /// CPU 地址空间中的一段地址
+    Cpu(RangeInclusive<u16>),
+    /// 一个 PPU 寄存器($2000-$2007 或 $4014), 对 $2008-$3FFF 镜像的访问同样会触发
+    PpuRegister(u16),
+    /// 一段 PRG ROM 偏移, 只在对应的 bank 被映射时触发
+    PrgRom(RangeInclusive<u32>),
+}
+
+/// 断点命中后的动作
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BreakAction {
+    /// 停下
+    Stop,
+    /// 输出一条日志后继续运行
+    Log(LogTemplate),
+}
+
+/// 断点
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub target: BreakTarget,
+    pub on: BreakOn,
+    pub enabled: bool,
+    /// 只有条件非 0 时才算命中, 读写断点的条件在指令执行之后求值
+    pub condition: Option<Expr>,
+    pub action: BreakAction,
+    /// 前 ignore_count 次命中不执行动作
+    pub ignore_count: u64,
+    /// 命中次数, 包括被忽略的
+    pub hit_count: u64,
+}
+
+impl Breakpoint {
+    pub fn new(target: BreakTarget, on: BreakOn) -> Self {
+        Breakpoint {
+            target,
+            on,
+            enabled: true,
+            condition: None,
+            action: BreakAction::Stop,
+            ignore_count: 0,
+            hit_count: 0,
+        }
+    }
+
+    /// 设置条件, 如 `A == $3F && [$00FE] > 2`
+    pub fn with_condition(mut self, condition: &str) -> Result<Self, ExprError> {
+        self.condition = Some(Expr::parse(condition)?);
+        Ok(self)
+    }
+
+    /// 命中时只输出日志, 见 [`LogTemplate`]
+    pub fn with_log(mut self, template: &str) -> Result<Self, ExprError> {
+        self.action = BreakAction::Log(LogTemplate::parse(template)?);
+        Ok(self)
+    }
+
+    pub fn with_ignore_count(mut self, ignore_count: u64) -> Self {
+        self.ignore_count = ignore_count;
+        self
+    }
+
+    /// 执行断点, 在执行 addr 处的指令之前停下
+    pub fn exec(addr: u16) -> Self {
+        Self::new(BreakTarget::Cpu(addr..=addr), BreakOn::EXEC)
+    }
+
+    /// 读断点, 取指与读取操作数不算在内
+    pub fn read(range: RangeInclusive<u16>) -> Self {
+        Self::new(BreakTarget::Cpu(range), BreakOn::READ)
+    }
+
+    /// 写断点
+    pub fn write(range: RangeInclusive<u16>) -> Self {
+        Self::new(BreakTarget::Cpu(range), BreakOn::WRITE)
+    }
+
+    /// PPU 寄存器访问断点, register 为 $2000-$2007 或 $4014
+    pub fn ppu_register(register: u16, on: BreakOn) -> Self {
+        Self::new(BreakTarget::PpuRegister(register), on)
+    }
+
+    /// prg_offset 为 addr 当前映射到的 PRG ROM 偏移
+    fn matches_addr(&self, addr: u16, prg_offset: Option<u32>) -> bool {
+        match &self.target {
+            BreakTarget::Cpu(range) => range.contains(&addr),
+            BreakTarget::PrgRom(range) => prg_offset.is_some_and(|offset| range.contains(&offset)),
+            BreakTarget::PpuRegister(register) => match addr {
+                0x2000..=0x3fff => addr & 0b0010_0000_0000_0111 == *register,
+                _ => addr == *register,
+            },
+        }
+    }
+
+    fn matches_exec(&self, pc: u16, prg_offset: Option<u32>) -> bool {
+        self.enabled && self.on.contains(BreakOn::EXEC) && self.matches_addr(pc, prg_offset)
+    }
+
+    fn matches_access(&self, access: &BusAccess, prg_offset: Option<u32>) -> bool {
+        let on = match access.kind {
+            AccessKind::Read => BreakOn::READ,
+            AccessKind::Write => BreakOn::WRITE,
+        };
+        self.enabled && self.on.contains(on) && self.matches_addr(access.addr, prg_offset)
+    }
+}
+
+/// 断点编号, 由 [`Debugger::add_breakpoint`] 返回
+pub type BreakpointId = usize;
+
+/// 停下的原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    /// 执行断点, 停在 pc 处的指令执行之前
+    Breakpoint { id: BreakpointId, pc: u16 },
+    /// 读写断点, 停在进行该访问的指令执行之后
+    Watchpoint { id: BreakpointId, access: BusAccess },
+    /// 进入了 NMI 或 IRQ 处理程序, 停在处理程序的第一条指令之前
+    Interrupt(Interrupt),
+    /// step into/over/out 完成
+    Step,
+    /// 到达了指定的扫描线
+    Scanline(u16),
+    /// 一帧结束(vblank 开始)
+    FrameEnd,
+    /// CPU 停机
+    Halted(CpuHalted),
+}
+
+/// 包装 [`Cpu`] 的调试器
+pub struct Debugger {
+    cpu: Cpu,
+    breakpoints: Vec<Option<Breakpoint>>, // 下标即编号, 删除后为 None
+    break_on_nmi: bool,
+    break_on_irq: bool,
+    call_depth: i32, // JSR, BRK 与中断加一, RTS 与 RTI 减一
+    resume_pc: Option<u16>, // 上次停在的执行断点, 继续运行时不再在此处停下
+    logs: Vec<String>, // 日志断点输出的消息
+}
+
+impl Debugger {
+    pub fn new(cpu: Cpu) -> Self {
+        Debugger {
+            cpu,
+            breakpoints: vec![],
+            break_on_nmi: false,
+            break_on_irq: false,
+            call_depth: 0,
+            resume_pc: None,
+            logs: vec![],
+        }
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    /// 取回 Cpu
+    pub fn into_cpu(self) -> Cpu {
+        self.cpu
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> BreakpointId {
+        self.breakpoints.push(Some(breakpoint));
+        self.breakpoints.len() - 1
+    }
+
+    pub fn remove_breakpoint(&mut self, id: BreakpointId) -> Option<Breakpoint> {
+        self.breakpoints.get_mut(id).and_then(|breakpoint| breakpoint.take())
+    }
+
+    pub fn breakpoint_mut(&mut self, id: BreakpointId) -> Option<&mut Breakpoint> {
+        self.breakpoints.get_mut(id).and_then(|breakpoint| breakpoint.as_mut())
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = (BreakpointId, &Breakpoint)> {
+        self.breakpoints
+            .iter()
+            .enumerate()
+            .filter_map(|(id, breakpoint)| breakpoint.as_ref().map(|breakpoint| (id, breakpoint)))
+    }
+
+    /// 符号表, 保存在总线上, 见 [`Bus::set_symbols`](crate::Bus::set_symbols)
+    pub fn symbols(&self) -> &Symbols {
+        self.cpu.bus().symbols()
+    }
+
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.cpu.bus_mut().set_symbols(symbols);
+    }
+
+    /// 解析表达式, 其中的符号按当前的 bank 映射解析为 CPU 地址
+    pub fn parse_expr(&self, source: &str) -> Result<Expr, ExprError> {
+        let bus = self.cpu.bus();
+        Expr::parse_with_symbols(source, &|name| bus.symbols().resolve(bus, name).map(|addr| addr as i64))
+    }
+
+    /// 设置指令日志, 之后每条指令执行之前都会记录; 写入失败时会停止记录
+    ///
+    /// 日志挂在 CPU 上(见 [`Cpu::set_instruction_hook`]), 会替换已有的指令回调
+    pub fn set_trace_logger(&mut self, logger: Option<TraceLogger>) {
+        self.cpu.set_instruction_hook(logger.map(|logger| Box::new(logger) as _));
+    }
+
+    pub fn trace_logger_mut(&mut self) -> Option<&mut TraceLogger> {
+        self.cpu.instruction_hook_mut()
+    }
+
+    pub fn take_trace_logger(&mut self) -> Option<TraceLogger> {
+        self.cpu.take_instruction_hook()
+    }
+
+    /// 取出日志断点输出的消息, 这些消息同时以 info 级别输出到 log
+    pub fn take_logs(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.logs)
+    }
+
+    pub fn set_break_on_nmi(&mut self, enabled: bool) {
+        self.break_on_nmi = enabled;
+    }
+
+    pub fn set_break_on_irq(&mut self, enabled: bool) {
+        self.break_on_irq = enabled;
+    }
+
+    /// 当前调用深度, 从创建调试器时的 0 开始计算
+    pub fn call_depth(&self) -> i32 {
+        self.call_depth
+    }
+
+    /// 执行一条指令(以及随后进入的中断)
+    pub fn step_into(&mut self) -> StopReason {
+        self.run_until(|_, _| Some(StopReason::Step))
+    }
+
+    /// 执行一条指令, 若为 JSR 则一直执行到子程序返回
+    pub fn step_over(&mut self) -> StopReason {
+        let pc = self.cpu.registers().pc;
+        if self.cpu.bus().peek(pc) != 0x20 {
+            return self.step_into();
+        }
+        let depth = self.call_depth;
+        self.run_until(move |debugger, _| {
+            (debugger.call_depth <= depth).then_some(StopReason::Step)
+        })
+    }
+
+    /// 一直执行到当前子程序(或中断处理程序)返回
+    pub fn step_out(&mut self) -> StopReason {
+        let depth = self.call_depth;
+        self.run_until(move |debugger, _| {
+            (debugger.call_depth < depth).then_some(StopReason::Step)
+        })
+    }
+
+    /// 一直执行到 PPU 进入 scanline 这一扫描线, 若当前已在该扫描线则到下一帧的该扫描线
+    pub fn run_to_scanline(&mut self, scanline: u16) -> StopReason {
+        let mut prev_scanline = self.cpu.bus().scanline();
+        self.run_until(move |debugger, _| {
+            let current = debugger.cpu.bus().scanline();
+            let reached = current == scanline && prev_scanline != scanline;
+            prev_scanline = current;
+            reached.then_some(StopReason::Scanline(scanline))
+        })
+    }
+
+    /// 一直执行到这一帧结束
+    pub fn run_frame(&mut self) -> StopReason {
+        self.run_until(|_, frame_end| frame_end.then_some(StopReason::FrameEnd))
+    }
+
+    /// 逐条执行指令, 直到遇到断点或 done 返回停下的原因; done 的参数为调试器与这一帧是否结束
+    ///
+    /// 若上次停在执行断点, 第一条指令不再检查执行断点, 以便从断点处继续运行.
+    /// 总线的访问记录(用于读写断点)只在此期间开启, 通过 `cpu_mut` 直接运行时不会积累
+    fn run_until<F>(&mut self, done: F) -> StopReason
+    where
+        F: FnMut(&Self, bool) -> Option<StopReason>
+    {
+        self.cpu.bus_mut().set_access_log_enabled(true);
+        let reason = self.run_until_stopped(done);
+        self.cpu.bus_mut().set_access_log_enabled(false);
+        reason
+    }
+
+    fn run_until_stopped<F>(&mut self, mut done: F) -> StopReason
+    where
+        F: FnMut(&Self, bool) -> Option<StopReason>
+    {
+        let mut resume_pc = self.resume_pc.take();
+        loop {
+            if let Some(halted) = self.cpu.halted() {
+                return StopReason::Halted(halted);
+            }
+
+            let pc = self.cpu.registers().pc;
+            if resume_pc.take() != Some(pc) {
+                let prg_offset = self.cpu.bus().prg_rom_offset(pc);
+                if let Some(id) = self.hit_breakpoint(None, |breakpoint| breakpoint.matches_exec(pc, prg_offset)) {
+                    self.resume_pc = Some(pc);
+                    return StopReason::Breakpoint { id, pc };
+                }
+            }
+
+            let instruction = disassemble(self.cpu.bus(), pc);
+            self.cpu.bus_mut().take_access_log();
+            let frame_end = self.cpu.run_next_instruction();
+            let accesses = self.cpu.bus_mut().take_access_log();
+
+            match instruction.bytes[0] {
+                0x20 => self.call_depth += 1, // JSR
+                0x60 | 0x40 => self.call_depth -= 1, // RTS, RTI
+                _ => {}
+            }
+            let interrupt = self.cpu.last_interrupt();
+            if interrupt.is_some() {
+                self.call_depth += 1;
+            }
+
+            if let Some(halted) = self.cpu.halted() {
+                return StopReason::Halted(halted);
+            }
+            let fetch = pc as u32..pc as u32 + instruction.bytes.len() as u32;
+            for access in accesses.iter() {
+                if access.kind == AccessKind::Read && fetch.contains(&(access.addr as u32)) {
+                    continue; // 取指与读取操作数
+                }
+                let prg_offset = self.cpu.bus().prg_rom_offset(access.addr);
+                let matches = |breakpoint: &Breakpoint| breakpoint.matches_access(access, prg_offset);
+                if let Some(id) = self.hit_breakpoint(Some(*access), matches) {
+                    return StopReason::Watchpoint { id, access: *access };
+                }
+            }
+            match interrupt {
+                Some(Interrupt::Nmi) if self.break_on_nmi => return StopReason::Interrupt(Interrupt::Nmi),
+                Some(Interrupt::Irq) if self.break_on_irq => return StopReason::Interrupt(Interrupt::Irq),
+                _ => {}
+            }
+            if let Some(reason) = done(self, frame_end) {
+                return reason;
+            }
+        }
+    }
+
+    /// 对每个匹配且满足条件的断点计数并执行动作, 返回第一个需要停下的断点
+    fn hit_breakpoint<F>(&mut self, access: Option<BusAccess>, mut matches: F) -> Option<BreakpointId>
+    where
+        F: FnMut(&Breakpoint) -> bool
+    {
+        let mut stop = None;
+        for id in 0..self.breakpoints.len() {
+            let ctx = EvalContext { cpu: &self.cpu, access };
+            let breakpoint = match self.breakpoints[id].as_ref() {
+                Some(breakpoint) if matches(breakpoint) => breakpoint,
+                _ => continue,
+            };
+            if breakpoint.condition.as_ref().is_some_and(|condition| condition.eval(&ctx) == 0) {
+                continue;
+            }
+            let message = match &breakpoint.action {
+                BreakAction::Log(template) => Some(template.format(&ctx)),
+                BreakAction::Stop => None,
+            };
+
+            let breakpoint = self.breakpoints[id].as_mut().unwrap();
+            breakpoint.hit_count += 1;
+            if breakpoint.hit_count <= breakpoint.ignore_count {
+                continue;
+            }
+            match message {
+                Some(message) => {
+                    log::info!("{}", message);
+                    self.logs.push(message);
+                }
+                None => {
+                    stop = stop.or(Some(id));
+                }
+            }
+        }
+        stop
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::tests::test_rom_with_2_bank_prg;
+
+    /// 程序位于 $8000, NMI 处理程序位于 $9000
+    fn debugger(code: &[u8]) -> Debugger {
+        let mut prg = vec![0xea; 0x8000]; // NOP
+        prg[..code.len()].copy_from_slice(code);
+        prg[0x1000] = 0x40; // RTI
+        prg[0x7ffa] = 0x00; // NMI -> $9000
+        prg[0x7ffb] = 0x90;
+        let mut cpu = Cpu::new(test_rom_with_2_bank_prg(prg));
+        cpu.reset();
+        Debugger::new(cpu)
+    }
+
+    #[test]
+    fn test_exec_and_write_breakpoints() {
+        let mut debugger = debugger(&[
+            0xa9, 0x01, // 8000 LDA #$01
+            0x85, 0x10, // 8002 STA $10
+            0xe6, 0x10, // 8004 INC $10
+            0x4c, 0x00, 0x80, // 8006 JMP $8000
+        ]);
+        let exec = debugger.add_breakpoint(Breakpoint::exec(0x8004));
+        let write = debugger.add_breakpoint(Breakpoint::write(0x10..=0x10));
+
+        assert_eq!(
+            debugger.run_frame(),
+            StopReason::Watchpoint { id: write, access: BusAccess { addr: 0x10, data: 1, kind: AccessKind::Write } }
+        );
+        assert_eq!(debugger.cpu().registers().pc, 0x8004);
+        assert_eq!(debugger.run_frame(), StopReason::Breakpoint { id: exec, pc: 0x8004 });
+
+        // INC 先写回原值
+        debugger.remove_breakpoint(exec);
+        assert_eq!(
+            debugger.run_frame(),
+            StopReason::Watchpoint { id: write, access: BusAccess { addr: 0x10, data: 1, kind: AccessKind::Write } }
+        );
+        assert_eq!(debugger.cpu().registers().pc, 0x8006);
+
+        debugger.breakpoint_mut(write).unwrap().enabled = false;
+        assert_eq!(debugger.run_frame(), StopReason::FrameEnd);
+
+        // 不经过调试器运行时不记录访问
+        debugger.cpu_mut().run_next_frame().unwrap();
+        assert!(debugger.cpu_mut().bus_mut().take_access_log().is_empty());
+    }
+
+    #[test]
+    fn test_step_over_and_out() {
+        let mut debugger = debugger(&[
+            0x20, 0x10, 0x80, // 8000 JSR $8010
+            0xe8, // 8003 INX
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x20, 0x20, 0x80, // 8010 JSR $8020
+            0x60, // 8013 RTS
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0xc8, // 8020 INY
+            0x60, // 8021 RTS
+        ]);
+        assert_eq!(debugger.step_over(), StopReason::Step);
+        assert_eq!(debugger.cpu().registers().pc, 0x8003);
+        assert_eq!(debugger.cpu().registers().y, 1);
+        assert_eq!(debugger.call_depth(), 0);
+
+        debugger.cpu_mut().reset();
+        assert_eq!(debugger.step_into(), StopReason::Step);
+        assert_eq!(debugger.step_into(), StopReason::Step);
+        assert_eq!(debugger.cpu().registers().pc, 0x8020);
+        assert_eq!(debugger.step_out(), StopReason::Step);
+        assert_eq!(debugger.cpu().registers().pc, 0x8013);
+        assert_eq!(debugger.step_out(), StopReason::Step);
+        assert_eq!(debugger.cpu().registers().pc, 0x8003);
+    }
+
+    #[test]
+    fn test_ppu_register_breakpoint_and_nmi() {
+        let mut debugger = debugger(&[
+            0xa9, 0x80, // 8000 LDA #$80
+            0x8d, 0x08, 0x20, // 8002 STA $2008(PPUCTRL 的镜像), 开启 NMI
+            0x4c, 0x05, 0x80, // 8005 JMP $8005
+        ]);
+        let ctrl = debugger.add_breakpoint(Breakpoint::ppu_register(0x2000, BreakOn::WRITE));
+        assert_eq!(
+            debugger.run_frame(),
+            StopReason::Watchpoint { id: ctrl, access: BusAccess { addr: 0x2008, data: 0x80, kind: AccessKind::Write } }
+        );
+
+        // vblank 开始时一帧结束, NMI 在下一条指令之前响应
+        debugger.set_break_on_nmi(true);
+        assert_eq!(debugger.run_frame(), StopReason::FrameEnd);
+        assert_eq!(debugger.step_into(), StopReason::Interrupt(Interrupt::Nmi));
+        assert_eq!(debugger.cpu().registers().pc, 0x9000);
+        assert_eq!(debugger.cpu().bus().scanline(), 241);
+        assert_eq!(debugger.step_out(), StopReason::Step);
+        assert_eq!(debugger.cpu().registers().pc, 0x8005);
+
+        assert_eq!(debugger.run_to_scanline(20), StopReason::Scanline(20));
+        assert_eq!(debugger.cpu().bus().scanline(), 20);
+    }
+
+    #[test]
+    fn test_conditions_and_logs() {
+        let mut debugger = debugger(&[
+            0xe8, // 8000 INX
+            0x86, 0x10, // 8001 STX $10
+            0x4c, 0x00, 0x80, // 8003 JMP $8000
+        ]);
+        let exec = debugger.add_breakpoint(Breakpoint::exec(0x8001).with_condition("X == 3").unwrap());
+        debugger.add_breakpoint(
+            Breakpoint::write(0x10..=0x10).with_log("[$10]={{value}} x={{X}}").unwrap().with_ignore_count(1)
+        );
+        assert_eq!(debugger.run_frame(), StopReason::Breakpoint { id: exec, pc: 0x8001 });
+        assert_eq!(debugger.cpu().registers().x, 3);
+        assert_eq!(debugger.take_logs(), vec!["[$10]=$02 x=$02"]);
+
+        let write = debugger.add_breakpoint(
+            Breakpoint::write(0x10..=0x10).with_condition("value >= 5 && address == $10").unwrap()
+        );
+        assert_eq!(
+            debugger.run_frame(),
+            StopReason::Watchpoint { id: write, access: BusAccess { addr: 0x10, data: 5, kind: AccessKind::Write } }
+        );
+        assert_eq!(debugger.take_logs(), vec!["[$10]=$03 x=$03", "[$10]=$04 x=$04", "[$10]=$05 x=$05"]);
+        let hits = debugger.breakpoints().map(|(_, breakpoint)| breakpoint.hit_count).collect::<Vec<_>>();
+        assert_eq!(hits, vec![1, 5, 1]);
+    }
+}
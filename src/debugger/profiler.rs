@@ -0,0 +1,365 @@
+//! 性能分析, 把每个 CPU 周期记到当时所在的子程序上
+//!
+//! 调用栈由 JSR 与中断入栈, 由栈指针的回退出栈: 栈指针回到调用前的位置即视为返回,
+//! 因此 RTS/RTI 以及弹出返回地址后直接 JMP 的写法都能正确处理.
+//! JSR 的周期记在调用者上, RTS/RTI 的周期记在被调用者上, 中断序列的 7 个周期记在中断处理程序上.
+
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::{self, Write},
+};
+
+use crate::{Bus, Cpu, CpuHalted, Interrupt, Peek, debugger::Symbols};
+
+/// NTSC 每帧的扫描线数
+const SCANLINES: usize = 262;
+/// 中断序列的周期数
+const INTERRUPT_CYCLES: u64 = 7;
+
+/// 调用栈中的一个子程序, 由入口地址区分
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Routine {
+    /// 开始分析时所在的代码, 位于调用栈底
+    Main,
+    /// JSR 调用的子程序
+    Subroutine(u16),
+    /// NMI 处理程序
+    Nmi(u16),
+    /// IRQ 与 BRK 处理程序
+    Irq(u16),
+}
+
+impl Routine {
+    /// 有符号时使用符号, 否则使用地址
+    pub fn name(&self, bus: &Bus, symbols: &Symbols) -> String {
+        let name = |addr: u16| match symbols.label_at(bus, addr) {
+            Some(label) => label.to_string(),
+            None => format!("${:04X}", addr),
+        };
+        match *self {
+            Routine::Main => "main".to_string(),
+            Routine::Subroutine(addr) => name(addr),
+            Routine::Nmi(addr) => format!("NMI@{}", name(addr)),
+            Routine::Irq(addr) => format!("IRQ@{}", name(addr)),
+        }
+    }
+}
+
+/// 一个子程序的统计
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutineStats {
+    pub routine: Routine,
+    pub calls: u64,
+    /// 包括其调用的子程序, 递归调用只计一次
+    pub inclusive_cycles: u64,
+    /// 不包括其调用的子程序
+    pub exclusive_cycles: u64,
+    /// 单帧中最多的 inclusive 周期数
+    pub max_frame_cycles: u64,
+    frame_cycles: u64,
+}
+
+impl RoutineStats {
+    fn new(routine: Routine) -> Self {
+        RoutineStats {
+            routine,
+            calls: 0,
+            inclusive_cycles: 0,
+            exclusive_cycles: 0,
+            max_frame_cycles: 0,
+            frame_cycles: 0,
+        }
+    }
+}
+
+struct StackFrame {
+    routine: Routine,
+    return_sp: u8, // 调用前的栈指针, 栈指针回到此处(或更高)时出栈
+    node: usize, // 在调用树中的节点
+}
+
+/// 调用树的节点, 用于输出 collapsed stack
+struct CallNode {
+    routine: Routine,
+    parent: Option<usize>,
+    children: HashMap<Routine, usize>,
+    cycles: u64, // 不包括子节点
+}
+
+/// 性能分析器, 通过 [`Profiler::run_instruction`] 或 [`Profiler::run_frame`] 代替 [`Cpu`] 的对应方法运行
+pub struct Profiler {
+    stack: Vec<StackFrame>,
+    nodes: Vec<CallNode>,
+    stats: HashMap<Routine, RoutineStats>,
+    nmi_scanline_cycles: [u64; SCANLINES],
+    frames: u64,
+    cycles: u64,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        let mut stats = HashMap::new();
+        stats.insert(Routine::Main, RoutineStats { calls: 1, ..RoutineStats::new(Routine::Main) });
+        Profiler {
+            stack: vec![StackFrame { routine: Routine::Main, return_sp: 0, node: 0 }],
+            nodes: vec![CallNode { routine: Routine::Main, parent: None, children: HashMap::new(), cycles: 0 }],
+            stats,
+            nmi_scanline_cycles: [0; SCANLINES],
+            frames: 0,
+            cycles: 0,
+        }
+    }
+
+    /// 执行一条指令(以及随后进入的中断)并记录, 返回是否到达帧末
+    pub fn run_instruction(&mut self, cpu: &mut Cpu) -> bool {
+        let opcode = cpu.bus().peek(cpu.registers().pc);
+        let sp_before = cpu.registers().sp;
+        let cycles_before = cpu.bus().cycles();
+        let scanline = cpu.bus().scanline() as usize;
+
+        let frame_end = cpu.run_next_instruction();
+
+        let registers = cpu.registers();
+        let mut cycles = cpu.bus().cycles() - cycles_before;
+        let interrupt = cpu.last_interrupt();
+        if interrupt.is_some() {
+            cycles = cycles.saturating_sub(INTERRUPT_CYCLES);
+        }
+        self.add_cycles(cycles, scanline);
+
+        // 栈指针回到调用前的位置, 视为已经返回
+        while self.stack.len() > 1 && registers.sp >= self.stack.last().unwrap().return_sp {
+            self.stack.pop();
+        }
+        if let Some(interrupt) = interrupt {
+            let routine = match interrupt {
+                Interrupt::Nmi => Routine::Nmi(registers.pc),
+                _ => Routine::Irq(registers.pc),
+            };
+            self.push(routine, registers.sp.wrapping_add(3));
+            self.add_cycles(INTERRUPT_CYCLES, scanline);
+        } else if opcode == 0x20 { // JSR
+            self.push(Routine::Subroutine(registers.pc), sp_before);
+        }
+
+        if frame_end {
+            self.end_frame();
+        }
+        frame_end
+    }
+
+    /// 执行到帧末并记录, CPU 停机时返回错误
+    pub fn run_frame(&mut self, cpu: &mut Cpu) -> Result<(), CpuHalted> {
+        loop {
+            if let Some(halted) = cpu.halted() {
+                return Err(halted);
+            }
+            if self.run_instruction(cpu) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// 各个子程序的统计, 按 inclusive 周期数从多到少排列
+    pub fn routines(&self) -> Vec<&RoutineStats> {
+        let mut routines: Vec<_> = self.stats.values().collect();
+        routines.sort_by(|a, b| b.inclusive_cycles.cmp(&a.inclusive_cycles).then(a.routine.cmp(&b.routine)));
+        routines
+    }
+
+    pub fn routine(&self, routine: Routine) -> Option<&RoutineStats> {
+        self.stats.get(&routine)
+    }
+
+    /// 每条扫描线上 NMI 处理程序(及其调用的子程序)运行的周期数, 按指令开始时的扫描线统计
+    pub fn nmi_scanline_cycles(&self) -> &[u64] {
+        &self.nmi_scanline_cycles
+    }
+
+    /// 记录的总周期数
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// 记录的完整帧数
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// 文本报告: 子程序统计表与 NMI 的扫描线热图
+    pub fn report(&self, bus: &Bus, symbols: &Symbols) -> String {
+        let mut report = String::new();
+        let _ = writeln!(report, "Frames: {}  Cycles: {}", self.frames, self.cycles);
+        let _ = writeln!(report);
+        let _ = writeln!(report, "{:<24} {:>8} {:>12} {:>7} {:>12} {:>7} {:>10}",
+            "Routine", "Calls", "Inclusive", "%", "Exclusive", "%", "Max/frame");
+        let percent = |cycles: u64| cycles as f64 * 100.0 / self.cycles.max(1) as f64;
+        for stats in self.routines() {
+            let _ = writeln!(report, "{:<24} {:>8} {:>12} {:>6.2}% {:>12} {:>6.2}% {:>10}",
+                stats.routine.name(bus, symbols),
+                stats.calls,
+                stats.inclusive_cycles,
+                percent(stats.inclusive_cycles),
+                stats.exclusive_cycles,
+                percent(stats.exclusive_cycles),
+                stats.max_frame_cycles,
+            );
+        }
+
+        let max = self.nmi_scanline_cycles.iter().copied().max().unwrap_or(0);
+        if max > 0 {
+            let _ = writeln!(report);
+            let _ = writeln!(report, "NMI cycles by scanline:");
+            for (scanline, &cycles) in self.nmi_scanline_cycles.iter().enumerate() {
+                if cycles > 0 {
+                    let bar = "#".repeat(((cycles * 40).div_ceil(max)) as usize);
+                    let _ = writeln!(report, "{:>3} {:<40} {}", scanline, bar, cycles);
+                }
+            }
+        }
+        report
+    }
+
+    /// 写出 collapsed stack 格式(每行 `main;foo;bar 周期数`), 可用 flamegraph.pl 或 inferno 生成火焰图
+    pub fn write_collapsed<W: Write>(&self, mut writer: W, bus: &Bus, symbols: &Symbols) -> io::Result<()> {
+        for (idx, node) in self.nodes.iter().enumerate() {
+            if node.cycles == 0 {
+                continue;
+            }
+            let mut path = vec![];
+            let mut current = Some(idx);
+            while let Some(idx) = current {
+                path.push(self.nodes[idx].routine.name(bus, symbols));
+                current = self.nodes[idx].parent;
+            }
+            path.reverse();
+            writeln!(writer, "{} {}", path.join(";"), node.cycles)?;
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, routine: Routine, return_sp: u8) {
+        let parent = self.stack.last().unwrap().node;
+        let next_node = self.nodes.len();
+        let node = *self.nodes[parent].children.entry(routine).or_insert(next_node);
+        if node == next_node {
+            self.nodes.push(CallNode { routine, parent: Some(parent), children: HashMap::new(), cycles: 0 });
+        }
+        self.stack.push(StackFrame { routine, return_sp, node });
+        self.stats.entry(routine).or_insert_with(|| RoutineStats::new(routine)).calls += 1;
+    }
+
+    fn add_cycles(&mut self, cycles: u64, scanline: usize) {
+        if cycles == 0 {
+            return;
+        }
+        self.cycles += cycles;
+        let top = self.stack.last().unwrap();
+        self.nodes[top.node].cycles += cycles;
+        self.stats.get_mut(&top.routine).unwrap().exclusive_cycles += cycles;
+        let mut in_nmi = false;
+        for (depth, frame) in self.stack.iter().enumerate() {
+            in_nmi |= matches!(frame.routine, Routine::Nmi(_));
+            if self.stack[..depth].iter().any(|outer| outer.routine == frame.routine) {
+                continue; // 递归调用
+            }
+            let stats = self.stats.get_mut(&frame.routine).unwrap();
+            stats.inclusive_cycles += cycles;
+            stats.frame_cycles += cycles;
+        }
+        if in_nmi {
+            self.nmi_scanline_cycles[scanline.min(SCANLINES - 1)] += cycles;
+        }
+    }
+
+    fn end_frame(&mut self) {
+        self.frames += 1;
+        for stats in self.stats.values_mut() {
+            stats.max_frame_cycles = stats.max_frame_cycles.max(stats.frame_cycles);
+            stats.frame_cycles = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::tests::test_rom_with_2_bank_prg;
+
+    #[test]
+    fn test_profile_calls_and_nmi() {
+        let mut prg = vec![0xea; 0x8000];
+        let program: &[u8] = &[
+            0xa9, 0x80, // 8000 LDA #$80
+            0x8d, 0x00, 0x20, // 8002 STA $2000
+            0x20, 0x0b, 0x80, // 8005 JSR $800B
+            0x4c, 0x05, 0x80, // 8008 JMP $8005
+            0x20, 0x13, 0x80, // 800B JSR $8013
+            0x68, // 800E PLA
+            0x68, // 800F PLA
+            0x4c, 0x08, 0x80, // 8010 JMP $8008, 不经 RTS 返回
+            0x60, // 8013 RTS
+        ];
+        prg[..program.len()].copy_from_slice(program);
+        prg[0x0100..0x0104].copy_from_slice(&[
+            0x20, 0x13, 0x80, // 8100 JSR $8013
+            0x40, // 8103 RTI
+        ]);
+        prg[0x7ffa..0x7ffc].copy_from_slice(&[0x00, 0x81]); // NMI 向量
+        let mut cpu = Cpu::new(test_rom_with_2_bank_prg(prg));
+        cpu.reset();
+
+        let mut profiler = Profiler::new();
+        profiler.run_frame(&mut cpu).unwrap();
+        profiler.run_frame(&mut cpu).unwrap();
+        profiler.run_frame(&mut cpu).unwrap();
+        profiler.run_instruction(&mut cpu); // 进入 NMI
+        assert!(matches!(profiler.stack.last().unwrap().routine, Routine::Nmi(0x8100)));
+        for _ in 0..3 {
+            profiler.run_instruction(&mut cpu);
+        }
+        assert!(!profiler.stack.iter().any(|frame| matches!(frame.routine, Routine::Nmi(_))));
+
+        let main_loop = profiler.routine(Routine::Subroutine(0x800b)).unwrap();
+        let leaf = profiler.routine(Routine::Subroutine(0x8013)).unwrap();
+        let nmi = profiler.routine(Routine::Nmi(0x8100)).unwrap();
+        assert!(main_loop.calls > 100);
+        assert!(leaf.calls > main_loop.calls);
+        assert_eq!(nmi.calls, 3);
+        // 每次调用只执行一条 RTS, 最后一次调用可能尚未返回
+        assert!(leaf.calls * 6 - leaf.exclusive_cycles <= 6);
+        assert!(main_loop.inclusive_cycles > leaf.inclusive_cycles / 2);
+        assert_eq!(nmi.inclusive_cycles, 3 * (7 + 6 + 6 + 6));
+        assert!(nmi.max_frame_cycles > 0);
+
+        let main = profiler.routine(Routine::Main).unwrap();
+        assert_eq!(main.inclusive_cycles, profiler.cycles());
+        let exclusive_total: u64 = profiler.routines().iter().map(|stats| stats.exclusive_cycles).sum();
+        assert_eq!(exclusive_total, profiler.cycles());
+
+        let heat = profiler.nmi_scanline_cycles();
+        assert_eq!(heat.iter().sum::<u64>(), nmi.inclusive_cycles);
+        assert!(heat[241] > 0);
+
+        let mut symbols = Symbols::new();
+        symbols.insert(crate::SymbolLocation::PrgRom(0x0100), "nmi");
+        let report = profiler.report(cpu.bus(), &symbols);
+        assert!(report.contains("NMI@nmi"));
+        assert!(report.contains("NMI cycles by scanline:"));
+
+        let mut collapsed = vec![];
+        profiler.write_collapsed(&mut collapsed, cpu.bus(), &symbols).unwrap();
+        let collapsed = String::from_utf8(collapsed).unwrap();
+        assert!(collapsed.lines().any(|line| line.starts_with("main;$800B;$8013 ")));
+        assert!(collapsed.lines().any(|line| line.contains("NMI@nmi;$8013 ")));
+        let total: u64 = collapsed.lines().map(|line| line.rsplit(' ').next().unwrap().parse::<u64>().unwrap()).sum();
+        assert_eq!(total, profiler.cycles());
+    }
+}
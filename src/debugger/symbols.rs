@@ -0,0 +1,386 @@
+//! 符号表: 读取 ld65 的 `.dbg`, FCEUX 的 `.nl` 与 Mesen 的 `.mlb` 文件
+//!
+//! PRG ROM 中的符号以 ROM 偏移(不含 16 字节的文件头)记录, 因此可以区分映射到同一 CPU 地址的不同 bank;
+//! RAM 与寄存器中的符号直接以 CPU 地址记录. NESASM 可以输出 FCEUX 格式的 `.nl` 文件.
+
+use std::{collections::HashMap, fmt, fs, path::Path};
+
+use crate::{
+    Bus, Cpu,
+    cpu::{AddressingMode, Instruction, disassemble, trace::trace_readonly},
+    debugger::{BreakOn, BreakTarget, Breakpoint},
+};
+
+/// 符号所在的位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SymbolLocation {
+    /// CPU 地址, 用于 RAM 与寄存器
+    Cpu(u16),
+    /// PRG ROM 偏移
+    PrgRom(u32),
+}
+
+/// 符号文件解析错误
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolError {
+    /// 出错的行号, 从 1 开始; 为 0 时表示读取文件失败
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at line {}", self.message, self.line)
+    }
+}
+
+impl std::error::Error for SymbolError {}
+
+fn error(line: usize, message: String) -> SymbolError {
+    SymbolError { line: line + 1, message }
+}
+
+/// 符号表
+#[derive(Debug, Clone, Default)]
+pub struct Symbols {
+    labels: HashMap<SymbolLocation, String>,
+    locations: HashMap<String, SymbolLocation>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 添加一个符号, 同一位置已有符号时保留先添加的作为显示的名字
+    pub fn insert(&mut self, location: SymbolLocation, name: &str) {
+        self.labels.entry(location).or_insert_with(|| name.to_string());
+        self.locations.insert(name.to_string(), location);
+    }
+
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+
+    pub fn location(&self, name: &str) -> Option<SymbolLocation> {
+        self.locations.get(name).copied()
+    }
+
+    pub fn label(&self, location: SymbolLocation) -> Option<&str> {
+        self.labels.get(&location).map(|label| label.as_str())
+    }
+
+    /// CPU 地址 addr 按当前的 bank 映射对应的符号, 没有 bank 信息的符号按 CPU 地址查找
+    pub fn label_at(&self, bus: &Bus, addr: u16) -> Option<&str> {
+        bus.prg_rom_offset(addr)
+            .and_then(|offset| self.label(SymbolLocation::PrgRom(offset)))
+            .or_else(|| self.label(SymbolLocation::Cpu(addr)))
+    }
+
+    /// 符号按当前的 bank 映射对应的 CPU 地址, 有多个时取最小的
+    pub fn resolve(&self, bus: &Bus, name: &str) -> Option<u16> {
+        match self.location(name)? {
+            SymbolLocation::Cpu(addr) => Some(addr),
+            SymbolLocation::PrgRom(offset) => bus.prg_rom_addresses(offset).first().copied(),
+        }
+    }
+
+    /// 在符号处的断点, PRG ROM 中的符号只在其所在 bank 被映射时触发
+    pub fn breakpoint(&self, name: &str, on: BreakOn) -> Option<Breakpoint> {
+        let target = match self.location(name)? {
+            SymbolLocation::Cpu(addr) => BreakTarget::Cpu(addr..=addr),
+            SymbolLocation::PrgRom(offset) => BreakTarget::PrgRom(offset..=offset),
+        };
+        Some(Breakpoint::new(target, on))
+    }
+
+    /// 根据扩展名读取符号文件: `.dbg`, `.mlb`, 以及 `.nl`(`xxx.nes.0.nl` 为 bank 0, `xxx.nes.ram.nl` 为 RAM)
+    pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), SymbolError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| SymbolError {
+            line: 0,
+            message: format!("{}: {}", path.display(), e),
+        })?;
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("dbg") => self.load_ld65_dbg(&text),
+            Some("mlb") => self.load_mesen_mlb(&text),
+            Some("nl") => {
+                let bank = name
+                    .trim_end_matches(".nl")
+                    .rsplit('.')
+                    .next()
+                    .and_then(|bank| bank.parse::<u32>().ok());
+                self.load_fceux_nl(&text, bank)
+            }
+            _ => Err(SymbolError { line: 0, message: format!("{}: unknown symbol file type", path.display()) }),
+        }
+    }
+
+    /// 读取 FCEUX 的 `.nl` 文件, 每行形如 `$C4A2#UpdatePlayer#注释`
+    ///
+    /// bank 为 16KB PRG bank 的编号, 其中 $8000 以上的地址按该 bank 记录; RAM 的文件为 None
+    pub fn load_fceux_nl(&mut self, text: &str, bank: Option<u32>) -> Result<(), SymbolError> {
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut fields = line.splitn(3, '#');
+            let addr = fields.next().unwrap_or("");
+            let name = fields.next().unwrap_or("").trim();
+            let addr = addr
+                .strip_prefix('$')
+                .map(|addr| addr.split('/').next().unwrap_or(addr)) // `$0300/10` 表示数组
+                .and_then(|addr| u16::from_str_radix(addr, 16).ok())
+                .ok_or_else(|| error(i, format!("invalid address '{}'", addr)))?;
+            if name.is_empty() {
+                continue;
+            }
+            let location = match bank {
+                Some(bank) if addr >= 0x8000 => SymbolLocation::PrgRom(bank * 0x4000 + (addr & 0x3fff) as u32),
+                _ => SymbolLocation::Cpu(addr),
+            };
+            self.insert(location, name);
+        }
+        Ok(())
+    }
+
+    /// 读取 Mesen 的 `.mlb` 文件, 每行形如 `P:1A2B:UpdatePlayer:注释` 或 `R:0300-030F:Buffer`
+    ///
+    /// 类型 P 为 PRG ROM 偏移, R 为内部 RAM, S 与 W 为 $6000 起的 PRG RAM, G 为 CPU 地址
+    pub fn load_mesen_mlb(&mut self, text: &str) -> Result<(), SymbolError> {
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut fields = line.splitn(4, ':');
+            let (kind, addr, name) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(kind), Some(addr), Some(name)) => (kind, addr, name.trim()),
+                _ => return Err(error(i, format!("invalid label '{}'", line))),
+            };
+            let addr = addr.split('-').next().unwrap_or(addr);
+            let addr = u32::from_str_radix(addr, 16).map_err(|_| error(i, format!("invalid address '{}'", addr)))?;
+            if name.is_empty() {
+                continue; // 只有注释
+            }
+            let location = match kind {
+                "P" | "NesPrgRom" => SymbolLocation::PrgRom(addr),
+                "R" | "NesInternalRam" => SymbolLocation::Cpu((addr & 0x07ff) as u16),
+                "S" | "W" | "NesSaveRam" | "NesWorkRam" => SymbolLocation::Cpu(0x6000 + (addr & 0x1fff) as u16),
+                "G" | "NesMemory" => SymbolLocation::Cpu(addr as u16),
+                _ => continue, // CHR 等其他地址空间
+            };
+            self.insert(location, name);
+        }
+        Ok(())
+    }
+
+    /// 读取 ld65 的 `.dbg` 文件中的 `seg` 与 `sym` 行
+    ///
+    /// 位于 ROM 段中的标号按 `ooffs` 换算为 PRG ROM 偏移(假定文件头为 16 字节), 其他标号按 CPU 地址记录;
+    /// 常量(`type=equ`)与 `@` 开头的局部标号会被忽略
+    pub fn load_ld65_dbg(&mut self, text: &str) -> Result<(), SymbolError> {
+        let mut segments = HashMap::new(); // id -> (start, ooffs)
+        let mut syms = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let (kind, fields) = match line.split_once(|c: char| c.is_whitespace()) {
+                Some((kind @ ("seg" | "sym"), fields)) => (kind, parse_dbg_fields(fields.trim())),
+                _ => continue,
+            };
+            let number = |key: &str| -> Result<Option<u32>, SymbolError> {
+                fields.get(key).map(|value| parse_dbg_number(value).ok_or_else(|| {
+                    error(i, format!("invalid {} '{}'", key, value))
+                })).transpose()
+            };
+            match kind {
+                "seg" => {
+                    let id = number("id")?.ok_or_else(|| error(i, "segment without id".to_string()))?;
+                    let start = number("start")?.unwrap_or(0);
+                    segments.insert(id, (start, number("ooffs")?));
+                }
+                _ => {
+                    if fields.get("type").map(String::as_str) != Some("lab") {
+                        continue;
+                    }
+                    let name = fields.get("name").cloned().unwrap_or_default();
+                    if name.is_empty() || name.starts_with('@') {
+                        continue;
+                    }
+                    let val = number("val")?.ok_or_else(|| error(i, format!("symbol '{}' without value", name)))?;
+                    syms.push((name, val, number("seg")?));
+                }
+            }
+        }
+
+        for (name, val, seg) in syms {
+            let location = match seg.and_then(|seg| segments.get(&seg)) {
+                Some(&(start, Some(ooffs))) if ooffs >= 16 && val >= start => {
+                    SymbolLocation::PrgRom(val - start + ooffs - 16)
+                }
+                _ => SymbolLocation::Cpu(val as u16),
+            };
+            self.insert(location, &name);
+        }
+        Ok(())
+    }
+
+    /// 指令的操作数所引用的地址(跳转目标或访问的地址), 立即数与隐式寻址为 None
+    fn operand_addr(instruction: &Instruction) -> Option<u16> {
+        match instruction.mode {
+            AddressingMode::Relative | AddressingMode::Absolute if instruction.target.is_some() => instruction.target,
+            AddressingMode::Implied | AddressingMode::Accumulator | AddressingMode::Immediate
+            | AddressingMode::Relative | AddressingMode::Indirect_X | AddressingMode::Indirect_Y => None,
+            _ => instruction.operand,
+        }
+    }
+
+    /// 反汇编的文本, 操作数中的地址替换为符号, 如 `JSR UpdatePlayer`
+    pub fn format_instruction(&self, bus: &Bus, instruction: &Instruction) -> String {
+        let text = instruction.to_string();
+        match Self::operand_addr(instruction).and_then(|addr| self.label_at(bus, addr).map(|label| (addr, label))) {
+            Some((addr, label)) => {
+                let width = if instruction.bytes.len() == 2 && instruction.mode != AddressingMode::Relative { 2 } else { 4 };
+                text.replacen(&format!("${:0width$X}", addr, width = width), label, 1)
+            }
+            None => text,
+        }
+    }
+}
+
+/// 解析 `key=value,key="value"` 形式的字段
+fn parse_dbg_fields(fields: &str) -> HashMap<String, String> {
+    let mut map = HashMap::new();
+    let mut rest = fields;
+    while let Some((key, value)) = rest.split_once('=') {
+        let (value, next) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                (&quoted[..end], quoted[end..].trim_start_matches('"'))
+            }
+            None => value.split_at(value.find(',').unwrap_or(value.len())),
+        };
+        map.insert(key.trim().to_string(), value.to_string());
+        rest = next.trim_start_matches(',');
+    }
+    map
+}
+
+fn parse_dbg_number(value: &str) -> Option<u32> {
+    match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// 下一条指令的 trace, 跳转目标与访问的地址显示为总线上符号表(见 [`Bus::set_symbols`])中的符号
+pub fn trace(cpu: &Cpu) -> String {
+    trace_with_symbols(cpu, cpu.bus().symbols())
+}
+
+/// 与 `cpu_trace` 相同, 但使用给定的符号表, 跳转目标与访问的地址显示为符号, 如 `JSR UpdatePlayer`
+pub fn trace_with_symbols(cpu: &Cpu, symbols: &Symbols) -> String {
+    let line = trace_readonly(cpu);
+    let pc = cpu.registers().pc;
+    let instruction = disassemble(cpu.bus(), pc);
+    let label = Symbols::operand_addr(&instruction)
+        .and_then(|addr| symbols.label_at(cpu.bus(), addr).map(|label| (addr, label)));
+    let (addr, label) = match label {
+        Some(label) => label,
+        None => return line,
+    };
+
+    // 寄存器部分之前是按 47 字符对齐的指令
+    let (asm, registers) = line.split_at(line.rfind(" A:").unwrap_or(line.len()));
+    let width = if instruction.bytes.len() == 2 && instruction.mode != AddressingMode::Relative { 2 } else { 4 };
+    let asm = asm.trim_end().replacen(&format!("${:0width$X}", addr, width = width), label, 1);
+    format!("{:47}{}", asm, registers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cartridge::tests::test_rom_with_2_bank_prg, debugger::{Debugger, EvalContext, StopReason}};
+
+    #[test]
+    fn test_load_symbols() {
+        let mut symbols = Symbols::new();
+        symbols.load_ld65_dbg(concat!(
+            "version\tmajor=2,minor=0\n",
+            "seg\tid=0,name=\"ZEROPAGE\",start=0x000000,size=0x0010,addrsize=zeropage,type=rw\n",
+            "seg\tid=1,name=\"CODE\",start=0x00C000,size=0x0100,addrsize=absolute,type=ro,oname=\"game.nes\",ooffs=16400\n",
+            "sym\tid=0,name=\"UpdatePlayer\",addrsize=absolute,scope=0,def=1,val=0xC4A2,seg=1,type=lab\n",
+            "sym\tid=1,name=\"player_x\",addrsize=zeropage,scope=0,def=2,val=0x10,seg=0,type=lab\n",
+            "sym\tid=2,name=\"SPEED\",addrsize=zeropage,scope=0,def=3,val=0x3,type=equ\n",
+            "sym\tid=3,name=\"@loop\",addrsize=absolute,scope=0,def=4,val=0xC4A8,seg=1,type=lab,parent=0\n",
+        )).unwrap();
+        assert_eq!(symbols.location("UpdatePlayer"), Some(SymbolLocation::PrgRom(0x44a2)));
+        assert_eq!(symbols.location("player_x"), Some(SymbolLocation::Cpu(0x10)));
+        assert_eq!(symbols.location("SPEED"), None);
+        assert_eq!(symbols.location("@loop"), None);
+
+        symbols.load_fceux_nl("$C000#Reset#entry\n$0300/10#Buffer#\n", Some(1)).unwrap();
+        assert_eq!(symbols.location("Reset"), Some(SymbolLocation::PrgRom(0x4000)));
+        assert_eq!(symbols.location("Buffer"), Some(SymbolLocation::Cpu(0x300)));
+
+        symbols.load_mesen_mlb("P:0010:Nmi:comment\nR:0700-07FF:Stack\nP:0020::only comment\nG:2000:PPUCTRL\n").unwrap();
+        assert_eq!(symbols.location("Nmi"), Some(SymbolLocation::PrgRom(0x10)));
+        assert_eq!(symbols.location("Stack"), Some(SymbolLocation::Cpu(0x700)));
+        assert_eq!(symbols.location("PPUCTRL"), Some(SymbolLocation::Cpu(0x2000)));
+        assert_eq!(symbols.len(), 7);
+
+        assert_eq!(symbols.load_fceux_nl("C000#Oops#\n", None).unwrap_err().line, 1);
+        assert_eq!(symbols.load_mesen_mlb("P:0000:A\nP:zz:B\n").unwrap_err().line, 2);
+    }
+
+    #[test]
+    fn test_trace_with_symbols() {
+        let mut prg = vec![0xea; 0x8000];
+        prg[..8].copy_from_slice(&[
+            0x20, 0xa2, 0xc4, // 8000 JSR $C4A2
+            0xa5, 0x10, // 8003 LDA $10
+            0xd0, 0xfb, // 8005 BNE $8002
+            0x00,
+        ]);
+        let mut cpu = Cpu::new(test_rom_with_2_bank_prg(prg));
+        cpu.reset();
+        let mut symbols = Symbols::new();
+        symbols.load_mesen_mlb("P:44A2:UpdatePlayer\nR:0010:player_x\nP:0002:Loop\n").unwrap();
+
+        assert_eq!(
+            trace_with_symbols(&cpu, &symbols),
+            "8000  20 A2 C4  JSR UpdatePlayer                A:00 X:00 Y:00 P:24 SP:FD"
+        );
+        assert_eq!(symbols.resolve(cpu.bus(), "UpdatePlayer"), Some(0xc4a2));
+        assert_eq!(symbols.label_at(cpu.bus(), 0x8002), Some("Loop"));
+
+        let lda = disassemble(cpu.bus(), 0x8003);
+        assert_eq!(symbols.format_instruction(cpu.bus(), &lda), "LDA player_x");
+        let bne = disassemble(cpu.bus(), 0x8005);
+        assert_eq!(symbols.format_instruction(cpu.bus(), &bne), "BNE Loop");
+
+        // 没有 bank 信息的 $8000 以上的符号按 CPU 地址查找
+        let mut plain = Symbols::new();
+        plain.load_fceux_nl("$C4A2#UpdatePlayer#\n", None).unwrap();
+        assert_eq!(plain.location("UpdatePlayer"), Some(SymbolLocation::Cpu(0xc4a2)));
+        assert_eq!(plain.label_at(cpu.bus(), 0xc4a2), Some("UpdatePlayer"));
+
+        // cpu_trace 使用总线上的符号表
+        assert!(trace(&cpu).starts_with("8000  20 A2 C4  JSR $C4A2 "));
+        cpu.bus_mut().set_symbols(plain);
+        assert!(trace(&cpu).starts_with("8000  20 A2 C4  JSR UpdatePlayer "));
+
+        let breakpoint = symbols.breakpoint("UpdatePlayer", BreakOn::EXEC).unwrap();
+        let mut debugger = Debugger::new(cpu);
+        debugger.set_symbols(symbols);
+        let id = debugger.add_breakpoint(breakpoint);
+        assert_eq!(debugger.run_frame(), StopReason::Breakpoint { id, pc: 0xc4a2 });
+        let expr = debugger.parse_expr("PC == UpdatePlayer && [player_x] == 0").unwrap();
+        assert_eq!(expr.eval(&EvalContext { cpu: debugger.cpu(), access: None }), 1);
+    }
+}
@@ -0,0 +1,390 @@
+//! 指令日志, 按格式模板将每条指令执行前的状态写入文件
+//!
+//! 日志通过 [`Cpu::set_instruction_hook`] 挂在 CPU 上, 因而 `run_next_frame` 等也会记录,
+//! 不必经过调试器逐条执行.
+//!
+//! 模板中 `[Token]` 或 `[Token,宽度]` 会被替换, 其余字符原样输出. 可用的 Token:
+//! - `PC`, `A`, `X`, `Y`, `P`, `SP`: 寄存器, 十六进制
+//! - `Flags`: 状态寄存器, 置位的标志为大写字母, 如 `nvUbdIzc`
+//! - `ByteCode`: 指令的字节, 如 `4C F5 C5`; `Disassembly`: 反汇编, 如 `JMP $C5F5`
+//! - `CycleCount`: 上电以来的 CPU 周期数; `Scanline`, `Cycle`: PPU 扫描线与周期; `Frame`: 帧数
+//! - `Bank`: PC 所在的 16KB PRG bank; `RomOffset`: PC 对应的 PRG ROM 偏移
+//!
+//! 十进制的数值右对齐, 其余左对齐.
+
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::{
+    Cpu,
+    cpu::{disassemble, Instruction, InstructionHook},
+    debugger::{EvalContext, Expr, ExprError},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Pc,
+    A,
+    X,
+    Y,
+    P,
+    Sp,
+    Flags,
+    ByteCode,
+    Disassembly,
+    CycleCount,
+    Scanline,
+    Cycle,
+    Frame,
+    Bank,
+    RomOffset,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        let field = match name {
+            "PC" => Field::Pc,
+            "A" => Field::A,
+            "X" => Field::X,
+            "Y" => Field::Y,
+            "P" => Field::P,
+            "SP" => Field::Sp,
+            "Flags" => Field::Flags,
+            "ByteCode" => Field::ByteCode,
+            "Disassembly" => Field::Disassembly,
+            "CycleCount" => Field::CycleCount,
+            "Scanline" => Field::Scanline,
+            "Cycle" => Field::Cycle,
+            "Frame" => Field::Frame,
+            "Bank" => Field::Bank,
+            "RomOffset" => Field::RomOffset,
+            _ => return None,
+        };
+        Some(field)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Text(String),
+    Field(Field, usize),
+}
+
+/// 指令日志的格式
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceFormat {
+    tokens: Vec<Token>,
+}
+
+impl TraceFormat {
+    pub fn parse(template: &str) -> Result<Self, ExprError> {
+        let mut tokens = vec![];
+        let mut pos = 0;
+        while let Some(start) = template[pos..].find('[') {
+            let start = pos + start;
+            if start > pos {
+                tokens.push(Token::Text(template[pos..start].to_string()));
+            }
+            let end = template[start..].find(']').map(|end| start + end).ok_or(ExprError {
+                pos: start,
+                message: "unclosed '['".to_string(),
+            })?;
+            let (name, width) = match template[start + 1..end].split_once(',') {
+                Some((name, width)) => (name, width.trim().parse::<usize>().map_err(|_| ExprError {
+                    pos: start,
+                    message: format!("invalid width '{}'", width),
+                })?),
+                None => (&template[start + 1..end], 0),
+            };
+            let field = Field::from_name(name.trim()).ok_or(ExprError {
+                pos: start + 1,
+                message: format!("unknown token '{}'", name),
+            })?;
+            tokens.push(Token::Field(field, width));
+            pos = end + 1;
+        }
+        if pos < template.len() {
+            tokens.push(Token::Text(template[pos..].to_string()));
+        }
+        Ok(TraceFormat { tokens })
+    }
+
+    /// nestest.log 的格式, 但反汇编中不含内存的值
+    pub fn nestest() -> Self {
+        Self::parse("[PC,6][ByteCode,10][Disassembly,32]A:[A] X:[X] Y:[Y] P:[P] SP:[SP] PPU:[Scanline,3],[Cycle,3] CYC:[CycleCount]").unwrap()
+    }
+
+    /// 与 Mesen 的默认格式相近
+    pub fn mesen() -> Self {
+        Self::parse("[PC,4]  [Disassembly,30] A:[A] X:[X] Y:[Y] P:[P] SP:[SP] CYC:[Cycle,3] SL:[Scanline,3] FC:[Frame] CPU Cycle:[CycleCount]").unwrap()
+    }
+
+    /// 与 FCEUX 的格式相近
+    pub fn fceux() -> Self {
+        Self::parse("$[PC]:[ByteCode,9] [Disassembly,30] A:[A] X:[X] Y:[Y] S:[SP] P:[Flags]").unwrap()
+    }
+
+    /// 将 cpu 下一条指令的信息按格式写入 line
+    pub fn format(&self, cpu: &Cpu, line: &mut String) {
+        let instruction = if self.tokens.iter().any(|token| matches!(token, Token::Field(Field::ByteCode | Field::Disassembly, _))) {
+            Some(disassemble(cpu.bus(), cpu.registers().pc))
+        } else {
+            None
+        };
+        self.format_instruction(cpu, instruction.as_ref(), line);
+    }
+
+    /// 与 `format` 相同, 但使用已经解码的指令, 模板不含 `ByteCode` 与 `Disassembly` 时可以为 None
+    fn format_instruction(&self, cpu: &Cpu, instruction: Option<&Instruction>, line: &mut String) {
+        let registers = cpu.registers();
+        let bus = cpu.bus();
+        let mut value = String::new();
+        for token in self.tokens.iter() {
+            let (field, width) = match token {
+                Token::Text(text) => {
+                    line.push_str(text);
+                    continue;
+                }
+                Token::Field(field, width) => (*field, *width),
+            };
+            value.clear();
+            let _ = match field {
+                Field::Pc => write!(value, "{:04X}", registers.pc),
+                Field::A => write!(value, "{:02X}", registers.a),
+                Field::X => write!(value, "{:02X}", registers.x),
+                Field::Y => write!(value, "{:02X}", registers.y),
+                Field::P => write!(value, "{:02X}", registers.p),
+                Field::Sp => write!(value, "{:02X}", registers.sp),
+                Field::Flags => {
+                    for (i, flag) in "NVUBDIZC".chars().enumerate() {
+                        let set = registers.p & (0x80 >> i) != 0;
+                        value.push(if set { flag } else { flag.to_ascii_lowercase() });
+                    }
+                    Ok(())
+                }
+                Field::ByteCode => {
+                    let bytes = instruction.map_or(&[][..], |instruction| &instruction.bytes[..]);
+                    for (i, byte) in bytes.iter().enumerate() {
+                        let _ = write!(value, "{}{:02X}", if i == 0 { "" } else { " " }, byte);
+                    }
+                    Ok(())
+                }
+                Field::Disassembly => match instruction {
+                    Some(instruction) => write!(value, "{}", instruction),
+                    None => Ok(()),
+                },
+                Field::CycleCount => write!(value, "{}", bus.cycles()),
+                Field::Scanline => write!(value, "{}", bus.scanline()),
+                Field::Cycle => write!(value, "{}", bus.ppu_cycle()),
+                Field::Frame => write!(value, "{}", bus.frame_count()),
+                Field::Bank => match bus.prg_rom_offset(registers.pc) {
+                    Some(offset) => write!(value, "{:02X}", offset / 0x4000),
+                    None => write!(value, "--"),
+                },
+                Field::RomOffset => match bus.prg_rom_offset(registers.pc) {
+                    Some(offset) => write!(value, "{:05X}", offset),
+                    None => write!(value, "-----"),
+                },
+            };
+            let right_aligned = matches!(field, Field::CycleCount | Field::Scanline | Field::Cycle | Field::Frame);
+            let _ = if right_aligned {
+                write!(line, "{:>width$}", value, width = width)
+            } else {
+                write!(line, "{:<width$}", value, width = width)
+            };
+        }
+    }
+}
+
+/// 指令日志
+pub struct TraceLogger {
+    format: TraceFormat,
+    writer: Box<dyn Write>,
+    start: Option<Expr>,
+    stop: Option<Expr>,
+    active: bool,
+    lines: u64,
+    line: String,
+}
+
+impl TraceLogger {
+    pub fn new<W: Write + 'static>(format: TraceFormat, writer: W) -> Self {
+        TraceLogger {
+            format,
+            writer: Box::new(writer),
+            start: None,
+            stop: None,
+            active: true,
+            lines: 0,
+            line: String::new(),
+        }
+    }
+
+    /// 写入到带缓冲的文件
+    pub fn create<P: AsRef<Path>>(path: P, format: TraceFormat) -> io::Result<Self> {
+        Ok(Self::new(format, BufWriter::with_capacity(1 << 20, File::create(path)?)))
+    }
+
+    /// 条件成立时才开始记录, 开始后不再检查
+    pub fn set_start_condition(&mut self, condition: Option<Expr>) {
+        self.active = condition.is_none();
+        self.start = condition;
+    }
+
+    /// 条件成立时停止记录, 之后若有开始条件则重新等待其成立
+    pub fn set_stop_condition(&mut self, condition: Option<Expr>) {
+        self.stop = condition;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// 已写入的行数
+    pub fn lines(&self) -> u64 {
+        self.lines
+    }
+
+    /// 在 cpu 执行下一条指令之前调用; 挂在 CPU 上时由 CPU 自动调用
+    pub fn log(&mut self, cpu: &Cpu) -> io::Result<()> {
+        let instruction = disassemble(cpu.bus(), cpu.registers().pc);
+        self.log_instruction(cpu, &instruction)
+    }
+
+    fn log_instruction(&mut self, cpu: &Cpu, instruction: &Instruction) -> io::Result<()> {
+        let ctx = EvalContext { cpu, access: None };
+        if !self.active {
+            match self.start.as_ref() {
+                Some(start) if start.eval(&ctx) != 0 => self.active = true,
+                _ => return Ok(()),
+            }
+        } else if self.stop.as_ref().is_some_and(|stop| stop.eval(&ctx) != 0) {
+            self.active = false;
+            return self.writer.flush();
+        }
+
+        self.line.clear();
+        self.format.format_instruction(cpu, Some(instruction), &mut self.line);
+        self.line.push('\n');
+        self.lines += 1;
+        self.writer.write_all(self.line.as_bytes())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl InstructionHook for TraceLogger {
+    fn before_instruction(&mut self, cpu: &Cpu, instruction: &Instruction) {
+        if let Err(e) = self.log_instruction(cpu, instruction) {
+            log::warn!("Failed to write trace log, tracing stopped: {}", e);
+            self.active = false;
+            self.start = None;
+        }
+    }
+}
+
+impl Drop for TraceLogger {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cartridge::tests::test_rom_with_2_bank_prg, debugger::Debugger};
+    use std::{cell::RefCell, rc::Rc};
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn cpu() -> Cpu {
+        let mut cpu = Cpu::new(test_rom_with_2_bank_prg(vec![
+            0xa2, 0x00, // 8000 LDX #$00
+            0xe8, // 8002 INX
+            0x86, 0x10, // 8003 STX $10
+            0x4c, 0x02, 0x80, // 8005 JMP $8002
+        ]));
+        cpu.reset();
+        cpu
+    }
+
+    #[test]
+    fn test_formats() {
+        let cpu = cpu();
+        let mut line = String::new();
+        TraceFormat::nestest().format(&cpu, &mut line);
+        assert_eq!(line, "8000  A2 00     LDX #$00                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7");
+
+        line.clear();
+        TraceFormat::fceux().format(&cpu, &mut line);
+        assert_eq!(line, "$8000:A2 00     LDX #$00                       A:00 X:00 Y:00 S:FD P:nvUbdIzc");
+
+        line.clear();
+        TraceFormat::parse("[Bank]:[RomOffset] [Frame,3]|").unwrap().format(&cpu, &mut line);
+        assert_eq!(line, "00:00000   0|");
+
+        assert_eq!(TraceFormat::parse("[PC").unwrap_err().message, "unclosed '['");
+        assert_eq!(TraceFormat::parse("[Foo,2]").unwrap_err().message, "unknown token 'Foo'");
+    }
+
+    #[test]
+    fn test_start_and_stop_conditions() {
+        let buffer = SharedBuffer::default();
+        let mut logger = TraceLogger::new(TraceFormat::parse("[PC] X:[X]").unwrap(), buffer.clone());
+        logger.set_start_condition(Some(Expr::parse("X == 2").unwrap()));
+        logger.set_stop_condition(Some(Expr::parse("X == 3 && PC == $8005").unwrap()));
+
+        let mut debugger = Debugger::new(cpu());
+        debugger.set_trace_logger(Some(logger));
+        for _ in 0..12 {
+            debugger.step_into();
+        }
+        let logger = debugger.take_trace_logger().unwrap();
+        assert_eq!(logger.lines(), 4);
+        drop(logger);
+        assert_eq!(
+            String::from_utf8(buffer.0.borrow().clone()).unwrap(),
+            "8003 X:02\n8005 X:02\n8002 X:02\n8003 X:03\n"
+        );
+    }
+
+    #[test]
+    fn test_attached_to_cpu() {
+        let buffer = SharedBuffer::default();
+        let mut traced = cpu();
+        traced.set_instruction_hook(Some(Box::new(TraceLogger::new(TraceFormat::nestest(), buffer.clone()))));
+        let mut expected = cpu();
+        let mut lines = String::new();
+        for _ in 0..20 {
+            TraceFormat::nestest().format(&expected, &mut lines);
+            lines.push('\n');
+            expected.run_next_instruction();
+            traced.run_next_instruction();
+        }
+        assert_eq!(String::from_utf8(buffer.0.borrow().clone()).unwrap(), lines);
+
+        // 不经过调试器运行整帧时同样记录
+        traced.run_next_frame().unwrap();
+        assert!(traced.instruction_hook::<TraceLogger>().unwrap().lines() > 1000);
+        assert!(traced.take_instruction_hook::<TraceLogger>().is_some());
+        assert!(traced.instruction_hook::<TraceLogger>().is_none());
+    }
+}
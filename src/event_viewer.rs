@@ -0,0 +1,175 @@
+//! 事件查看器, 按扫描线与点记录一帧中的寄存器写入与 PPU/中断事件, 用于调试分屏滚动等光栅效果
+//!
+//! 通过 [`Bus::set_event_viewer_enabled`](crate::Bus::set_event_viewer_enabled) 开启.
+//! 一帧从扫描线 0 开始, 到预渲染扫描线 261 结束.
+
+/// 事件类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameEventKind {
+    /// 写 PPU 寄存器($2000-$2007 及其镜像, $4014), addr 为镜像前的地址
+    PpuRegisterWrite { addr: u16, data: u8 },
+    /// 写 APU 与手柄寄存器($4000-$4017 中除 $4014 以外)
+    IoRegisterWrite { addr: u16, data: u8 },
+    /// 写卡带地址空间($4020-$5FFF 与 $8000-$FFFF)
+    MapperRegisterWrite { addr: u16, data: u8 },
+    Nmi,
+    Irq,
+    Sprite0Hit,
+    VblankStart,
+}
+
+impl FrameEventKind {
+    /// 在图像中使用的颜色(RGB)
+    pub fn color(&self) -> [u8; 3] {
+        match self {
+            FrameEventKind::PpuRegisterWrite { addr, .. } if *addr == 0x4014 => [0xff, 0x80, 0xff],
+            FrameEventKind::PpuRegisterWrite { addr, .. } => match addr & 0x7 {
+                0 => [0xff, 0x40, 0x40], // PPUCTRL
+                1 => [0x80, 0xff, 0x40], // PPUMASK
+                5 => [0xff, 0xc0, 0x00], // PPUSCROLL
+                6 => [0x40, 0x80, 0xff], // PPUADDR
+                7 => [0x40, 0xff, 0xff], // PPUDATA
+                _ => [0xc0, 0xc0, 0xc0],
+            },
+            FrameEventKind::IoRegisterWrite { .. } => [0x90, 0x60, 0x30],
+            FrameEventKind::MapperRegisterWrite { .. } => [0xb0, 0x40, 0xff],
+            FrameEventKind::Nmi => [0xff, 0xff, 0x40],
+            FrameEventKind::Irq => [0xff, 0x80, 0x00],
+            FrameEventKind::Sprite0Hit => [0x40, 0xff, 0x80],
+            FrameEventKind::VblankStart => [0xff, 0xff, 0xff],
+        }
+    }
+}
+
+/// 一个事件及其发生时的光栅位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameEvent {
+    pub scanline: u16,
+    /// 扫描线内的 PPU 周期, 0..341
+    pub dot: u16,
+    pub kind: FrameEventKind,
+}
+
+/// 事件记录, 保存当前帧与上一个完整帧
+pub struct EventViewer {
+    current: Vec<FrameEvent>,
+    last_frame: Vec<FrameEvent>,
+}
+
+impl EventViewer {
+    /// 图像宽度, 即每条扫描线的 PPU 周期数
+    pub const WIDTH: usize = 341;
+    /// 图像高度, 即每帧的扫描线数
+    pub const HEIGHT: usize = 262;
+
+    pub(crate) fn new() -> Self {
+        EventViewer {
+            current: vec![],
+            last_frame: vec![],
+        }
+    }
+
+    pub(crate) fn push(&mut self, scanline: u16, dot: u16, kind: FrameEventKind) {
+        self.current.push(FrameEvent { scanline, dot, kind });
+    }
+
+    pub(crate) fn end_frame(&mut self) {
+        self.last_frame = std::mem::take(&mut self.current);
+    }
+
+    /// 上一个完整帧的事件, 按发生顺序排列
+    pub fn events(&self) -> &[FrameEvent] {
+        &self.last_frame
+    }
+
+    /// 当前帧到目前为止的事件
+    pub fn current_events(&self) -> &[FrameEvent] {
+        &self.current
+    }
+
+    /// 上一帧中发生在 scanline 上的事件
+    pub fn events_on_scanline(&self, scanline: u16) -> impl Iterator<Item = &FrameEvent> {
+        self.last_frame.iter().filter(move |event| event.scanline == scanline)
+    }
+
+    /// 上一帧的图像中覆盖 (dot, scanline) 的事件, 有多个时取最后画上的
+    pub fn event_at(&self, scanline: u16, dot: u16) -> Option<&FrameEvent> {
+        self.last_frame.iter().rev().find(|event| {
+            event.scanline.abs_diff(scanline) <= 1 && event.dot.abs_diff(dot) <= 1
+        })
+    }
+
+    /// 把上一帧的事件画成 341x262 的 RGBA 图像, 每个事件为 3x3 的方块, 其余部分透明,
+    /// 可以叠加在同样大小的画面上(可见区域位于第 1-256 点, 第 0-239 扫描线)
+    pub fn render(&self) -> Vec<u8> {
+        let mut image = vec![0u8; Self::WIDTH * Self::HEIGHT * 4];
+        for event in &self.last_frame {
+            let [r, g, b] = event.kind.color();
+            for y in event.scanline.saturating_sub(1)..=event.scanline + 1 {
+                for x in event.dot.saturating_sub(1)..=event.dot + 1 {
+                    let (x, y) = (x as usize, y as usize);
+                    if x < Self::WIDTH && y < Self::HEIGHT {
+                        let idx = (y * Self::WIDTH + x) * 4;
+                        image[idx..idx + 4].copy_from_slice(&[r, g, b, 0xff]);
+                    }
+                }
+            }
+        }
+        image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cpu, cartridge::tests::test_rom_with_2_bank_prg};
+
+    #[test]
+    fn test_record_frame_events() {
+        let mut prg = vec![0xea; 0x8000];
+        let program: &[u8] = &[
+            0xa9, 0x80, // 8000 LDA #$80
+            0x8d, 0x00, 0x20, // 8002 STA $2000
+            0x4c, 0x05, 0x80, // 8005 JMP $8005
+            0x8d, 0x05, 0x20, // 8008 STA $2005
+            0x8d, 0x05, 0x20, // 800B STA $2005
+            0x40, // 800E RTI
+        ];
+        prg[..program.len()].copy_from_slice(program);
+        prg[0x7ffa..0x7ffc].copy_from_slice(&[0x08, 0x80]); // NMI 向量
+        let mut cpu = Cpu::new(test_rom_with_2_bank_prg(prg));
+        cpu.reset();
+        cpu.bus_mut().set_event_viewer_enabled(true);
+        for _ in 0..3 {
+            cpu.run_next_frame().unwrap();
+        }
+
+        let viewer = cpu.bus().event_viewer().unwrap();
+        let events = viewer.events();
+        let kinds: Vec<_> = events.iter().map(|event| event.kind).collect();
+        assert_eq!(kinds, vec![
+            FrameEventKind::VblankStart,
+            FrameEventKind::Nmi,
+            FrameEventKind::PpuRegisterWrite { addr: 0x2005, data: 0x80 },
+            FrameEventKind::PpuRegisterWrite { addr: 0x2005, data: 0x80 },
+        ]);
+        assert_eq!(events[0].scanline, 241);
+        assert!((1..4).contains(&events[0].dot));
+        assert!(events.iter().all(|event| event.scanline == 241));
+        assert!(events.windows(2).all(|pair| pair[0].scanline < pair[1].scanline || pair[0].dot < pair[1].dot));
+        // 两次写入相隔 4 个 CPU 周期
+        assert_eq!(events[3].dot - events[2].dot, 12);
+        assert_eq!(viewer.events_on_scanline(241).count(), 4);
+        assert_eq!(viewer.event_at(242, events[3].dot + 1), Some(&events[3]));
+        assert_eq!(viewer.event_at(100, 100), None);
+
+        let image = viewer.render();
+        assert_eq!(image.len(), 341 * 262 * 4);
+        let pixel = |x: usize, y: usize| &image[(y * 341 + x) * 4..(y * 341 + x) * 4 + 4];
+        assert_eq!(pixel(events[2].dot as usize, 241), &[0xff, 0xc0, 0x00, 0xff]);
+        assert_eq!(pixel(100, 100), &[0, 0, 0, 0]);
+
+        cpu.bus_mut().set_event_viewer_enabled(false);
+        assert!(cpu.bus().event_viewer().is_none());
+    }
+}
@@ -0,0 +1,187 @@
+//! 总线事件回调, 供前端与调试工具观察模拟器内部
+//!
+//! 通过 [`Bus::add_hook`](crate::Bus::add_hook) 注册. 没有注册任何回调时, 每次访存只多一次位检查.
+
+use bitflags::bitflags;
+
+use crate::Interrupt;
+
+bitflags! {
+    /// 回调关心的事件类型
+    pub struct HookEvents: u16 {
+        const CPU_READ = 1 << 0;
+        const CPU_WRITE = 1 << 1;
+        const PPU_REGISTER_WRITE = 1 << 2;
+        const APU_REGISTER_WRITE = 1 << 3;
+        const MAPPER_REGISTER_WRITE = 1 << 4;
+        const INTERRUPT = 1 << 5;
+        const SCANLINE = 1 << 6;
+        const FRAME_END = 1 << 7;
+    }
+}
+
+/// 总线事件, cycle 为事件发生时上电以来的 CPU 周期数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusEvent {
+    /// CPU 的读取, 包括取指与空读, 不含 DMA
+    CpuRead { addr: u16, data: u8, cycle: u64 },
+    /// CPU 的写入
+    CpuWrite { addr: u16, data: u8, cycle: u64 },
+    /// 写 PPU 寄存器($2000-$3FFF 与 $4014), addr 为镜像前的地址
+    PpuRegisterWrite { addr: u16, data: u8, cycle: u64 },
+    /// 写 APU 寄存器($4000-$4013, $4015 与 $4017)
+    ApuRegisterWrite { addr: u16, data: u8, cycle: u64 },
+    /// 写卡带地址空间($4020-$5FFF 与 $8000-$FFFF), 有 mapper 的卡带据此切换 bank
+    MapperRegisterWrite { addr: u16, data: u8, cycle: u64 },
+    /// CPU 进入中断处理程序(包括 BRK)
+    Interrupt { kind: Interrupt, cycle: u64 },
+    /// PPU 开始一条新的扫描线
+    Scanline { scanline: u16, frame: u64, cycle: u64 },
+    /// 到达帧末(vblank 开始), frame 为已完成的帧数
+    FrameEnd { frame: u64, cycle: u64 },
+}
+
+impl BusEvent {
+    pub fn kind(&self) -> HookEvents {
+        match self {
+            BusEvent::CpuRead { .. } => HookEvents::CPU_READ,
+            BusEvent::CpuWrite { .. } => HookEvents::CPU_WRITE,
+            BusEvent::PpuRegisterWrite { .. } => HookEvents::PPU_REGISTER_WRITE,
+            BusEvent::ApuRegisterWrite { .. } => HookEvents::APU_REGISTER_WRITE,
+            BusEvent::MapperRegisterWrite { .. } => HookEvents::MAPPER_REGISTER_WRITE,
+            BusEvent::Interrupt { .. } => HookEvents::INTERRUPT,
+            BusEvent::Scanline { .. } => HookEvents::SCANLINE,
+            BusEvent::FrameEnd { .. } => HookEvents::FRAME_END,
+        }
+    }
+}
+
+/// 回调的编号, 用于移除
+pub type HookId = usize;
+
+struct Hook {
+    events: HookEvents,
+    callback: Box<dyn FnMut(&BusEvent)>,
+}
+
+/// 已注册的回调
+pub(crate) struct Hooks {
+    hooks: Vec<Option<Hook>>,
+    events: HookEvents, // 所有回调关心的事件的并集
+    muted: bool, // 暂停触发所有回调
+}
+
+impl Hooks {
+    pub fn new() -> Self {
+        Hooks {
+            hooks: vec![],
+            events: HookEvents::empty(),
+            muted: false,
+        }
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    pub fn add(&mut self, events: HookEvents, callback: Box<dyn FnMut(&BusEvent)>) -> HookId {
+        self.hooks.push(Some(Hook { events, callback }));
+        self.events |= events;
+        self.hooks.len() - 1
+    }
+
+    pub fn remove(&mut self, id: HookId) -> bool {
+        let removed = self.hooks.get_mut(id).and_then(|hook| hook.take()).is_some();
+        self.events = self.hooks.iter().flatten().fold(HookEvents::empty(), |events, hook| events | hook.events);
+        removed
+    }
+
+    /// 是否有回调关心这些事件中的任何一个, 暂停时总是 false
+    #[inline]
+    pub fn wants(&self, events: HookEvents) -> bool {
+        !self.muted && self.events.intersects(events)
+    }
+
+    pub fn emit(&mut self, event: BusEvent) {
+        let kind = event.kind();
+        for hook in self.hooks.iter_mut().flatten() {
+            if hook.events.contains(kind) {
+                (hook.callback)(&event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cpu, cartridge::tests::test_rom_with_2_bank_prg};
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn test_hooks() {
+        let mut prg = vec![0xea; 0x8000];
+        let program: &[u8] = &[
+            0xa9, 0x80, // 8000 LDA #$80
+            0x8d, 0x00, 0x20, // 8002 STA $2000
+            0x8d, 0x15, 0x40, // 8005 STA $4015
+            0x8d, 0x00, 0x80, // 8008 STA $8000
+            0xa5, 0x10, // 800B LDA $10
+            0x4c, 0x0b, 0x80, // 800D JMP $800B
+            0x40, // 8010 RTI
+        ];
+        prg[..program.len()].copy_from_slice(program);
+        prg[0x7ffa..0x7ffc].copy_from_slice(&[0x10, 0x80]); // NMI 向量
+        let mut cpu = Cpu::new(test_rom_with_2_bank_prg(prg));
+        cpu.reset();
+
+        let writes = Rc::new(RefCell::new(vec![]));
+        let events = Rc::new(RefCell::new(vec![]));
+        let reads = Rc::new(RefCell::new(0));
+        let writes_hook = {
+            let writes = writes.clone();
+            cpu.bus_mut().add_hook(
+                HookEvents::PPU_REGISTER_WRITE | HookEvents::APU_REGISTER_WRITE | HookEvents::MAPPER_REGISTER_WRITE,
+                move |event| writes.borrow_mut().push(*event),
+            )
+        };
+        {
+            let events = events.clone();
+            cpu.bus_mut().add_hook(HookEvents::INTERRUPT | HookEvents::FRAME_END, move |event| events.borrow_mut().push(*event));
+        }
+        let scanlines = Rc::new(RefCell::new(0));
+        {
+            let scanlines = scanlines.clone();
+            cpu.bus_mut().add_hook(HookEvents::SCANLINE, move |_| *scanlines.borrow_mut() += 1);
+        }
+        {
+            let reads = reads.clone();
+            cpu.bus_mut().add_hook(HookEvents::CPU_READ, move |event| {
+                if let BusEvent::CpuRead { addr: 0x0010, .. } = event {
+                    *reads.borrow_mut() += 1;
+                }
+            });
+        }
+
+        cpu.run_next_frame().unwrap();
+        cpu.run_next_frame().unwrap();
+
+        // 复位后 7 个周期, LDA #$80 2 个周期, STA 在第 4 个周期写入
+        assert_eq!(*writes.borrow(), vec![
+            BusEvent::PpuRegisterWrite { addr: 0x2000, data: 0x80, cycle: 12 },
+            BusEvent::ApuRegisterWrite { addr: 0x4015, data: 0x80, cycle: 16 },
+            BusEvent::MapperRegisterWrite { addr: 0x8000, data: 0x80, cycle: 20 },
+        ]);
+        let events = events.borrow();
+        assert_eq!(events.len(), 3);
+        assert!(matches!(events[0], BusEvent::FrameEnd { frame: 0, .. }));
+        assert!(matches!(events[1], BusEvent::Interrupt { kind: Interrupt::Nmi, .. }));
+        assert!(matches!(events[2], BusEvent::FrameEnd { frame: 1, .. }));
+        // 上电后第一帧在扫描线 241 结束
+        assert_eq!(*scanlines.borrow(), 241 + 262);
+        assert!(*reads.borrow() > 1000);
+
+        assert!(cpu.bus_mut().remove_hook(writes_hook));
+        assert!(!cpu.bus_mut().remove_hook(writes_hook));
+    }
+}
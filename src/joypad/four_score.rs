@@ -0,0 +1,92 @@
+use crate::save_state::{StateWriter, StateReader, SaveStateError};
+use super::{Beam, ButtonFlags, ControllerPort, InputDevice};
+
+/// NES Four Score, 每个端口连接两个手柄, 共 4 名玩家
+///
+/// 端口 1 依次读出玩家 1, 玩家 3 的 8 个按键与 8 位签名, 端口 2 为玩家 2 与玩家 4.
+/// 签名从低位起读出, 端口 1 为 0,0,0,1,0,0,0,0, 端口 2 为 0,0,1,0,0,0,0,0. 24 位之后读出 1
+pub struct FourScore {
+    strobe: bool,
+    bit_idx: u8, // 0..=24
+    signature: u8,
+    pub(crate) pads: [ButtonFlags; 2],
+}
+
+impl FourScore {
+    /// 一个 Four Score 同时连接两个端口, 每个端口各需要一个
+    pub fn new(port: ControllerPort) -> Self {
+        FourScore {
+            strobe: false,
+            bit_idx: 0,
+            signature: match port {
+                ControllerPort::Port1 => 0b0000_1000,
+                ControllerPort::Port2 => 0b0000_0100,
+            },
+            pads: [ButtonFlags::empty(); 2],
+        }
+    }
+
+    /// pad 为 0 时是玩家 1 或 2 的手柄, 为 1 时是玩家 3 或 4 的手柄, 格式同 `Joypad::set_buttons`
+    pub fn set_buttons(&mut self, pad: usize, buttons: u8) {
+        self.pads[pad] = ButtonFlags::from_bits_truncate(buttons);
+    }
+
+    pub fn buttons(&self, pad: usize) -> u8 {
+        self.pads[pad].bits()
+    }
+}
+
+impl InputDevice for FourScore {
+    fn name(&self) -> &'static str {
+        "four score"
+    }
+
+    fn write(&mut self, out: u8) {
+        self.strobe = out & 1 == 1;
+        if self.strobe {
+            self.bit_idx = 0;
+        }
+    }
+
+    fn read(&mut self, beam: &Beam) -> u8 {
+        let result = self.peek(beam);
+        if !self.strobe && self.bit_idx < 24 {
+            self.bit_idx += 1;
+        }
+        result
+    }
+
+    fn peek(&self, _beam: &Beam) -> u8 {
+        let bits = match self.bit_idx {
+            0..=7 => self.pads[0].bits(),
+            8..=15 => self.pads[1].bits(),
+            16..=23 => self.signature,
+            _ => return 1,
+        };
+        (bits >> (self.bit_idx % 8)) & 0x1
+    }
+
+    fn power_on(&mut self) {
+        self.strobe = false;
+        self.bit_idx = 0;
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::with_buffer(vec![]);
+        writer.bool(self.strobe);
+        writer.u8(self.bit_idx);
+        writer.u8(self.pads[0].bits());
+        writer.u8(self.pads[1].bits());
+        writer.into_bytes()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        let mut reader = StateReader::new(data);
+        self.strobe = reader.bool()?;
+        self.bit_idx = reader.u8_max(24)?;
+        for pad in self.pads.iter_mut() {
+            *pad = ButtonFlags::from_bits_truncate(reader.u8()?);
+        }
+        reader.finish()
+    }
+}
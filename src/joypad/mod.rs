@@ -0,0 +1,397 @@
+//! 手柄端口, $4016 与 $4017 各连接一个输入设备
+//!
+//! CPU 写 $4016 时两个端口的设备都收到 OUT0-OUT2, 读 $4016/$4017 时对应端口的设备返回 D0-D4.
+//! 默认连接两个标准手柄, NES 2.0 ROM 按文件头中的默认扩展设备连接, 运行时可以通过
+//! [`Joypad::set_device`] 更换.
+
+use std::any::Any;
+use bitflags::bitflags;
+
+use crate::{ppu::Frame, save_state::{Snapshot, StateWriter, StateReader, SaveStateError}};
+
+mod standard;
+mod four_score;
+mod zapper;
+mod vaus;
+mod power_pad;
+
+pub use standard::StandardController;
+pub use four_score::FourScore;
+pub use zapper::Zapper;
+pub use vaus::Vaus;
+pub use power_pad::PowerPad;
+
+bitflags! {
+    #[derive(Default)]
+    pub(crate) struct ButtonFlags: u8 {
+        const A = 0b0000_0001;
+        const B = 0b0000_0010;
+        const SELECT = 0b0000_0100;
+        const START = 0b0000_1000;
+        const UP = 0b0001_0000;
+        const DOWN = 0b0010_0000;
+        const LEFT = 0b0100_0000;
+        const RIGHT = 0b1000_0000;
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum JoypadButton {
+    A, B, SELECT, START, UP, DOWN, LEFT, RIGHT,
+}
+
+impl JoypadButton {
+    pub(crate) fn into_flags(&self) -> ButtonFlags {
+        match self {
+            JoypadButton::A => ButtonFlags::A,
+            JoypadButton::B => ButtonFlags::B,
+            JoypadButton::SELECT => ButtonFlags::SELECT,
+            JoypadButton::START => ButtonFlags::START,
+            JoypadButton::UP => ButtonFlags::UP,
+            JoypadButton::DOWN => ButtonFlags::DOWN,
+            JoypadButton::LEFT => ButtonFlags::LEFT,
+            JoypadButton::RIGHT => ButtonFlags::RIGHT,
+        }
+    }
+}
+
+/// 玩家, P3 与 P4 只在连接 Four Score 时存在
+#[derive(Clone, Copy)]
+pub enum PlayerId {
+    P1,
+    P2,
+    P3,
+    P4,
+}
+
+impl PlayerId {
+    /// 玩家的手柄所在的端口, 以及是该端口上的第几个手柄
+    fn port_and_index(self) -> (ControllerPort, usize) {
+        match self {
+            PlayerId::P1 => (ControllerPort::Port1, 0),
+            PlayerId::P2 => (ControllerPort::Port2, 0),
+            PlayerId::P3 => (ControllerPort::Port1, 1),
+            PlayerId::P4 => (ControllerPort::Port2, 1),
+        }
+    }
+}
+
+/// 手柄端口, Port1 读 $4016, Port2 读 $4017
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControllerPort {
+    Port1,
+    Port2,
+}
+
+/// 读取端口时的画面与 PPU 位置, 供光枪判断瞄准处是否刚被画成亮色
+pub struct Beam<'a> {
+    /// 正在绘制的画面, 当前位置之前的像素属于本帧, 之后的仍是上一帧
+    pub frame: &'a Frame,
+    pub scanline: u16,
+    /// 扫描线内的 PPU 周期, 像素 x 在第 x + 2 个周期画出
+    pub cycle: u16,
+}
+
+/// 连接在手柄端口上的输入设备
+pub trait InputDevice: Any {
+    /// 设备名称, 也用于在即时存档中识别设备
+    fn name(&self) -> &'static str;
+
+    /// CPU 写 $4016, out 的 bit 0..=2 为 OUT0-OUT2, 其中 OUT0 为选通位
+    fn write(&mut self, out: u8);
+
+    /// CPU 读端口, 返回值的 bit 0..=4 为 D0-D4
+    fn read(&mut self, beam: &Beam) -> u8;
+
+    /// 读取将得到的值, 但没有副作用
+    fn peek(&self, beam: &Beam) -> u8;
+
+    /// 重新上电, 清除移位寄存器等内部状态, 玩家的输入保留
+    fn power_on(&mut self) {}
+
+    /// 即时存档中保存的设备状态
+    fn save_state(&self) -> Vec<u8> {
+        vec![]
+    }
+
+    /// 恢复 `save_state` 保存的状态
+    fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        match data.is_empty() {
+            true => Ok(()),
+            false => Err(SaveStateError::Corrupted(format!("unexpected state for {}", self.name()))),
+        }
+    }
+}
+
+/// 两个手柄端口
+pub struct Joypad {
+    out: u8, // 最近一次写入 $4016 的 OUT0-OUT2
+    ports: [Box<dyn InputDevice>; 2],
+}
+
+impl Joypad {
+    pub(crate) fn new() -> Self {
+        Joypad {
+            out: 0,
+            ports: [Box::new(StandardController::new()), Box::new(StandardController::new())],
+        }
+    }
+
+    /// 按 NES 2.0 文件头中的默认扩展设备连接设备, 未知的设备使用标准手柄
+    pub(crate) fn for_expansion_device(device: u8) -> Self {
+        let mut joypad = Joypad::new();
+        match device {
+            0x02 => { // NES Four Score
+                joypad.set_device(ControllerPort::Port1, Box::new(FourScore::new(ControllerPort::Port1)));
+                joypad.set_device(ControllerPort::Port2, Box::new(FourScore::new(ControllerPort::Port2)));
+            }
+            0x08 => { // Zapper ($4017)
+                joypad.set_device(ControllerPort::Port2, Box::new(Zapper::new()));
+            }
+            0x09 => { // 两把 Zapper
+                joypad.set_device(ControllerPort::Port1, Box::new(Zapper::new()));
+                joypad.set_device(ControllerPort::Port2, Box::new(Zapper::new()));
+            }
+            0x0b | 0x0c => { // Power Pad A 面, B 面
+                joypad.set_device(ControllerPort::Port2, Box::new(PowerPad::new()));
+            }
+            0x0f => { // Arkanoid Vaus (NES)
+                joypad.set_device(ControllerPort::Port2, Box::new(Vaus::new()));
+            }
+            _ => {}
+        }
+        joypad
+    }
+
+    pub(crate) fn write(&mut self, data: u8) {
+        self.out = data & 0b111;
+        for device in self.ports.iter_mut() {
+            device.write(self.out);
+        }
+    }
+
+    pub(crate) fn read(&mut self, port: ControllerPort, beam: &Beam) -> u8 {
+        self.ports[port as usize].read(beam) & 0b1_1111
+    }
+
+    /// 读取将得到的值, 但不移动到下一个按键
+    pub(crate) fn peek(&self, port: ControllerPort, beam: &Beam) -> u8 {
+        self.ports[port as usize].peek(beam) & 0b1_1111
+    }
+
+    pub(crate) fn power_on(&mut self) {
+        self.out = 0;
+        for device in self.ports.iter_mut() {
+            device.power_on();
+        }
+    }
+
+    /// 更换端口上的设备, 返回原来的设备
+    pub fn set_device(&mut self, port: ControllerPort, mut device: Box<dyn InputDevice>) -> Box<dyn InputDevice> {
+        device.write(self.out);
+        std::mem::replace(&mut self.ports[port as usize], device)
+    }
+
+    pub fn device_name(&self, port: ControllerPort) -> &'static str {
+        self.ports[port as usize].name()
+    }
+
+    /// 端口上的设备, 类型不是 T 时为 None
+    pub fn device<T: InputDevice>(&self, port: ControllerPort) -> Option<&T> {
+        let device: &dyn Any = self.ports[port as usize].as_ref();
+        device.downcast_ref()
+    }
+
+    /// 端口上的设备, 类型不是 T 时为 None
+    pub fn device_mut<T: InputDevice>(&mut self, port: ControllerPort) -> Option<&mut T> {
+        let device: &mut dyn Any = self.ports[port as usize].as_mut();
+        device.downcast_mut()
+    }
+
+    /// 玩家的手柄, 对应的端口上没有标准手柄或 Four Score 时为 None
+    fn pad(&self, id: PlayerId) -> Option<ButtonFlags> {
+        let (port, index) = id.port_and_index();
+        match (self.device::<StandardController>(port), self.device::<FourScore>(port)) {
+            (Some(controller), _) if index == 0 => Some(controller.buttons),
+            (_, Some(four_score)) => Some(four_score.pads[index]),
+            _ => None,
+        }
+    }
+
+    fn pad_mut(&mut self, id: PlayerId) -> Option<&mut ButtonFlags> {
+        let (port, index) = id.port_and_index();
+        if self.device::<StandardController>(port).is_some() {
+            return self.device_mut::<StandardController>(port)
+                .filter(|_| index == 0)
+                .map(|controller| &mut controller.buttons);
+        }
+        self.device_mut::<FourScore>(port).map(|four_score| &mut four_score.pads[index])
+    }
+
+    /// 玩家的手柄不存在时不做任何事
+    pub fn set_button_pressed(&mut self, id: PlayerId, button: JoypadButton, pressed: bool) {
+        if let Some(buttons) = self.pad_mut(id) {
+            buttons.set(button.into_flags(), pressed);
+        }
+    }
+
+    /// 一次设置全部按键, bit 0..=7 依次为 A, B, SELECT, START, UP, DOWN, LEFT, RIGHT
+    pub fn set_buttons(&mut self, id: PlayerId, buttons: u8) {
+        if let Some(pad) = self.pad_mut(id) {
+            *pad = ButtonFlags::from_bits_truncate(buttons);
+        }
+    }
+
+    /// 当前按下的按键, 格式同 `set_buttons`, 玩家的手柄不存在时为 0
+    pub fn buttons(&self, id: PlayerId) -> u8 {
+        self.pad(id).map_or(0, |buttons| buttons.bits())
+    }
+}
+
+impl Snapshot for Joypad {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.u8(self.out);
+        for device in self.ports.iter() {
+            writer.blob(device.name().as_bytes());
+            writer.blob(&device.save_state());
+        }
+    }
+
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.out = reader.u8_max(0b111)?;
+        for device in self.ports.iter_mut() {
+            let name = reader.blob()?;
+            let state = reader.blob()?;
+            if name == device.name().as_bytes() {
+                device.load_state(state)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 连续读取 n 次端口的 bit
+    fn read_bits(joypad: &mut Joypad, port: ControllerPort, bit: u8, n: usize) -> Vec<u8> {
+        let frame = Frame::new();
+        let beam = Beam { frame: &frame, scanline: 0, cycle: 0 };
+        (0..n).map(|_| joypad.read(port, &beam) >> bit & 1).collect()
+    }
+
+    fn strobe(joypad: &mut Joypad) {
+        joypad.write(1);
+        joypad.write(0);
+    }
+
+    #[test]
+    fn test_input_devices() {
+        let mut joypad = Joypad::new();
+        joypad.set_button_pressed(PlayerId::P1, JoypadButton::START, true);
+        joypad.set_buttons(PlayerId::P2, 0x81);
+        joypad.set_button_pressed(PlayerId::P3, JoypadButton::A, true); // 没有 Four Score, 忽略
+        assert_eq!((joypad.buttons(PlayerId::P1), joypad.buttons(PlayerId::P3)), (0x08, 0));
+        strobe(&mut joypad);
+        assert_eq!(read_bits(&mut joypad, ControllerPort::Port1, 0, 8), [0, 0, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(read_bits(&mut joypad, ControllerPort::Port2, 0, 8), [1, 0, 0, 0, 0, 0, 0, 1]);
+
+        // Four Score: 两个手柄之后为签名
+        let mut joypad = Joypad::for_expansion_device(0x02);
+        joypad.set_buttons(PlayerId::P1, 0x01);
+        joypad.set_buttons(PlayerId::P3, 0x80);
+        joypad.set_buttons(PlayerId::P4, 0x02);
+        strobe(&mut joypad);
+        let bits = read_bits(&mut joypad, ControllerPort::Port1, 0, 25);
+        assert_eq!(bits[..16], [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(bits[16..], [0, 0, 0, 1, 0, 0, 0, 0, 1]);
+        let bits = read_bits(&mut joypad, ControllerPort::Port2, 0, 24);
+        assert_eq!(bits[8..], [0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0]);
+
+        // Zapper: D3 为 0 表示检测到亮光, D4 为扳机
+        let mut joypad = Joypad::for_expansion_device(0x08);
+        assert_eq!(joypad.device_name(ControllerPort::Port2), "zapper");
+        let mut frame = Frame::new();
+        frame.fill_rect(100, 50, 8, 8, (0xff, 0xff, 0xff));
+        let sense = |joypad: &mut Joypad, scanline, cycle| {
+            let beam = Beam { frame: &frame, scanline, cycle };
+            joypad.read(ControllerPort::Port2, &beam)
+        };
+        assert_eq!(sense(&mut joypad, 60, 0), 0b0_1000);
+        let zapper = joypad.device_mut::<Zapper>(ControllerPort::Port2).unwrap();
+        zapper.set_trigger(true);
+        zapper.set_aim(Some((98, 48)));
+        assert_eq!(sense(&mut joypad, 60, 0), 0b1_0000);
+        assert_eq!(sense(&mut joypad, 50, 101), 0b1_1000); // 电子束尚未画到目标
+        assert_eq!(sense(&mut joypad, 50, 102), 0b1_0000);
+        assert_eq!(sense(&mut joypad, 70, 0), 0b1_0000);
+        assert_eq!(sense(&mut joypad, 71, 0), 0b1_1000); // 超过余辉时间
+        assert_eq!(sense(&mut joypad, 261, 0), 0b1_1000);
+        joypad.device_mut::<Zapper>(ControllerPort::Port2).unwrap().set_aim(Some((200, 50)));
+        assert_eq!(sense(&mut joypad, 60, 0), 0b1_1000); // 瞄准黑色
+
+        // Vaus: 旋钮位置取反后由 D3 高位在前读出, D4 为按钮
+        let mut joypad = Joypad::for_expansion_device(0x0f);
+        let vaus = joypad.device_mut::<Vaus>(ControllerPort::Port2).unwrap();
+        vaus.set_position(0b1010_0000);
+        vaus.set_button_pressed(true);
+        strobe(&mut joypad);
+        assert_eq!(read_bits(&mut joypad, ControllerPort::Port2, 4, 1), [1]);
+        strobe(&mut joypad);
+        assert_eq!(read_bits(&mut joypad, ControllerPort::Port2, 3, 9), [0, 1, 0, 1, 1, 1, 1, 1, 1]);
+
+        // Power Pad: D3 依次为 2, 1, 5, 9, 6, 10, 11, 7, D4 依次为 4, 3, 12, 8
+        let mut joypad = Joypad::for_expansion_device(0x0b);
+        let pad = joypad.device_mut::<PowerPad>(ControllerPort::Port2).unwrap();
+        for button in [1, 9, 7, 3, 8] {
+            pad.set_button_pressed(button, true);
+        }
+        strobe(&mut joypad);
+        assert_eq!(read_bits(&mut joypad, ControllerPort::Port2, 3, 9), [0, 1, 0, 1, 0, 0, 0, 1, 1]);
+        strobe(&mut joypad);
+        assert_eq!(read_bits(&mut joypad, ControllerPort::Port2, 4, 9), [0, 1, 0, 1, 1, 1, 1, 1, 1]);
+
+        // 运行时更换设备, 存档只恢复与存档时相同的设备
+        let mut joypad = Joypad::new();
+        joypad.set_buttons(PlayerId::P1, 0xff);
+        joypad.write(1);
+        let mut writer = StateWriter::with_buffer(vec![]);
+        joypad.save(&mut writer);
+        let state = writer.into_bytes();
+        let old = joypad.set_device(ControllerPort::Port1, Box::new(Zapper::new()));
+        assert_eq!(old.name(), "standard");
+        assert!(joypad.device::<StandardController>(ControllerPort::Port1).is_none());
+        joypad.set_device(ControllerPort::Port2, Box::new(FourScore::new(ControllerPort::Port2)));
+        joypad.load(&mut StateReader::new(&state)).unwrap();
+        assert_eq!(joypad.device_name(ControllerPort::Port1), "zapper");
+        assert_eq!(joypad.device_name(ControllerPort::Port2), "four score");
+        joypad.set_device(ControllerPort::Port1, old);
+        assert_eq!(joypad.buttons(PlayerId::P1), 0xff);
+
+        // Vaus 与 Power Pad 的输入也随存档恢复
+        let mut joypad = Joypad::for_expansion_device(0x0f);
+        let vaus = joypad.device_mut::<Vaus>(ControllerPort::Port2).unwrap();
+        vaus.set_position(200);
+        vaus.set_button_pressed(true);
+        let mut writer = StateWriter::with_buffer(vec![]);
+        joypad.save(&mut writer);
+        let state = writer.into_bytes();
+        let vaus = joypad.device_mut::<Vaus>(ControllerPort::Port2).unwrap();
+        vaus.set_position(100);
+        vaus.set_button_pressed(false);
+        joypad.load(&mut StateReader::new(&state)).unwrap();
+        assert_eq!(joypad.device::<Vaus>(ControllerPort::Port2).unwrap().position(), 200);
+        strobe(&mut joypad);
+        assert_eq!(read_bits(&mut joypad, ControllerPort::Port2, 4, 1), [1]);
+
+        let mut joypad = Joypad::for_expansion_device(0x0b);
+        joypad.device_mut::<PowerPad>(ControllerPort::Port2).unwrap().set_button_pressed(12, true);
+        let mut writer = StateWriter::with_buffer(vec![]);
+        joypad.save(&mut writer);
+        let state = writer.into_bytes();
+        joypad.device_mut::<PowerPad>(ControllerPort::Port2).unwrap().set_button_pressed(12, false);
+        joypad.load(&mut StateReader::new(&state)).unwrap();
+        assert_eq!(joypad.device::<PowerPad>(ControllerPort::Port2).unwrap().buttons(), 0x800);
+    }
+}
@@ -0,0 +1,104 @@
+use crate::save_state::{StateWriter, StateReader, SaveStateError};
+use super::{Beam, InputDevice};
+
+/// D3 依次读出的按键
+const D3_BUTTONS: [usize; 8] = [2, 1, 5, 9, 6, 10, 11, 7];
+/// D4 依次读出的按键, 之后读出 1
+const D4_BUTTONS: [usize; 4] = [4, 3, 12, 8];
+
+/// Power Pad 跳舞毯, 12 个按键按 B 面的编号 1..=12, 由 D3 与 D4 同时读出(1 表示按下)
+pub struct PowerPad {
+    buttons: u16, // bit n-1 为按键 n
+    strobe: bool,
+    shift_d3: u8,
+    shift_d4: u8,
+}
+
+impl Default for PowerPad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PowerPad {
+    pub fn new() -> Self {
+        PowerPad {
+            buttons: 0,
+            strobe: false,
+            shift_d3: 0,
+            shift_d4: 0,
+        }
+    }
+
+    /// button 为 1..=12
+    pub fn set_button_pressed(&mut self, button: usize, pressed: bool) {
+        assert!((1..=12).contains(&button), "Power Pad button {} out of range 1..=12", button);
+        let mask = 1 << (button - 1);
+        match pressed {
+            true => self.buttons |= mask,
+            false => self.buttons &= !mask,
+        }
+    }
+
+    /// bit n-1 为按键 n
+    pub fn buttons(&self) -> u16 {
+        self.buttons
+    }
+
+    fn latch(&mut self) {
+        let bits = |order: &[usize]| order.iter().enumerate()
+            .fold(0u8, |bits, (idx, &button)| bits | (((self.buttons >> (button - 1)) & 1) as u8) << idx);
+        self.shift_d3 = bits(&D3_BUTTONS);
+        self.shift_d4 = bits(&D4_BUTTONS) | 0xf0;
+    }
+}
+
+impl InputDevice for PowerPad {
+    fn name(&self) -> &'static str {
+        "power pad"
+    }
+
+    fn write(&mut self, out: u8) {
+        self.strobe = out & 1 == 1;
+        if self.strobe {
+            self.latch();
+        }
+    }
+
+    fn read(&mut self, beam: &Beam) -> u8 {
+        let result = self.peek(beam);
+        if !self.strobe {
+            self.shift_d3 = (self.shift_d3 >> 1) | 0x80;
+            self.shift_d4 = (self.shift_d4 >> 1) | 0x80;
+        }
+        result
+    }
+
+    fn peek(&self, _beam: &Beam) -> u8 {
+        ((self.shift_d3 & 1) << 3) | ((self.shift_d4 & 1) << 4)
+    }
+
+    fn power_on(&mut self) {
+        self.strobe = false;
+        self.shift_d3 = 0;
+        self.shift_d4 = 0;
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::with_buffer(vec![]);
+        writer.bool(self.strobe);
+        writer.u8(self.shift_d3);
+        writer.u8(self.shift_d4);
+        writer.u16(self.buttons);
+        writer.into_bytes()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        let mut reader = StateReader::new(data);
+        self.strobe = reader.bool()?;
+        self.shift_d3 = reader.u8()?;
+        self.shift_d4 = reader.u8()?;
+        self.buttons = reader.u16()? & 0x0fff;
+        reader.finish()
+    }
+}
@@ -0,0 +1,88 @@
+use crate::save_state::{StateWriter, StateReader, SaveStateError};
+use super::{Beam, ButtonFlags, InputDevice, JoypadButton};
+
+/// 标准手柄, 8 个按键按 A, B, SELECT, START, UP, DOWN, LEFT, RIGHT 的顺序由 D0 读出
+///
+/// The controller operates in 2 modes:
+/// - strobe bit on - controller reports only status of the button A on every read
+/// - strobe bit off - controller cycles through all buttons
+pub struct StandardController {
+    strobe: bool,
+    button_idx: u8,
+    pub(crate) buttons: ButtonFlags,
+}
+
+impl Default for StandardController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StandardController {
+    pub fn new() -> Self {
+        StandardController {
+            strobe: false,
+            button_idx: 0,
+            buttons: ButtonFlags::empty(),
+        }
+    }
+
+    pub fn set_button_pressed(&mut self, button: JoypadButton, pressed: bool) {
+        self.buttons.set(button.into_flags(), pressed);
+    }
+
+    /// 格式同 `Joypad::set_buttons`
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.buttons = ButtonFlags::from_bits_truncate(buttons);
+    }
+
+    pub fn buttons(&self) -> u8 {
+        self.buttons.bits()
+    }
+}
+
+impl InputDevice for StandardController {
+    fn name(&self) -> &'static str {
+        "standard"
+    }
+
+    fn write(&mut self, out: u8) {
+        self.strobe = out & 1 == 1;
+        if self.strobe {
+            self.button_idx = 0;
+        }
+    }
+
+    fn read(&mut self, beam: &Beam) -> u8 {
+        let result = self.peek(beam);
+        if !self.strobe {
+            self.button_idx = (self.button_idx + 1) % 8;
+        }
+        result
+    }
+
+    fn peek(&self, _beam: &Beam) -> u8 {
+        (self.buttons.bits() >> self.button_idx) & 0x1
+    }
+
+    fn power_on(&mut self) {
+        self.strobe = false;
+        self.button_idx = 0;
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::with_buffer(vec![]);
+        writer.bool(self.strobe);
+        writer.u8(self.button_idx);
+        writer.u8(self.buttons.bits());
+        writer.into_bytes()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        let mut reader = StateReader::new(data);
+        self.strobe = reader.bool()?;
+        self.button_idx = reader.u8_max(7)?;
+        self.buttons = ButtonFlags::from_bits_truncate(reader.u8()?);
+        reader.finish()
+    }
+}
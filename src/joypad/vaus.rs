@@ -0,0 +1,93 @@
+use crate::save_state::{StateWriter, StateReader, SaveStateError};
+use super::{Beam, InputDevice};
+
+/// Arkanoid 的 Vaus 控制器(NES 版)
+///
+/// 选通位为 1 时锁存旋钮位置, 之后由 D3 高位在前读出取反后的 8 位, D4 为按钮(1 表示按下).
+/// 旋钮的实际范围约为 98..=242
+pub struct Vaus {
+    position: u8,
+    button: bool,
+    strobe: bool,
+    shift: u8, // 锁存的位置, 每次读取后左移
+}
+
+impl Default for Vaus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vaus {
+    pub fn new() -> Self {
+        Vaus {
+            position: 170,
+            button: false,
+            strobe: false,
+            shift: 0,
+        }
+    }
+
+    pub fn set_position(&mut self, position: u8) {
+        self.position = position;
+        if self.strobe {
+            self.shift = position;
+        }
+    }
+
+    pub fn position(&self) -> u8 {
+        self.position
+    }
+
+    pub fn set_button_pressed(&mut self, pressed: bool) {
+        self.button = pressed;
+    }
+}
+
+impl InputDevice for Vaus {
+    fn name(&self) -> &'static str {
+        "vaus"
+    }
+
+    fn write(&mut self, out: u8) {
+        self.strobe = out & 1 == 1;
+        if self.strobe {
+            self.shift = self.position;
+        }
+    }
+
+    fn read(&mut self, beam: &Beam) -> u8 {
+        let result = self.peek(beam);
+        if !self.strobe {
+            self.shift <<= 1;
+        }
+        result
+    }
+
+    fn peek(&self, _beam: &Beam) -> u8 {
+        ((!self.shift >> 7) << 3) | ((self.button as u8) << 4)
+    }
+
+    fn power_on(&mut self) {
+        self.strobe = false;
+        self.shift = 0;
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::with_buffer(vec![]);
+        writer.bool(self.strobe);
+        writer.u8(self.shift);
+        writer.u8(self.position);
+        writer.bool(self.button);
+        writer.into_bytes()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        let mut reader = StateReader::new(data);
+        self.strobe = reader.bool()?;
+        self.shift = reader.u8()?;
+        self.position = reader.u8()?;
+        self.button = reader.bool()?;
+        reader.finish()
+    }
+}
@@ -0,0 +1,89 @@
+use crate::ppu::Frame;
+use super::{Beam, InputDevice};
+
+/// 瞄准点周围检测亮光的半径(像素)
+const SENSE_RADIUS: usize = 2;
+/// 像素画出后的多少条扫描线内仍能检测到, 模拟荧光与光电管的余辉
+const PERSISTENCE: u16 = 20;
+/// 亮度(0..=255)不低于该值时视为亮光
+const BRIGHTNESS_THRESHOLD: u32 = 0x80;
+
+/// Zapper 光枪, D3 为光感(0 表示检测到亮光), D4 为扳机(1 表示扣下)
+///
+/// 读取时检查瞄准点附近本帧已经画出, 且在最近若干条扫描线内画出的像素是否足够亮,
+/// 因此游戏需要在画出白色目标后的同一帧内读取.
+pub struct Zapper {
+    aim: Option<(usize, usize)>, // 瞄准的画面坐标, None 表示没有对准屏幕
+    trigger: bool,
+}
+
+impl Default for Zapper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Zapper {
+    pub fn new() -> Self {
+        Zapper {
+            aim: None,
+            trigger: false,
+        }
+    }
+
+    /// 瞄准画面上的 (x, y), None 表示没有对准屏幕
+    pub fn set_aim(&mut self, aim: Option<(usize, usize)>) {
+        self.aim = aim.filter(|&(x, y)| x < Frame::WIDTH && y < Frame::HEIGHT);
+    }
+
+    pub fn aim(&self) -> Option<(usize, usize)> {
+        self.aim
+    }
+
+    pub fn set_trigger(&mut self, pulled: bool) {
+        self.trigger = pulled;
+    }
+
+    /// 瞄准点附近是否有刚画出的亮色像素
+    fn light_sensed(&self, beam: &Beam) -> bool {
+        let Some((aim_x, aim_y)) = self.aim else {
+            return false;
+        };
+        let ys = aim_y.saturating_sub(SENSE_RADIUS)..=(aim_y + SENSE_RADIUS).min(Frame::HEIGHT - 1);
+        let xs = aim_x.saturating_sub(SENSE_RADIUS)..=(aim_x + SENSE_RADIUS).min(Frame::WIDTH - 1);
+        let (scanline, cycle) = (beam.scanline as usize, beam.cycle as usize);
+        for y in ys {
+            if y > scanline || scanline - y > PERSISTENCE as usize {
+                continue;
+            }
+            for x in xs.clone() {
+                let drawn = y < scanline || x + 2 <= cycle;
+                if drawn && brightness(beam.frame.pixel(x, y)) >= BRIGHTNESS_THRESHOLD {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+/// 人眼感知的亮度, 0..=255
+fn brightness((r, g, b): (u8, u8, u8)) -> u32 {
+    (299 * r as u32 + 587 * g as u32 + 114 * b as u32) / 1000
+}
+
+impl InputDevice for Zapper {
+    fn name(&self) -> &'static str {
+        "zapper"
+    }
+
+    fn write(&mut self, _out: u8) {}
+
+    fn read(&mut self, beam: &Beam) -> u8 {
+        self.peek(beam)
+    }
+
+    fn peek(&self, beam: &Beam) -> u8 {
+        ((!self.light_sensed(beam) as u8) << 3) | ((self.trigger as u8) << 4)
+    }
+}
@@ -5,16 +5,91 @@ mod ppu;
 mod apu;
 mod joypad;
 mod common;
+mod debugger;
+mod cdl;
+mod hooks;
+mod event_viewer;
+mod cheats;
+mod ram_search;
+mod save_state;
+mod rewind;
+mod run_ahead;
+mod movie;
+#[cfg(feature="scripting")]
+mod script;
 #[cfg(feature="simple_run")]
 mod simple_run;
 
 pub use cpu::{
     Cpu,
-    trace::trace_readonly as cpu_trace,
+    CpuHalted,
+    InstructionHook,
+    Registers,
+    Interrupt,
+    CpuBus,
+    Peek,
+    AddressingMode,
+    Instruction,
+    disassemble,
+    disassemble_range,
+    FlatBus,
+    BusAccess,
+    AccessKind,
+};
+pub use bus::Bus;
+pub use hooks::{BusEvent, HookEvents, HookId};
+pub use event_viewer::{EventViewer, FrameEvent, FrameEventKind};
+pub use cheats::{Cheat, CheatCode, CheatError, Cheats};
+pub use ram_search::{RamSearch, ValueSize, Comparison, CompareTo, Candidate};
+pub use save_state::{SaveStateError, SAVE_STATE_VERSION};
+pub use rewind::Rewind;
+pub use run_ahead::RunAhead;
+pub use movie::{Movie, MovieFrame, MovieCommands, MovieError, MovieRecorder, MoviePlayer};
+#[cfg(feature="scripting")]
+pub use script::{ScriptHost, ScriptError};
+pub use cdl::{CodeDataLog, CdlCoverage, PrgFlags as CdlPrgFlags, ChrFlags as CdlChrFlags};
+pub use debugger::{
+    Debugger,
+    Breakpoint,
+    BreakpointId,
+    BreakOn,
+    BreakTarget,
+    BreakAction,
+    StopReason,
+    Expr,
+    ExprError,
+    EvalContext,
+    LogTemplate,
+    Variable,
+    UnaryOp,
+    BinaryOp,
+    GdbServer,
+    Profiler,
+    Routine,
+    RoutineStats,
+    Symbols,
+    SymbolLocation,
+    SymbolError,
+    trace as cpu_trace,
+    trace_with_symbols as cpu_trace_with_symbols,
+    TraceFormat,
+    TraceLogger,
 };
 pub use cartridge::Rom;
 pub use ppu::{Mirroring, Frame};
 pub use apu::Samples;
-pub use joypad::{Joypad, JoypadButton, PlayerId};
+pub use joypad::{
+    Joypad,
+    JoypadButton,
+    PlayerId,
+    ControllerPort,
+    InputDevice,
+    Beam,
+    StandardController,
+    FourScore,
+    Zapper,
+    Vaus,
+    PowerPad,
+};
 #[cfg(feature="simple_run")]
-pub use simple_run::run;
\ No newline at end of file
+pub use simple_run::{run, run_with_options, RunOptions};
\ No newline at end of file
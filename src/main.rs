@@ -1,14 +1,70 @@
+const DEFAULT_ROM: &str = "test_roms/NES-NROM-256/Super Mario Bros.nes";
+// "test_roms/NES-NROM-128/pacman.nes"
+// "test_roms/NES-NROM-128/Balloon Fight.nes"
+// "test_roms/NES-NROM-128/Golf.nes"
+// "test_roms/NES-NROM-128/Tennis.nes"
+// "test_roms/NES-NROM-128/Ice Climber.nes"
+// "test_roms/NES-NROM-128/F-1 Race.nes" // render bug, 可以实现弯道显示, 但是锯齿严重, 并且转弯时 HUD 最下层 tile 虚化, 结束关卡时显示错误 
+// "test_roms/NES-NROM-128/Baseball.nes"
+// "test_roms/NES-NROM-128/Bomberman.nes" // fail
+// "test_roms/NES-NROM-256/1942.nes"
+// "test_roms/NES-NROM-256/10-Yard Fight.nes"
+// "test_roms/NES-NROM-256/Volleyball.nes"
+
+/// 用法: cnes [ROM] [--script 脚本] [--headless] [--run-ahead 帧数] [--record 录像] [--play 录像] [--show-lag] [--zapper]
+///
+/// --headless 时不打开窗口, 由脚本调用 frame_advance() 推进模拟, 脚本结束即退出
 fn main() {
-    // cnes::run("test_roms/NES-NROM-128/pacman.nes");
-    // cnes::run("test_roms/NES-NROM-128/Balloon Fight.nes");
-    // cnes::run("test_roms/NES-NROM-128/Golf.nes");
-    // cnes::run("test_roms/NES-NROM-128/Tennis.nes");
-    // cnes::run("test_roms/NES-NROM-128/Ice Climber.nes");
-    // cnes::run("test_roms/NES-NROM-128/F-1 Race.nes"); // render bug, 可以实现弯道显示, 但是锯齿严重, 并且转弯时 HUD 最下层 tile 虚化, 结束关卡时显示错误 
-    // cnes::run("test_roms/NES-NROM-128/Baseball.nes");
-    // cnes::run("test_roms/NES-NROM-128/Bomberman.nes"); // fail
-    // cnes::run("test_roms/NES-NROM-256/1942.nes");
-    cnes::run("test_roms/NES-NROM-256/Super Mario Bros.nes");
-    // cnes::run("test_roms/NES-NROM-256/10-Yard Fight.nes");
-    // cnes::run("test_roms/NES-NROM-256/Volleyball.nes");
+    let mut rom_filename = None;
+    let mut options = cnes::RunOptions::default();
+    let mut headless = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--script" => options.script = args.next(),
+            "--headless" => headless = true,
+            "--record" => options.record_movie = args.next(),
+            "--play" => options.play_movie = args.next(),
+            "--show-lag" => options.show_lag = true,
+            "--zapper" => options.zapper = true,
+            "--run-ahead" => {
+                options.run_ahead = args.next().and_then(|frames| frames.parse().ok()).unwrap_or_else(|| {
+                    eprintln!("--run-ahead requires a number of frames");
+                    std::process::exit(1);
+                });
+            }
+            _ => rom_filename = Some(arg),
+        }
+    }
+    let rom_filename = rom_filename.as_deref().unwrap_or(DEFAULT_ROM);
+
+    if headless {
+        run_headless(rom_filename, options.script.as_deref());
+    } else {
+        cnes::run_with_options(rom_filename, &options);
+    }
+}
+
+#[cfg(feature = "scripting")]
+fn run_headless(rom_filename: &str, script_filename: Option<&str>) {
+    use std::{cell::RefCell, rc::Rc};
+
+    let Some(script_filename) = script_filename else {
+        eprintln!("--headless requires --script");
+        std::process::exit(1);
+    };
+    let rom_bytes = std::fs::read(rom_filename).unwrap();
+    let mut cpu = cnes::Cpu::new(cnes::Rom::new(&rom_bytes).unwrap());
+    cpu.reset();
+    let mut host = cnes::ScriptHost::new(Rc::new(RefCell::new(cpu)));
+    if let Err(err) = host.run_file(script_filename) {
+        eprintln!("{}: {}", script_filename, err);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+fn run_headless(_rom_filename: &str, _script_filename: Option<&str>) {
+    eprintln!("--headless requires the scripting feature");
+    std::process::exit(1);
 }
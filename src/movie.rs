@@ -0,0 +1,592 @@
+//! 录像, 逐帧记录两个手柄的输入, 从上电或存档开始确定性地回放
+//!
+//! 文件格式为 FCEUX 的 `.fm2` 文本格式: 头部每行为 `键 值`, 之后每帧一行 `|命令|手柄1|手柄2||`,
+//! 手柄按 `RLDUTSBA` 的顺序, 按下的键为字母, 未按下为 `.`.
+//! 从存档开始的录像在 `savestate` 中保存本模拟器的存档, 因此无法使用 FCEUX 的存档.
+//! 额外的 `ramChecksum 帧数 CRC32` 记录若干帧后 CPU RAM 的校验值, 回放时用于检测不同步, FCEUX 会忽略它.
+
+use std::{fmt, time::{SystemTime, UNIX_EPOCH}};
+use bitflags::bitflags;
+
+use crate::{Cpu, CpuHalted, Peek, PlayerId, SaveStateError, save_state};
+
+/// 每隔多少帧记录一次 CPU RAM 的校验值
+const CHECKSUM_INTERVAL: usize = 60;
+
+bitflags! {
+    /// 一帧开始时执行的命令, 与 FM2 的命令字段相同
+    #[derive(Default)]
+    pub struct MovieCommands: u8 {
+        const SOFT_RESET = 0b0000_0001;
+        const POWER = 0b0000_0010;
+    }
+}
+
+/// 一帧的输入
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MovieFrame {
+    pub commands: MovieCommands,
+    /// 两个手柄的按键, 格式同 `Joypad::set_buttons`
+    pub ports: [u8; 2],
+}
+
+/// 录像
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_filename: String,
+    /// PRG ROM 与 CHR ROM 的 MD5, 见 `Bus::rom_md5`
+    pub rom_checksum: [u8; 16],
+    pub guid: String,
+    pub rerecord_count: u32,
+    /// 录像起点的存档, None 表示从上电开始
+    pub savestate: Option<Vec<u8>>,
+    pub frames: Vec<MovieFrame>,
+    /// (帧数, 运行该数量的帧后 CPU RAM 的 CRC32)
+    pub ram_checksums: Vec<(usize, u32)>,
+}
+
+#[derive(Debug)]
+pub enum MovieError {
+    /// 第 line 行(从 1 开始)格式错误
+    Parse { line: usize, message: String },
+    /// FCEUX 支持但本模拟器不支持的设置, 例如 Four Score 与 PAL
+    Unsupported(String),
+    RomMismatch { expected: [u8; 16], found: [u8; 16] },
+    /// 无法读取录像起点的存档
+    SaveState(SaveStateError),
+    /// 运行 frame 帧后 CPU RAM 与录制时不同
+    Desync { frame: usize, expected: u32, found: u32 },
+    Halted(CpuHalted),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            MovieError::Unsupported(setting) => write!(f, "unsupported movie setting: {}", setting),
+            MovieError::RomMismatch { expected, found } => write!(
+                f, "movie is for ROM with MD5 {}, but loaded ROM has MD5 {}", hex(expected), hex(found)
+            ),
+            MovieError::SaveState(err) => write!(f, "cannot load movie savestate: {}", err),
+            MovieError::Desync { frame, expected, found } => write!(
+                f, "movie desynced at frame {}: RAM checksum {:08x}, expected {:08x}", frame, found, expected
+            ),
+            MovieError::Halted(halted) => halted.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl Movie {
+    /// 空录像, ROM 校验值取自 cpu
+    pub fn new(rom_filename: &str, cpu: &Cpu) -> Self {
+        let rom_checksum = cpu.bus().rom_md5();
+        Movie {
+            rom_filename: rom_filename.to_string(),
+            rom_checksum,
+            guid: new_guid(&rom_checksum),
+            rerecord_count: 0,
+            savestate: None,
+            frames: vec![],
+            ram_checksums: vec![],
+        }
+    }
+
+    /// 读取 `.fm2` 文件的内容
+    pub fn parse_fm2(text: &str) -> Result<Movie, MovieError> {
+        let mut movie = Movie {
+            rom_filename: String::new(),
+            rom_checksum: [0; 16],
+            guid: String::new(),
+            rerecord_count: 0,
+            savestate: None,
+            frames: vec![],
+            ram_checksums: vec![],
+        };
+        let mut ports = [true, true]; // 各端口是否接有手柄
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim_end();
+            let error = |message: &str| MovieError::Parse { line: idx + 1, message: message.to_string() };
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(fields) = line.strip_prefix('|') {
+                movie.frames.push(parse_frame(fields, ports).map_err(|message| error(&message))?);
+                continue;
+            }
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "version" if value != "3" => return Err(MovieError::Unsupported(format!("version {}", value))),
+                "romFilename" => movie.rom_filename = value.to_string(),
+                "romChecksum" => {
+                    let checksum = value.strip_prefix("base64:")
+                        .and_then(base64_decode)
+                        .ok_or_else(|| error("invalid romChecksum"))?;
+                    movie.rom_checksum = checksum.try_into().map_err(|_| error("romChecksum is not an MD5"))?;
+                }
+                "guid" => movie.guid = value.to_string(),
+                "rerecordCount" => movie.rerecord_count = value.parse().map_err(|_| error("invalid rerecordCount"))?,
+                "savestate" => {
+                    let state = value.strip_prefix("base64:")
+                        .and_then(base64_decode)
+                        .ok_or_else(|| error("invalid savestate"))?;
+                    movie.savestate = Some(state);
+                }
+                "port0" | "port1" => {
+                    let connected = &mut ports[(key == "port1") as usize];
+                    match value {
+                        "0" => *connected = false,
+                        "1" => *connected = true,
+                        _ => return Err(MovieError::Unsupported(line.to_string())),
+                    }
+                }
+                "port2" | "fourscore" | "palFlag" | "binary" | "FDS" if value != "0" => {
+                    return Err(MovieError::Unsupported(line.to_string()));
+                }
+                "ramChecksum" => {
+                    let checksum = value.split_once(' ').and_then(|(frame, crc)| {
+                        Some((frame.parse().ok()?, u32::from_str_radix(crc, 16).ok()?))
+                    });
+                    movie.ram_checksums.push(checksum.ok_or_else(|| error("invalid ramChecksum"))?);
+                }
+                _ => {} // emuVersion, comment, subtitle 等与回放无关
+            }
+        }
+        Ok(movie)
+    }
+
+    /// 导出为 `.fm2` 文件的内容
+    pub fn to_fm2(&self) -> String {
+        let mut text = String::new();
+        text.push_str("version 3\n");
+        text.push_str(&format!("rerecordCount {}\n", self.rerecord_count));
+        text.push_str("palFlag 0\n");
+        text.push_str(&format!("romFilename {}\n", self.rom_filename));
+        text.push_str(&format!("romChecksum base64:{}\n", base64_encode(&self.rom_checksum)));
+        text.push_str(&format!("guid {}\n", self.guid));
+        text.push_str("fourscore 0\nmicrophone 0\nport0 1\nport1 1\nport2 0\nFDS 0\nNewPPU 0\n");
+        if let Some(state) = &self.savestate {
+            text.push_str(&format!("savestate base64:{}\n", base64_encode(state)));
+        }
+        for (frame, crc) in &self.ram_checksums {
+            text.push_str(&format!("ramChecksum {} {:08x}\n", frame, crc));
+        }
+        for frame in &self.frames {
+            text.push_str(&format!(
+                "|{}|{}|{}||\n", frame.commands.bits(), format_buttons(frame.ports[0]), format_buttons(frame.ports[1])
+            ));
+        }
+        text
+    }
+}
+
+/// 录制录像, 每帧记录运行前手柄的按键
+pub struct MovieRecorder {
+    movie: Movie,
+    pending: MovieCommands, // 下一帧开始时执行的命令
+}
+
+impl MovieRecorder {
+    /// 让机器重新上电, 从上电开始录制
+    pub fn power_on(rom_filename: &str, cpu: &mut Cpu) -> Self {
+        cpu.power_cycle();
+        MovieRecorder { movie: Movie::new(rom_filename, cpu), pending: MovieCommands::empty() }
+    }
+
+    /// 从当前状态开始录制
+    pub fn from_state(rom_filename: &str, cpu: &Cpu) -> Self {
+        let mut movie = Movie::new(rom_filename, cpu);
+        movie.savestate = Some(cpu.save_state());
+        MovieRecorder { movie, pending: MovieCommands::empty() }
+    }
+
+    /// 在下一帧开始时 reset
+    pub fn soft_reset(&mut self) {
+        self.pending.insert(MovieCommands::SOFT_RESET);
+    }
+
+    /// 在下一帧开始时重新上电
+    pub fn power(&mut self) {
+        self.pending.insert(MovieCommands::POWER);
+    }
+
+    /// 在 `run_next_frame` 之前调用, 执行命令并记录这一帧的输入
+    pub fn before_frame(&mut self, cpu: &mut Cpu) {
+        let joypad = cpu.io_interface().1;
+        let ports = [joypad.buttons(PlayerId::P1), joypad.buttons(PlayerId::P2)];
+        let commands = std::mem::take(&mut self.pending);
+        apply_frame(cpu, commands, ports);
+        self.movie.frames.push(MovieFrame { commands, ports });
+    }
+
+    /// 在 `run_next_frame` 之后调用, 定期记录 CPU RAM 的校验值
+    pub fn after_frame(&mut self, cpu: &Cpu) {
+        let frame = self.movie.frames.len();
+        if frame.is_multiple_of(CHECKSUM_INTERVAL) && self.movie.ram_checksums.last().map(|&(last, _)| last) != Some(frame) {
+            self.movie.ram_checksums.push((frame, ram_checksum(cpu)));
+        }
+    }
+
+    /// 记录输入并运行一帧
+    pub fn run_frame(&mut self, cpu: &mut Cpu) -> Result<(), CpuHalted> {
+        self.before_frame(cpu);
+        let result = cpu.run_next_frame();
+        self.after_frame(cpu);
+        result
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn into_movie(self) -> Movie {
+        self.movie
+    }
+}
+
+/// 回放录像, 每帧运行前按录像设置手柄的按键, 录像结束后不再改变输入
+pub struct MoviePlayer {
+    movie: Movie,
+    frame: usize, // 已回放的帧数
+    next_checksum: usize, // 下一个要检查的校验值
+}
+
+impl MoviePlayer {
+    /// 检查 ROM, 并让机器回到录像的起点
+    pub fn new(movie: Movie, cpu: &mut Cpu) -> Result<Self, MovieError> {
+        let found = cpu.bus().rom_md5();
+        if movie.rom_checksum != found {
+            return Err(MovieError::RomMismatch { expected: movie.rom_checksum, found });
+        }
+        match &movie.savestate {
+            Some(state) => cpu.load_state(state).map_err(MovieError::SaveState)?,
+            None => cpu.power_cycle(),
+        }
+        Ok(MoviePlayer { movie, frame: 0, next_checksum: 0 })
+    }
+
+    /// 在 `run_next_frame` 之前调用, 执行这一帧的命令并设置手柄
+    pub fn before_frame(&mut self, cpu: &mut Cpu) {
+        if let Some(frame) = self.movie.frames.get(self.frame) {
+            apply_frame(cpu, frame.commands, frame.ports);
+            self.frame += 1;
+        }
+    }
+
+    /// 在 `run_next_frame` 之后调用, 检查是否与录制时同步
+    pub fn after_frame(&mut self, cpu: &Cpu) -> Result<(), MovieError> {
+        match self.movie.ram_checksums.get(self.next_checksum) {
+            Some(&(frame, expected)) if frame == self.frame => {
+                self.next_checksum += 1;
+                let found = ram_checksum(cpu);
+                if found != expected {
+                    return Err(MovieError::Desync { frame, expected, found });
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// 按录像设置输入并运行一帧
+    pub fn run_frame(&mut self, cpu: &mut Cpu) -> Result<(), MovieError> {
+        self.before_frame(cpu);
+        cpu.run_next_frame().map_err(MovieError::Halted)?;
+        self.after_frame(cpu)
+    }
+
+    /// 已回放的帧数
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.movie.frames.len()
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+}
+
+fn apply_frame(cpu: &mut Cpu, commands: MovieCommands, ports: [u8; 2]) {
+    if commands.contains(MovieCommands::POWER) {
+        cpu.power_cycle();
+    } else if commands.contains(MovieCommands::SOFT_RESET) {
+        cpu.reset();
+    }
+    let joypad = cpu.io_interface().1;
+    joypad.set_buttons(PlayerId::P1, ports[0]);
+    joypad.set_buttons(PlayerId::P2, ports[1]);
+}
+
+fn ram_checksum(cpu: &Cpu) -> u32 {
+    let ram: Vec<u8> = (0..0x800).map(|addr| cpu.bus().peek(addr)).collect();
+    save_state::crc32([&ram[..]])
+}
+
+/// 解析输入行 `|命令|手柄1|手柄2|扩展端口|` 去掉开头 `|` 后的部分, 未接手柄的端口为空
+fn parse_frame(fields: &str, ports: [bool; 2]) -> Result<MovieFrame, String> {
+    let mut fields = fields.split('|');
+    let commands = fields.next().unwrap().trim().parse::<u8>()
+        .map_err(|_| "invalid commands".to_string())?;
+    let mut frame = MovieFrame {
+        commands: MovieCommands::from_bits(commands).ok_or_else(|| format!("unsupported commands {}", commands))?,
+        ports: [0; 2],
+    };
+    for (buttons, connected) in frame.ports.iter_mut().zip(ports) {
+        let field = fields.next().ok_or_else(|| "missing port".to_string())?;
+        *buttons = match (field.len(), connected) {
+            (0, false) => 0,
+            (8, true) => parse_buttons(field),
+            _ => return Err(format!("invalid gamepad input {:?}", field)),
+        };
+    }
+    Ok(frame)
+}
+
+/// `RLDUTSBA` 顺序的按键, 第 i 个字符对应 `Joypad::set_buttons` 的 bit 7-i
+fn parse_buttons(field: &str) -> u8 {
+    field.bytes().enumerate()
+        .filter(|&(_, c)| c != b'.' && c != b' ')
+        .fold(0, |buttons, (idx, _)| buttons | 0x80 >> idx)
+}
+
+fn format_buttons(buttons: u8) -> String {
+    b"RLDUTSBA".iter().enumerate()
+        .map(|(idx, &c)| if buttons & 0x80 >> idx != 0 { c as char } else { '.' })
+        .collect()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// 由当前时间与 seed 生成 FCEUX 格式的 GUID
+fn new_guid(seed: &[u8]) -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos());
+    let hex = hex(&md5([&nanos.to_le_bytes()[..], seed])).to_uppercase();
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+const BASE64_CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut text = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (idx, &byte)| bits | (byte as u32) << (16 - 8 * idx));
+        for idx in 0..4 {
+            if idx <= chunk.len() {
+                text.push(BASE64_CHARS[(bits >> (18 - 6 * idx) & 0x3f) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=');
+    let mut data = Vec::with_capacity(text.len() * 3 / 4);
+    let (mut bits, mut len) = (0u32, 0);
+    for c in text.bytes() {
+        let value = BASE64_CHARS.iter().position(|&x| x == c)? as u32;
+        bits = bits << 6 | value;
+        len += 6;
+        if len >= 8 {
+            len -= 8;
+            data.push((bits >> len) as u8);
+        }
+    }
+    Some(data)
+}
+
+/// RFC 1321 MD5, FCEUX 以 PRG ROM 与 CHR ROM 的 MD5 识别 ROM
+pub(crate) fn md5<'a, I: IntoIterator<Item = &'a [u8]>>(chunks: I) -> [u8; 16] {
+    const SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+    const K: [u32; 64] = [
+        0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee, 0xf57c0faf, 0x4787c62a, 0xa8304613, 0xfd469501,
+        0x698098d8, 0x8b44f7af, 0xffff5bb1, 0x895cd7be, 0x6b901122, 0xfd987193, 0xa679438e, 0x49b40821,
+        0xf61e2562, 0xc040b340, 0x265e5a51, 0xe9b6c7aa, 0xd62f105d, 0x02441453, 0xd8a1e681, 0xe7d3fbc8,
+        0x21e1cde6, 0xc33707d6, 0xf4d50d87, 0x455a14ed, 0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a,
+        0xfffa3942, 0x8771f681, 0x6d9d6122, 0xfde5380c, 0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70,
+        0x289b7ec6, 0xeaa127fa, 0xd4ef3085, 0x04881d05, 0xd9d4d039, 0xe6db99e5, 0x1fa27cf8, 0xc4ac5665,
+        0xf4292244, 0x432aff97, 0xab9423a7, 0xfc93a039, 0x655b59c3, 0x8f0ccc92, 0xffeff47d, 0x85845dd1,
+        0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1, 0xf7537e82, 0xbd3af235, 0x2ad7d2bb, 0xeb86d391,
+    ];
+    let mut data: Vec<u8> = chunks.into_iter().flatten().copied().collect();
+    let bit_len = (data.len() as u64).wrapping_mul(8);
+    data.push(0x80);
+    while data.len() % 64 != 56 {
+        data.push(0);
+    }
+    data.extend_from_slice(&bit_len.to_le_bytes());
+
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+    for block in data.chunks(64) {
+        let words: Vec<u32> = block.chunks(4).map(|word| u32::from_le_bytes(word.try_into().unwrap())).collect();
+        let [mut a, mut b, mut c, mut d] = state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let rotated = a.wrapping_add(f).wrapping_add(K[i]).wrapping_add(words[g])
+                .rotate_left(SHIFTS[i / 16 * 4 + i % 4]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(rotated);
+        }
+        for (value, add) in state.iter_mut().zip([a, b, c, d]) {
+            *value = value.wrapping_add(add);
+        }
+    }
+    let mut digest = [0; 16];
+    for (bytes, value) in digest.chunks_mut(4).zip(state) {
+        bytes.copy_from_slice(&value.to_le_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::tests::test_rom_with_2_bank_prg;
+
+    fn test_prg() -> Vec<u8> {
+        let mut prg = vec![0xea; 0x8000];
+        let program: &[u8] = &[
+            0xa9, 0x80, // 8000 LDA #$80
+            0x8d, 0x00, 0x20, // 8002 STA $2000
+            0x4c, 0x05, 0x80, // 8005 JMP $8005
+            // NMI: 读取手柄 1, 累加到 $10
+            0xa9, 0x01, // 8008 LDA #$01
+            0x8d, 0x16, 0x40, // 800A STA $4016
+            0xa9, 0x00, // 800D LDA #$00
+            0x8d, 0x16, 0x40, // 800F STA $4016
+            0xa2, 0x08, // 8012 LDX #$08
+            0xad, 0x16, 0x40, // 8014 LDA $4016
+            0x4a, // 8017 LSR A
+            0x26, 0x11, // 8018 ROL $11
+            0xca, // 801A DEX
+            0xd0, 0xf7, // 801B BNE $8014
+            0xa5, 0x11, // 801D LDA $11
+            0x18, // 801F CLC
+            0x65, 0x10, // 8020 ADC $10
+            0x85, 0x10, // 8022 STA $10
+            0xe6, 0x12, // 8024 INC $12
+            0x40, // 8026 RTI
+        ];
+        prg[..program.len()].copy_from_slice(program);
+        prg[0x7ffa..0x7ffc].copy_from_slice(&[0x08, 0x80]); // NMI 向量
+        prg
+    }
+
+    #[test]
+    fn test_movie_record_and_playback() {
+        let mut cpu = Cpu::new(test_rom_with_2_bank_prg(test_prg()));
+        cpu.reset();
+        for _ in 0..10 {
+            cpu.run_next_frame().unwrap();
+        }
+
+        let mut recorder = MovieRecorder::power_on("test", &mut cpu);
+        for frame in 0..150u32 {
+            cpu.io_interface().1.set_buttons(PlayerId::P1, (frame * 7 % 256) as u8);
+            cpu.io_interface().1.set_buttons(PlayerId::P2, (frame % 3) as u8);
+            match frame {
+                70 => recorder.soft_reset(),
+                100 => recorder.power(),
+                _ => {}
+            }
+            recorder.run_frame(&mut cpu).unwrap();
+        }
+        let movie = recorder.into_movie();
+        assert_eq!(movie.frames.len(), 150);
+        assert_eq!(movie.frames[70].commands, MovieCommands::SOFT_RESET);
+        assert_eq!(movie.frames[100].commands, MovieCommands::POWER);
+        assert_eq!(movie.ram_checksums.iter().map(|&(frame, _)| frame).collect::<Vec<_>>(), vec![60, 120]);
+        assert_eq!(Movie::parse_fm2(&movie.to_fm2()).unwrap(), movie);
+
+        // 在不同状态的机器上回放, 结果与录制时完全相同
+        let mut other = Cpu::new(test_rom_with_2_bank_prg(test_prg()));
+        let mut player = MoviePlayer::new(movie.clone(), &mut other).unwrap();
+        while !player.is_finished() {
+            player.run_frame(&mut other).unwrap();
+        }
+        assert_eq!(player.frame(), 150);
+        assert_eq!(other.save_state(), cpu.save_state());
+
+        // 输入不同时检测到不同步
+        let mut changed = movie.clone();
+        changed.frames[30].ports[0] ^= 0x01;
+        let mut player = MoviePlayer::new(changed, &mut other).unwrap();
+        let errors: Vec<_> = (0..150).filter_map(|_| player.run_frame(&mut other).err()).collect();
+        assert!(matches!(errors[..], [MovieError::Desync { frame: 60, .. }]));
+
+        // 从存档开始的录像
+        let mut recorder = MovieRecorder::from_state("test", &cpu);
+        for _ in 0..20 {
+            cpu.io_interface().1.set_buttons(PlayerId::P1, 0x81);
+            recorder.run_frame(&mut cpu).unwrap();
+        }
+        let movie = Movie::parse_fm2(&recorder.movie().to_fm2()).unwrap();
+        let mut player = MoviePlayer::new(movie, &mut other).unwrap();
+        while !player.is_finished() {
+            player.run_frame(&mut other).unwrap();
+        }
+        assert_eq!(other.save_state(), cpu.save_state());
+
+        let mut movie = recorder.into_movie();
+        movie.rom_checksum = [0; 16];
+        assert!(matches!(MoviePlayer::new(movie, &mut other), Err(MovieError::RomMismatch { .. })));
+    }
+
+    #[test]
+    fn test_fm2_format() {
+        let text = "version 3\n\
+            emuVersion 22020\n\
+            rerecordCount 5\n\
+            palFlag 0\n\
+            romFilename smb\n\
+            romChecksum base64:jjYwGG411HcjG/j9UOVM3Q==\n\
+            guid 452DE2C3-EF43-2FA9-77AC-0677FC51543B\n\
+            fourscore 0\n\
+            port0 1\n\
+            port1 0\n\
+            port2 0\n\
+            comment author someone\n\
+            |1|........|||\n\
+            |0|R......A|||\n\
+            |0|.L.U.SB.|||\n";
+        let movie = Movie::parse_fm2(text).unwrap();
+        assert_eq!(movie.rom_filename, "smb");
+        assert_eq!(movie.rerecord_count, 5);
+        assert_eq!(base64_encode(&movie.rom_checksum), "jjYwGG411HcjG/j9UOVM3Q==");
+        assert_eq!(movie.frames, vec![
+            MovieFrame { commands: MovieCommands::SOFT_RESET, ports: [0, 0] },
+            MovieFrame { commands: MovieCommands::empty(), ports: [0x81, 0] },
+            MovieFrame { commands: MovieCommands::empty(), ports: [0x56, 0] },
+        ]);
+        assert!(movie.to_fm2().contains("|0|.L.U.SB.|........||\n"));
+
+        assert!(matches!(Movie::parse_fm2("fourscore 1\n"), Err(MovieError::Unsupported(_))));
+        assert!(matches!(Movie::parse_fm2("version 3\n|0|RLDU|........||\n"), Err(MovieError::Parse { line: 2, .. })));
+
+        assert_eq!(hex(&md5([&b""[..]])), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(
+            hex(&md5([&b"The quick brown fox "[..], &b"jumps over the lazy dog"[..]])),
+            "9e107d9d372bb6826bd81d3542a419d6"
+        );
+        for len in 0..8 {
+            let data: Vec<u8> = (0..len).map(|x| x * 37).collect();
+            assert_eq!(base64_decode(&base64_encode(&data)).unwrap(), data);
+        }
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+    }
+}
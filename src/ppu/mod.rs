@@ -1,6 +1,7 @@
 mod registers;
+mod overlay;
 
-use crate::common::Clock;
+use crate::{common::Clock, cdl::ChrFlags, save_state::{Snapshot, StateWriter, StateReader, SaveStateError}};
 use registers::{ControllerRegister, MaskRegister, StatusRegister, ScrollAddrRegister};
 
 
@@ -61,7 +62,7 @@ impl Frame {
     pub const WIDTH: usize = 256; // 32 * 8
     pub const HEIGHT: usize = 240; // 30 * 8
 
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Frame { data: vec![0; Frame::WIDTH * Frame::HEIGHT * 3] }
     }
 
@@ -198,9 +199,32 @@ pub(crate) struct Ppu {
     scanline: u16, // 扫描行数 0..262, 在 241 时生成 NMI 中断
     cycle: u16, // scanline 内 ppu 周期, 0..341
     frame: Frame,
+    frame_count: u64, // 已经完成的帧数
+    // 调试
+    chr_cdl: Option<Vec<u8>>, // 开启后记录 CHR ROM 每个字节的用途, 见 `ChrFlags`
 }
 
 
+impl Snapshot for Sprite {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.u8(self.y);
+        writer.u8(self.tile_index);
+        writer.u8(self.attributes);
+        writer.u8(self.x);
+        writer.bytes(&self.tile);
+        writer.bytes(&self.other_tile);
+    }
+
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.y = reader.u8()?;
+        self.tile_index = reader.u8()?;
+        self.attributes = reader.u8()?;
+        self.x = reader.u8()?;
+        reader.bytes(&mut self.tile)?;
+        reader.bytes(&mut self.other_tile)
+    }
+}
+
 impl Ppu {
     pub fn new(chr_rom: Vec<u8>, mirroring: Mirroring) -> Self {
         Ppu {
@@ -237,9 +261,20 @@ impl Ppu {
             scanline: 0,
             cycle: 0,
             frame: Frame::new(),
+            frame_count: 0,
+            chr_cdl: None,
         }
     }
 
+    /// 重新上电, 除 CHR ROM, mirroring 与调试记录外恢复初始状态
+    pub fn power_cycle(&mut self) {
+        let chr_rom = std::mem::take(&mut self.chr_rom);
+        let mirroring = std::mem::replace(&mut self.mirroring, Mirroring::HORIZONTAL);
+        let chr_cdl = self.chr_cdl.take();
+        *self = Ppu::new(chr_rom, mirroring);
+        self.chr_cdl = chr_cdl;
+    }
+
     /// 运行 1 个 PPU 周期
     /// 
     /// ## Background
@@ -377,6 +412,9 @@ impl Ppu {
         if self.cycle >= 341 { // cycle: 0-341
             self.cycle = 0;
             self.scanline = (self.scanline + 1) % 262; // scanleine: 0-161
+            if self.scanline == 0 {
+                self.frame_count += 1;
+            }
         } else {
             self.cycle += 1;
         }
@@ -386,6 +424,10 @@ impl Ppu {
         self.status.contains(StatusRegister::VBLANK_STARTED)
     }
 
+    pub fn sprite_zero_hit(&self) -> bool {
+        self.status.contains(StatusRegister::SPRITE_ZERO_HIT)
+    }
+
     /// 返回 nmi 线电平
     pub fn nmi_line_level(&self) -> bool {
         // NMI_occurred 推测即为 PPUSTATUS:VBLANK_STARTED
@@ -399,11 +441,54 @@ impl Ppu {
         }
     }
 
+    /// 当前扫描线 0..262, 其中 261 为 pre-render scanline
+    pub fn scanline(&self) -> u16 {
+        self.scanline
+    }
+
+    /// 当前扫描线内的 PPU 周期 0..341
+    pub fn cycle(&self) -> u16 {
+        self.cycle
+    }
+
+    /// 上电以来完成的帧数, 在 pre-render scanline 结束时加一
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    pub fn chr_rom_len(&self) -> usize {
+        self.chr_rom.len()
+    }
+
+    pub fn set_chr_cdl(&mut self, cdl: Option<Vec<u8>>) {
+        self.chr_cdl = cdl;
+    }
+
+    pub fn chr_cdl(&self) -> Option<&Vec<u8>> {
+        self.chr_cdl.as_ref()
+    }
+
+    pub fn take_chr_cdl(&mut self) -> Option<Vec<u8>> {
+        self.chr_cdl.take()
+    }
+
+    /// 渲染时读取 pattern table
+    fn fetch_chr(&mut self, addr: usize) -> u8 {
+        if let Some(cdl) = self.chr_cdl.as_mut() {
+            cdl[addr] |= ChrFlags::RENDERED.bits();
+        }
+        self.chr_rom[addr]
+    }
+
     /// 获得此时的屏幕状态
     pub fn frame(&self) -> &Frame {
         &self.frame
     }
 
+    pub fn frame_mut(&mut self) -> &mut Frame {
+        &mut self.frame
+    }
+
     fn rendering_enabled(&self) -> bool {
         self.mask.contains(MaskRegister::SHOW_BACKGROUND) || self.mask.contains(MaskRegister::SHOW_SPRITES)
     }
@@ -542,11 +627,11 @@ impl Ppu {
     }
 
     fn fetch_tile_lo(&mut self) {
-        self.fetched_tile_lo = self.chr_rom[self.fetched_tile_addr];
+        self.fetched_tile_lo = self.fetch_chr(self.fetched_tile_addr);
     }
 
     fn fetch_tile_hi(&mut self) {
-        self.fetched_tile_hi = self.chr_rom[self.fetched_tile_addr + 8];
+        self.fetched_tile_hi = self.fetch_chr(self.fetched_tile_addr + 8);
     }
 
     // -- sprite evaluation --
@@ -625,16 +710,16 @@ impl Ppu {
                             0usize
                         };
                         for idx in 0..16usize {
-                            self.current_sprites[n].tile[idx] = self.chr_rom[bank_base + tile_index * 16 + idx];
+                            self.current_sprites[n].tile[idx] = self.fetch_chr(bank_base + tile_index * 16 + idx);
                         }
                     } else {
                         let bank_base = (tile_index & 0x1) * 0x1000;
                         let tile_index = tile_index >> 1;
                         for idx in 0..16usize {
-                            self.current_sprites[n].tile[idx] = self.chr_rom[bank_base + tile_index * 16 + idx];
+                            self.current_sprites[n].tile[idx] = self.fetch_chr(bank_base + tile_index * 16 + idx);
                         }
                         for idx in 0..16usize {
-                            self.current_sprites[n].other_tile[idx] = self.chr_rom[bank_base + tile_index * 16 + 16 + idx];
+                            self.current_sprites[n].other_tile[idx] = self.fetch_chr(bank_base + tile_index * 16 + 16 + idx);
                         }
                     }
                 }
@@ -724,13 +809,18 @@ impl Ppu {
     }
 
     pub fn read_status(&mut self) -> u8 { // 0x2002
-        let data = self.status.bits();
+        let data = self.peek_status();
         self.status.remove(StatusRegister::VBLANK_STARTED);
         self.scroll_addr.reset_toggle();
         data
     }
 
 
+    /// 读取 $2002 将得到的值, 但不清除 vblank 标志与写入开关
+    pub fn peek_status(&self) -> u8 {
+        self.status.bits()
+    }
+
     pub fn write_to_oam_addr(&mut self, data: u8) { // 0x2003
         self.oam_addr = data;
     }
@@ -793,19 +883,36 @@ impl Ppu {
     pub fn read_data(&mut self) -> u8 {
         let addr = self.scroll_addr.get_addr();
         self.increment_vram_addr();
+        let data = self.peek_ppu(addr);
+        if let (0..=0x1fff, Some(cdl)) = (addr & 0x3fff, self.chr_cdl.as_mut()) {
+            cdl[(addr & 0x3fff) as usize] |= ChrFlags::READ.bits();
+        }
         match addr {
-            0..=0x1fff => {
-                let result = self.read_buffer;
-                self.read_buffer = self.chr_rom[addr as usize];
-                result
-            }
-            0x2000..=0x3eff => {
+            0..=0x3eff => { // 调色板以外的读取经过读缓冲, 得到的是上一次读取的值
                 let result = self.read_buffer;
-                let addr = self.vram_mirror_addr(addr);
-                self.read_buffer = self.vram[addr as usize];
+                self.read_buffer = data;
                 result
             }
-            0x3f00..=0x3fff => {
+            _ => data,
+        }
+    }
+
+    /// 读取 $2007 将得到的值, 但不改变 PPU 地址与读缓冲
+    pub fn peek_data(&self) -> u8 {
+        let addr = self.scroll_addr.get_addr();
+        match addr {
+            0..=0x3eff => self.read_buffer,
+            _ => self.peek_ppu(addr),
+        }
+    }
+
+    /// 无副作用地读取 PPU 地址空间($0000-$3FFF, 更高的地址镜像到该范围)
+    pub fn peek_ppu(&self, addr: u16) -> u8 {
+        let addr = addr & 0x3fff;
+        match addr {
+            0..=0x1fff => self.chr_rom[addr as usize],
+            0x2000..=0x3eff => self.vram[self.vram_mirror_addr(addr) as usize],
+            _ => {
                 let addr = addr & 0b0011_1111_0001_1111; // mirroring
                 match addr {
                     //  $3F10/$3F14/$3F18/$3F1C are mirrors of $3F00/$3F04/$3F08/$3F0C
@@ -817,10 +924,6 @@ impl Ppu {
                     }
                 }
             }
-            _ => {
-                log::warn!("Attempt to read from mirrored space PPU address {:04x}", addr);
-                0
-            }
         }
     }
 
@@ -839,4 +942,72 @@ impl Clock for Ppu {
         self.tick();
         self.tick();
     }
-}
\ No newline at end of file
+}
+
+/// 不含画面, 画面在下一帧重新生成
+impl Snapshot for Ppu {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.u8(self.controller.bits());
+        writer.u8(self.mask.bits());
+        writer.u8(self.status.bits());
+        writer.u8(self.oam_addr);
+        self.scroll_addr.save(writer);
+        writer.bytes(&self.palettes_ram);
+        writer.bytes(&self.vram);
+        writer.bytes(&self.oam_data);
+        writer.u8(self.read_buffer);
+        writer.u16(self.tile_hi_shift_register);
+        writer.u16(self.tile_lo_shift_register);
+        writer.u16(self.attr_hi_shift_register);
+        writer.u16(self.attr_lo_shift_register);
+        writer.u16(self.fetched_tile_addr as u16);
+        writer.u8(self.fetched_attribute);
+        writer.u8(self.fetched_tile_lo);
+        writer.u8(self.fetched_tile_hi);
+        for sprite in &self.current_sprites {
+            sprite.save(writer);
+        }
+        writer.bytes(&self.second_oam);
+        writer.u8(self.second_oam_n as u8);
+        writer.u8(self.sprite_eval_n as u8);
+        writer.u8(self.sprite_eval_m as u8);
+        writer.u8(self.sprite_eval_tmp_data);
+        writer.bool(self.sprite_eval_done);
+        writer.u16(self.scanline);
+        writer.u16(self.cycle);
+        writer.u64(self.frame_count);
+    }
+
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.controller = ControllerRegister::from_bits_truncate(reader.u8()?);
+        self.mask = MaskRegister::from_bits_truncate(reader.u8()?);
+        self.status = StatusRegister::from_bits_truncate(reader.u8()?);
+        self.oam_addr = reader.u8()?;
+        self.scroll_addr.load(reader)?;
+        reader.bytes(&mut self.palettes_ram)?;
+        reader.bytes(&mut self.vram)?;
+        reader.bytes(&mut self.oam_data)?;
+        self.read_buffer = reader.u8()?;
+        self.tile_hi_shift_register = reader.u16()?;
+        self.tile_lo_shift_register = reader.u16()?;
+        self.attr_hi_shift_register = reader.u16()?;
+        self.attr_lo_shift_register = reader.u16()?;
+        self.fetched_tile_addr = reader.u16()? as usize;
+        self.fetched_attribute = reader.u8()?;
+        self.fetched_tile_lo = reader.u8()?;
+        self.fetched_tile_hi = reader.u8()?;
+        for sprite in &mut self.current_sprites {
+            sprite.load(reader)?;
+        }
+        reader.bytes(&mut self.second_oam)?;
+        self.second_oam_n = reader.u8_max(8)? as usize;
+        self.sprite_eval_n = reader.u8_max(64)? as usize;
+        self.sprite_eval_m = reader.u8_max(3)? as usize;
+        self.sprite_eval_tmp_data = reader.u8()?;
+        self.sprite_eval_done = reader.bool()?;
+        self.scanline = reader.u16()?;
+        self.cycle = reader.u16()?;
+        self.frame_count = reader.u64()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+//! 在画面上叠加文字与矩形, 用于脚本与前端的提示信息
+//!
+//! 坐标可以为负或超出画面, 超出的部分被裁掉.
+
+use super::Frame;
+
+/// 3x5 点阵字体的字形, 每行取低 3 位, 高位在左. 小写字母按大写绘制, 未知字符画成实心方块
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        ' ' => [0b000, 0b000, 0b000, 0b000, 0b000],
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        ';' => [0b000, 0b010, 0b000, 0b010, 0b100],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '*' => [0b101, 0b010, 0b101, 0b000, 0b000],
+        '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+        '?' => [0b111, 0b001, 0b010, 0b000, 0b010],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '(' => [0b010, 0b100, 0b100, 0b100, 0b010],
+        ')' => [0b010, 0b001, 0b001, 0b001, 0b010],
+        '[' => [0b110, 0b100, 0b100, 0b100, 0b110],
+        ']' => [0b011, 0b001, 0b001, 0b001, 0b011],
+        '<' => [0b001, 0b010, 0b100, 0b010, 0b001],
+        '>' => [0b100, 0b010, 0b001, 0b010, 0b100],
+        '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+        '$' => [0b011, 0b110, 0b010, 0b011, 0b110],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '\'' => [0b010, 0b010, 0b000, 0b000, 0b000],
+        '"' => [0b101, 0b101, 0b000, 0b000, 0b000],
+        _ => [0b111; 5],
+    }
+}
+
+impl Frame {
+    /// 字符的宽度(含间隔)
+    pub const CHAR_WIDTH: i32 = 4;
+    /// 行高(含间隔)
+    pub const LINE_HEIGHT: i32 = 6;
+
+    /// (x, y) 处像素的颜色
+    pub fn pixel(&self, x: usize, y: usize) -> (u8, u8, u8) {
+        let base = (y * Frame::WIDTH + x) * 3;
+        (self.data[base], self.data[base + 1], self.data[base + 2])
+    }
+
+    /// 填充左上角为 (x, y) 的矩形
+    pub fn fill_rect(&mut self, x: i32, y: i32, width: i32, height: i32, rgb: (u8, u8, u8)) {
+        let x_range = x.max(0)..(x + width).min(Frame::WIDTH as i32);
+        for y in y.max(0)..(y + height).min(Frame::HEIGHT as i32) {
+            for x in x_range.clone() {
+                self.set_pixel(x as usize, y as usize, rgb);
+            }
+        }
+    }
+
+    /// 画 1 像素宽的矩形边框
+    pub fn draw_rect(&mut self, x: i32, y: i32, width: i32, height: i32, rgb: (u8, u8, u8)) {
+        if width <= 0 || height <= 0 {
+            return;
+        }
+        self.fill_rect(x, y, width, 1, rgb);
+        self.fill_rect(x, y + height - 1, width, 1, rgb);
+        self.fill_rect(x, y, 1, height, rgb);
+        self.fill_rect(x + width - 1, y, 1, height, rgb);
+    }
+
+    /// 以 3x5 点阵字体画出文字, (x, y) 为第一个字符的左上角, `\n` 换行
+    pub fn draw_text(&mut self, x: i32, y: i32, text: &str, rgb: (u8, u8, u8)) {
+        for (line_idx, line) in text.split('\n').enumerate() {
+            let top = y + line_idx as i32 * Frame::LINE_HEIGHT;
+            for (char_idx, c) in line.chars().enumerate() {
+                let left = x + char_idx as i32 * Frame::CHAR_WIDTH;
+                for (row, bits) in glyph(c).iter().enumerate() {
+                    for col in 0..3 {
+                        if bits & (0b100 >> col) != 0 {
+                            self.fill_rect(left + col, top + row as i32, 1, 1, rgb);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use crate::save_state::{Snapshot, StateWriter, StateReader, SaveStateError};
+
 
 /// 包含 PPUADDR, PPUSCROLL 与 PPUCTRL 的 2bit NN
 /// 
@@ -172,4 +174,21 @@ impl ScrollAddrRegister {
     pub fn coarse_y(&self) -> u8 {
         ((self.v & Self::COARSE_Y_MASK) >> 5) as u8
     } 
-}
\ No newline at end of file
+}
+
+impl Snapshot for ScrollAddrRegister {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.u16(self.v);
+        writer.u16(self.t);
+        writer.u8(self.x);
+        writer.bool(self.w);
+    }
+
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.v = reader.u16()?;
+        self.t = reader.u16()?;
+        self.x = reader.u8()?;
+        self.w = reader.bool()?;
+        Ok(())
+    }
+}
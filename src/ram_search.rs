@@ -0,0 +1,190 @@
+//! RAM 搜索, 通过反复比较数值的变化找出游戏保存某个数值(如生命数)的地址
+//!
+//! 搜索范围为 2KB CPU RAM($0000-$07FF)与 8KB PRG RAM($6000-$7FFF).
+//! 16 位的值按小端序读取, 不跨越两个区域.
+
+use std::ops::RangeInclusive;
+
+use crate::Peek;
+
+const REGIONS: [RangeInclusive<u16>; 2] = [0x0000..=0x07ff, 0x6000..=0x7fff];
+
+/// 值的宽度
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueSize {
+    Byte,
+    Word,
+}
+
+/// 比较方式, 左侧为当前值
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Greater,
+    Less,
+    GreaterOrEqual,
+    LessOrEqual,
+}
+
+impl Comparison {
+    fn test(&self, current: i32, other: i32) -> bool {
+        match self {
+            Comparison::Equal => current == other,
+            Comparison::NotEqual => current != other,
+            Comparison::Greater => current > other,
+            Comparison::Less => current < other,
+            Comparison::GreaterOrEqual => current >= other,
+            Comparison::LessOrEqual => current <= other,
+        }
+    }
+}
+
+/// 与什么比较
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareTo {
+    /// 上一次快照中的值
+    Previous,
+    /// 常数, 按当前的有无符号解释
+    Value(i32),
+}
+
+/// 一个候选地址
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Candidate {
+    pub addr: u16,
+    pub value: i32,
+    /// 上一次快照中的值
+    pub previous: i32,
+}
+
+/// 逐步缩小候选范围的 RAM 搜索
+#[derive(Debug, Clone)]
+pub struct RamSearch {
+    size: ValueSize,
+    signed: bool,
+    candidates: Vec<u16>,
+    previous: Vec<i32>, // 与 candidates 一一对应
+}
+
+impl RamSearch {
+    /// 以全部地址为候选开始搜索, 并保存快照
+    pub fn new<P: Peek>(memory: &P, size: ValueSize, signed: bool) -> Self {
+        let mut search = RamSearch { size, signed, candidates: vec![], previous: vec![] };
+        search.reset(memory);
+        search
+    }
+
+    /// 重新以全部地址为候选, 并保存快照
+    pub fn reset<P: Peek>(&mut self, memory: &P) {
+        let last = match self.size {
+            ValueSize::Byte => 0,
+            ValueSize::Word => 1,
+        };
+        self.candidates = REGIONS.iter()
+            .flat_map(|region| *region.start()..=*region.end() - last)
+            .collect();
+        self.snapshot(memory);
+    }
+
+    /// 改变值的解释方式, 保留候选并重新保存快照
+    pub fn set_view<P: Peek>(&mut self, memory: &P, size: ValueSize, signed: bool) {
+        if size == ValueSize::Word && self.size == ValueSize::Byte {
+            self.candidates.retain(|addr| !REGIONS.iter().any(|region| addr == region.end()));
+        }
+        self.size = size;
+        self.signed = signed;
+        self.snapshot(memory);
+    }
+
+    /// 用当前值更新快照, 不改变候选
+    pub fn snapshot<P: Peek>(&mut self, memory: &P) {
+        self.previous = self.candidates.iter().map(|&addr| self.value(memory, addr)).collect();
+    }
+
+    /// 只保留满足 `当前值 comparison 比较对象` 的候选, 之后更新快照, 返回剩余的候选数
+    pub fn filter<P: Peek>(&mut self, memory: &P, comparison: Comparison, compare_to: CompareTo) -> usize {
+        let mut candidates = vec![];
+        let mut previous = vec![];
+        for (&addr, &prev) in self.candidates.iter().zip(&self.previous) {
+            let value = self.value(memory, addr);
+            let other = match compare_to {
+                CompareTo::Previous => prev,
+                CompareTo::Value(other) => other,
+            };
+            if comparison.test(value, other) {
+                candidates.push(addr);
+                previous.push(value);
+            }
+        }
+        self.candidates = candidates;
+        self.previous = previous;
+        self.candidates.len()
+    }
+
+    /// 剩余的候选及其当前值
+    pub fn candidates<P: Peek>(&self, memory: &P) -> Vec<Candidate> {
+        self.candidates.iter().zip(&self.previous)
+            .map(|(&addr, &previous)| Candidate { addr, value: self.value(memory, addr), previous })
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+
+    fn value<P: Peek>(&self, memory: &P, addr: u16) -> i32 {
+        match (self.size, self.signed) {
+            (ValueSize::Byte, false) => memory.peek(addr) as i32,
+            (ValueSize::Byte, true) => memory.peek(addr) as i8 as i32,
+            (ValueSize::Word, signed) => {
+                let word = u16::from_le_bytes([memory.peek(addr), memory.peek(addr + 1)]);
+                if signed { word as i16 as i32 } else { word as i32 }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FlatBus;
+
+    #[test]
+    fn test_search() {
+        let mut memory = FlatBus::new();
+        memory.load(0x0040, &[3]);
+        memory.load(0x6010, &[3]);
+        let mut search = RamSearch::new(&memory, ValueSize::Byte, false);
+        assert_eq!(search.len(), 0x800 + 0x2000);
+
+        assert_eq!(search.filter(&memory, Comparison::Equal, CompareTo::Value(3)), 2);
+        memory.load(0x0040, &[2]);
+        memory.load(0x6010, &[4]);
+        assert_eq!(search.filter(&memory, Comparison::Less, CompareTo::Previous), 1);
+        assert_eq!(search.candidates(&memory), vec![Candidate { addr: 0x0040, value: 2, previous: 2 }]);
+        memory.load(0x0040, &[1]);
+        assert_eq!(search.candidates(&memory), vec![Candidate { addr: 0x0040, value: 1, previous: 2 }]);
+
+        // 有符号 16 位
+        memory.load(0x07fe, &[0xff, 0xff]);
+        memory.load(0x0100, &[0x00, 0x80]);
+        let mut search = RamSearch::new(&memory, ValueSize::Word, true);
+        assert_eq!(search.len(), 0x7ff + 0x1fff);
+        assert_eq!(search.filter(&memory, Comparison::Less, CompareTo::Value(-1)), 2);
+        assert_eq!(search.candidates(&memory), vec![
+            Candidate { addr: 0x0100, value: -32768, previous: -32768 },
+            Candidate { addr: 0x07fd, value: -256, previous: -256 },
+        ]);
+        search.set_view(&memory, ValueSize::Word, false);
+        assert_eq!(search.filter(&memory, Comparison::Greater, CompareTo::Value(0x8000)), 1);
+
+        search.reset(&memory);
+        assert_eq!(search.filter(&memory, Comparison::Equal, CompareTo::Value(-1)), 0);
+        assert!(search.is_empty());
+    }
+}
@@ -0,0 +1,247 @@
+//! 倒带, 每帧保存一次即时存档, 用于按住按键时逐帧倒退
+//!
+//! 只完整保存最新的存档, 更早的存档保存为"由后一个存档得到它"的差分:
+//! 两者按字节异或后, 连续的 0 只记录长度. 相邻帧之间变化很少, 每帧通常只需要几百字节.
+//! 超出内存预算时丢弃最早的差分.
+
+use std::collections::VecDeque;
+
+use crate::Cpu;
+
+/// 倒带缓冲区
+pub struct Rewind {
+    budget: usize, // 差分的总字节数上限
+    latest: Option<Vec<u8>>, // 最新的存档
+    deltas: VecDeque<Vec<u8>>, // 由后一个存档得到前一个存档的差分, 最早的在前
+    delta_bytes: usize,
+}
+
+impl Rewind {
+    /// budget 为差分占用内存的上限(字节)
+    pub fn new(budget: usize) -> Self {
+        Rewind {
+            budget,
+            latest: None,
+            deltas: VecDeque::new(),
+            delta_bytes: 0,
+        }
+    }
+
+    /// 每帧结束后调用, 记录当前状态
+    pub fn push(&mut self, cpu: &Cpu) {
+        let state = cpu.save_state();
+        if let Some(latest) = self.latest.take() {
+            let delta = encode_delta(&state, &latest);
+            self.delta_bytes += delta.len();
+            self.deltas.push_back(delta);
+            while self.delta_bytes > self.budget {
+                match self.deltas.pop_front() {
+                    Some(delta) => self.delta_bytes -= delta.len(),
+                    None => break,
+                }
+            }
+        }
+        self.latest = Some(state);
+    }
+
+    /// 倒退一帧, 返回是否成功
+    ///
+    /// 恢复到前两帧结束时的状态, 再运行一帧, 因而画面与这一帧的声音样本都与当时相同.
+    /// 重新运行时的输入可能与当时不同, 运行后再恢复为前一帧结束时的状态, 它成为新的最新存档.
+    pub fn step_back(&mut self, cpu: &mut Cpu) -> bool {
+        if self.deltas.len() < 2 {
+            return false;
+        }
+        let latest = self.latest.take().unwrap();
+        let delta = self.deltas.pop_back().unwrap();
+        self.delta_bytes -= delta.len();
+        let previous = apply_delta(&latest, &delta);
+        let before_previous = apply_delta(&previous, self.deltas.back().unwrap());
+        cpu.restore_own_state(&before_previous);
+        let _ = cpu.run_next_frame();
+        cpu.restore_own_state(&previous);
+        self.latest = Some(previous);
+        true
+    }
+
+    /// 可以倒退的帧数
+    pub fn len(&self) -> usize {
+        self.deltas.len().saturating_sub(1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 差分与最新存档占用的字节数
+    pub fn memory_usage(&self) -> usize {
+        self.delta_bytes + self.latest.as_ref().map_or(0, |state| state.len())
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+        self.delta_bytes = 0;
+    }
+}
+
+/// 差分格式: 标记 0 后接若干组 `[0 的个数][异或值的个数][异或值...]`, 个数为 LEB128 变长整数.
+/// 两者长度不同(不应出现)时为标记 1 后接完整的 target
+fn encode_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    if base.len() != target.len() {
+        let mut delta = vec![1];
+        delta.extend_from_slice(target);
+        return delta;
+    }
+    let mut delta = vec![0];
+    let xor: Vec<u8> = base.iter().zip(target).map(|(a, b)| a ^ b).collect();
+    let mut pos = 0;
+    while pos < xor.len() {
+        let zeros = xor[pos..].iter().take_while(|&&byte| byte == 0).count();
+        pos += zeros;
+        let literals = xor[pos..].windows(2)
+            .position(|pair| pair == [0, 0]) // 单个 0 不值得另起一组
+            .unwrap_or(xor.len() - pos);
+        write_varint(&mut delta, zeros);
+        write_varint(&mut delta, literals);
+        delta.extend_from_slice(&xor[pos..pos + literals]);
+        pos += literals;
+    }
+    delta
+}
+
+fn apply_delta(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    if delta[0] == 1 {
+        return delta[1..].to_vec();
+    }
+    let mut target = base.to_vec();
+    let (mut pos, mut idx) = (0, 1);
+    while idx < delta.len() {
+        pos += read_varint(delta, &mut idx);
+        let literals = read_varint(delta, &mut idx);
+        for (byte, xor) in target[pos..pos + literals].iter_mut().zip(&delta[idx..idx + literals]) {
+            *byte ^= xor;
+        }
+        pos += literals;
+        idx += literals;
+    }
+    target
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], idx: &mut usize) -> usize {
+    let (mut value, mut shift) = (0, 0);
+    loop {
+        let byte = data[*idx];
+        *idx += 1;
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Peek, PlayerId, cartridge::tests::test_rom_with_2_bank_prg};
+
+    #[test]
+    fn test_rewind() {
+        let mut prg = vec![0xea; 0x8000];
+        let program: &[u8] = &[
+            0xa9, 0x80, // 8000 LDA #$80
+            0x8d, 0x00, 0x20, // 8002 STA $2000
+            0xa9, 0x00, // 8005 LDA #$00
+            0x8d, 0x01, 0x20, // 8007 STA $2001, 不开启渲染, 画面为背景色
+            0xa9, 0x0f, // 800A LDA #$0f
+            0x8d, 0x15, 0x40, // 800C STA $4015
+            0xa9, 0xbf, // 800F LDA #$bf
+            0x8d, 0x00, 0x40, // 8011 STA $4000
+            0x8d, 0x02, 0x40, // 8014 STA $4002
+            0x8d, 0x03, 0x40, // 8017 STA $4003
+            0x4c, 0x1a, 0x80, // 801A JMP $801A
+            // NMI: 每帧改变背景色与音高
+            0xe6, 0x10, // 801D INC $10
+            0xa9, 0x3f, // 801F LDA #$3f
+            0x8d, 0x06, 0x20, // 8021 STA $2006
+            0xa9, 0x00, // 8024 LDA #$00
+            0x8d, 0x06, 0x20, // 8026 STA $2006
+            0xa5, 0x10, // 8029 LDA $10
+            0x29, 0x3f, // 802B AND #$3f
+            0x8d, 0x07, 0x20, // 802D STA $2007
+            0x8d, 0x02, 0x40, // 8030 STA $4002, 每帧改变音高
+            0xa9, 0x01, // 8033 LDA #$01
+            0x8d, 0x16, 0x40, // 8035 STA $4016
+            0xa9, 0x00, // 8038 LDA #$00
+            0x8d, 0x16, 0x40, // 803A STA $4016
+            0xad, 0x16, 0x40, // 803D LDA $4016, 读取 A 键
+            0x85, 0x11, // 8040 STA $11
+            0x40, // 8042 RTI
+        ];
+        prg[..program.len()].copy_from_slice(program);
+        prg[0x7ffa..0x7ffc].copy_from_slice(&[0x1d, 0x80]); // NMI 向量
+        let mut cpu = Cpu::new(test_rom_with_2_bank_prg(prg));
+        cpu.reset();
+
+        let mut rewind = Rewind::new(1 << 20);
+        assert!(!rewind.step_back(&mut cpu));
+        let mut history = vec![];
+        for n in 0..20 {
+            // 每帧的输入都不同
+            cpu.io_interface().1.set_buttons(PlayerId::P1, n % 2);
+            cpu.run_next_frame().unwrap();
+            let (frame, _, samples) = cpu.io_interface();
+            history.push((frame.data().to_vec(), samples.data().to_vec(), cpu.save_state()));
+            cpu.io_interface().2.clear();
+            rewind.push(&cpu);
+        }
+        // 第一帧之前没有存档, 无法重建第一帧
+        assert_eq!(rewind.len(), 18);
+        // 每帧的差分远小于完整的存档
+        assert!(rewind.memory_usage() < cpu.save_state().len() * 3);
+
+        let counter = cpu.bus().peek(0x0010);
+        for back in 1..=18 {
+            assert!(rewind.step_back(&mut cpu));
+            let (frame, _, samples) = cpu.io_interface();
+            let (expected_frame, expected_samples, expected_state) = &history[19 - back];
+            assert_eq!(frame.data(), &expected_frame[..]);
+            assert_eq!(samples.data(), &expected_samples[..]);
+            samples.clear();
+            assert_eq!(cpu.bus().peek(0x0010), counter - back as u8);
+            assert_eq!(cpu.bus().peek(0x0011) & 1, (19 - back as u8) % 2);
+            assert_eq!(&cpu.save_state(), expected_state);
+        }
+        assert!(rewind.is_empty());
+        assert!(!rewind.step_back(&mut cpu));
+        assert_ne!(history[1].1, history[2].1);
+
+        // 超出预算时丢弃最早的差分
+        let mut rewind = Rewind::new(1);
+        for _ in 0..5 {
+            cpu.run_next_frame().unwrap();
+            rewind.push(&cpu);
+        }
+        assert!(rewind.is_empty());
+
+        let base = vec![1, 2, 3, 0, 0, 0, 7, 8, 9, 10];
+        let target = vec![1, 2, 4, 0, 0, 0, 7, 9, 9, 11];
+        let delta = encode_delta(&base, &target);
+        assert_eq!(apply_delta(&base, &delta), target);
+        assert_eq!(apply_delta(&base, &encode_delta(&base, &base)), base);
+        assert_eq!(apply_delta(&base, &encode_delta(&base, &[5])), vec![5]);
+        let long = vec![0; 1000];
+        let mut changed = long.clone();
+        changed[999] = 1;
+        assert_eq!(encode_delta(&long, &changed), vec![0, 0xe7, 0x07, 1, 1]);
+    }
+}
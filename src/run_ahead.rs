@@ -0,0 +1,116 @@
+//! 预运行(run-ahead), 用于减少输入延迟
+//!
+//! 游戏通常在读取输入一帧或几帧之后才显示结果. 每帧正常运行后保存状态, 以当前输入再运行 N 帧,
+//! 丢弃这些帧的声音, 然后恢复状态. 画面不属于存档, 因而恢复后保留的是 N 帧之后的画面.
+//! 预运行的帧不会触发总线回调与指令回调, 也不会进入事件查看器.
+
+use crate::{Cpu, CpuHalted};
+
+pub struct RunAhead {
+    frames: usize,
+    state: Vec<u8>,
+}
+
+impl RunAhead {
+    /// frames 为预运行的帧数, 为 0 时与直接运行相同
+    pub fn new(frames: usize) -> Self {
+        RunAhead { frames, state: vec![] }
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    pub fn set_frames(&mut self, frames: usize) {
+        self.frames = frames;
+    }
+
+    /// 运行一帧, 之后的状态与声音和直接运行一帧相同, 画面为预运行的最后一帧
+    pub fn run_frame(&mut self, cpu: &mut Cpu) -> Result<(), CpuHalted> {
+        cpu.run_next_frame()?;
+        if self.frames == 0 {
+            return Ok(());
+        }
+        let samples_len = cpu.io_interface().2.data().len();
+        cpu.save_state_into(&mut self.state);
+        cpu.bus_mut().set_speculative(true);
+        let hook = cpu.set_instruction_hook(None);
+        for _ in 0..self.frames {
+            if cpu.run_next_frame().is_err() {
+                break;
+            }
+        }
+        cpu.set_instruction_hook(hook);
+        cpu.bus_mut().set_speculative(false);
+        cpu.io_interface().2.truncate(samples_len);
+        cpu.restore_own_state(&self.state);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HookEvents, TraceFormat, TraceLogger, cartridge::tests::test_rom_with_2_bank_prg};
+    use std::{cell::Cell, rc::Rc};
+
+    #[test]
+    fn test_run_ahead() {
+        let mut prg = vec![0xea; 0x8000];
+        let program: &[u8] = &[
+            0xa9, 0x80, // 8000 LDA #$80
+            0x8d, 0x00, 0x20, // 8002 STA $2000
+            0xa9, 0x0f, // 8005 LDA #$0f
+            0x8d, 0x15, 0x40, // 8007 STA $4015
+            0xa9, 0xbf, // 800A LDA #$bf
+            0x8d, 0x00, 0x40, // 800C STA $4000
+            0x8d, 0x03, 0x40, // 800F STA $4003
+            0x4c, 0x12, 0x80, // 8012 JMP $8012
+            0xe6, 0x10, // 8015 INC $10
+            0xa5, 0x10, // 8017 LDA $10
+            0x8d, 0x02, 0x40, // 8019 STA $4002
+            0x40, // 801C RTI
+        ];
+        prg[..program.len()].copy_from_slice(program);
+        prg[0x7ffa..0x7ffc].copy_from_slice(&[0x15, 0x80]); // NMI 向量
+        let new_cpu = || {
+            let mut cpu = Cpu::new(test_rom_with_2_bank_prg(prg.clone()));
+            cpu.reset();
+            cpu
+        };
+        let mut expected = new_cpu();
+        let mut cpu = new_cpu();
+        let frames = Rc::new(Cell::new(0));
+        {
+            let frames = frames.clone();
+            cpu.bus_mut().add_hook(HookEvents::FRAME_END, move |_| frames.set(frames.get() + 1));
+        }
+        let trace_logger = || Some(Box::new(TraceLogger::new(TraceFormat::nestest(), std::io::sink())) as _);
+        cpu.set_instruction_hook(trace_logger());
+        expected.set_instruction_hook(trace_logger());
+
+        let mut run_ahead = RunAhead::new(2);
+        for _ in 0..10 {
+            expected.run_next_frame().unwrap();
+            run_ahead.run_frame(&mut cpu).unwrap();
+            assert_eq!(cpu.save_state(), expected.save_state());
+            assert_eq!(cpu.io_interface().2.data(), expected.io_interface().2.data());
+        }
+        // 预运行的帧不触发回调, 也不进入事件查看器
+        assert_eq!(frames.get(), 10);
+        let lines = |cpu: &Cpu| cpu.instruction_hook::<TraceLogger>().unwrap().lines();
+        assert_eq!(lines(&cpu), lines(&expected));
+        cpu.bus_mut().set_event_viewer_enabled(true);
+        expected.bus_mut().set_event_viewer_enabled(true);
+        for _ in 0..2 {
+            expected.run_next_frame().unwrap();
+            run_ahead.run_frame(&mut cpu).unwrap();
+        }
+        assert_eq!(cpu.bus().event_viewer().unwrap().events(), expected.bus().event_viewer().unwrap().events());
+        assert!(!cpu.bus().event_viewer().unwrap().events().is_empty());
+
+        run_ahead.set_frames(0);
+        run_ahead.run_frame(&mut cpu).unwrap();
+        assert_eq!(frames.get(), 13);
+    }
+}
@@ -0,0 +1,329 @@
+//! 即时存档, 保存整台机器的状态
+//!
+//! 通过 [`Cpu::save_state`](crate::Cpu::save_state) 与 [`Cpu::load_state`](crate::Cpu::load_state) 使用.
+//! 存档为二进制格式, 所有整数均为小端序:
+//!
+//! | 偏移 | 长度 | 内容 |
+//! |------|------|------|
+//! | 0    | 8    | 魔数 `CNESSTAT` |
+//! | 8    | 2    | 格式版本, 见 [`SAVE_STATE_VERSION`] |
+//! | 10   | 4    | ROM 的 CRC32, 按 PRG ROM, CHR ROM 的顺序计算 |
+//! | 14   | ...  | 各节 |
+//!
+//! 每节由 4 字节标签, 4 字节内容长度与内容组成, 依次为:
+//! - `CPU `: 寄存器, 中断线与轮询状态, 停机状态
+//! - `BUS `: 2KB CPU RAM, 周期数, 中断线电平, 本帧是否读取过手柄与 lag 帧计数
+//! - `PPU `: 寄存器, 内部的 v/t/x/w, 调色板, 2KB VRAM, OAM, secondary OAM, 移位寄存器与锁存器,
+//!   本行的 sprite, 扫描线, 周期与帧数(不含画面)
+//! - `APU `: 两个方波, 三角波, 噪声, DMC 通道与帧计数器(不含未取走的样本)
+//! - `JOYP`: 最近写入 $4016 的值, 两个端口上设备的名称与状态(设备与存档时不同的端口不恢复)
+//! - `MAPR`: 卡带状态, 目前只支持 NROM, 只有 8KB PRG RAM
+//!
+//! 节内字段的顺序见各部件的 [`Snapshot`] 实现. bool 占 1 字节, `Option` 为 1 字节的有无标记加内容.
+
+use std::{error::Error, fmt};
+
+const MAGIC: &[u8; 8] = b"CNESSTAT";
+
+/// 当前的存档格式版本, 格式有任何改变都要增加
+pub const SAVE_STATE_VERSION: u16 = 3;
+
+/// 读取存档失败的原因, 失败时机器状态不变
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SaveStateError {
+    /// 不是存档文件
+    InvalidFormat,
+    /// 存档的格式版本与当前不同
+    UnsupportedVersion(u16),
+    /// 存档属于另一个 ROM
+    RomMismatch { expected: u32, found: u32 },
+    /// 存档不完整或内容错误
+    Corrupted(String),
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveStateError::InvalidFormat => write!(f, "not a save state"),
+            SaveStateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {} (expected {})", version, SAVE_STATE_VERSION)
+            }
+            SaveStateError::RomMismatch { expected, found } => {
+                write!(f, "save state is for ROM {:08x}, but ROM {:08x} is loaded", found, expected)
+            }
+            SaveStateError::Corrupted(message) => write!(f, "corrupted save state: {}", message),
+        }
+    }
+}
+
+impl Error for SaveStateError {}
+
+/// 可以保存与恢复状态的部件
+pub(crate) trait Snapshot {
+    fn save(&self, writer: &mut StateWriter);
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError>;
+}
+
+pub(crate) struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    /// 在 data 之后写入
+    pub fn with_buffer(data: Vec<u8>) -> Self {
+        StateWriter { data }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// 定长的字节, 长度不写入存档
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    /// 变长的字节, 先写入 4 字节长度
+    pub fn blob(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.bytes(bytes);
+    }
+
+    /// 写入一节, 长度在内容写完后补上
+    pub fn section<F: FnOnce(&mut Self)>(&mut self, tag: &[u8; 4], content: F) {
+        self.bytes(tag);
+        let len_pos = self.data.len();
+        self.u32(0);
+        content(self);
+        let len = (self.data.len() - len_pos - 4) as u32;
+        self.data[len_pos..len_pos + 4].copy_from_slice(&len.to_le_bytes());
+    }
+}
+
+pub(crate) struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        StateReader { data, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        if self.data.len() - self.pos < len {
+            return Err(SaveStateError::Corrupted("unexpected end of data".to_string()));
+        }
+        self.pos += len;
+        Ok(&self.data[self.pos - len..self.pos])
+    }
+
+    pub fn u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, SaveStateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(SaveStateError::Corrupted(format!("invalid bool {}", value))),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// 读取 u8 并检查其不大于 max, 用于枚举与下标
+    pub fn u8_max(&mut self, max: u8) -> Result<u8, SaveStateError> {
+        let value = self.u8()?;
+        if value > max {
+            return Err(SaveStateError::Corrupted(format!("value {} out of range 0..={}", value, max)));
+        }
+        Ok(value)
+    }
+
+    /// 读取恰好填满 bytes 的字节
+    pub fn bytes(&mut self, bytes: &mut [u8]) -> Result<(), SaveStateError> {
+        bytes.copy_from_slice(self.take(bytes.len())?);
+        Ok(())
+    }
+
+    /// 读取 `StateWriter::blob` 写入的变长字节
+    pub fn blob(&mut self) -> Result<&'a [u8], SaveStateError> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    /// 确认已经读完全部数据
+    pub fn finish(&self) -> Result<(), SaveStateError> {
+        if self.pos != self.data.len() {
+            return Err(SaveStateError::Corrupted("unexpected data after the last section".to_string()));
+        }
+        Ok(())
+    }
+
+    /// 读取一节, 标签必须相符且内容必须恰好读完
+    pub fn section<F>(&mut self, tag: &[u8; 4], content: F) -> Result<(), SaveStateError>
+    where
+        F: FnOnce(&mut StateReader<'a>) -> Result<(), SaveStateError>,
+    {
+        let found = self.take(4)?;
+        if found != tag {
+            return Err(SaveStateError::Corrupted(format!(
+                "expected section '{}', found '{}'", String::from_utf8_lossy(tag), String::from_utf8_lossy(found)
+            )));
+        }
+        let len = self.u32()? as usize;
+        let mut reader = StateReader::new(self.take(len)?);
+        content(&mut reader)?;
+        reader.finish()
+    }
+}
+
+/// 写入文件头, 之后由调用者写入各节
+pub(crate) fn write_header(writer: &mut StateWriter, rom_crc32: u32) {
+    writer.bytes(MAGIC);
+    writer.u16(SAVE_STATE_VERSION);
+    writer.u32(rom_crc32);
+}
+
+/// 检查文件头, 返回指向第一节的 reader
+pub(crate) fn read_header(data: &[u8], rom_crc32: u32) -> Result<StateReader<'_>, SaveStateError> {
+    if data.len() < 14 || &data[..8] != MAGIC {
+        return Err(SaveStateError::InvalidFormat);
+    }
+    let mut reader = StateReader::new(&data[8..]);
+    let version = reader.u16()?;
+    if version != SAVE_STATE_VERSION {
+        return Err(SaveStateError::UnsupportedVersion(version));
+    }
+    let found = reader.u32()?;
+    if found != rom_crc32 {
+        return Err(SaveStateError::RomMismatch { expected: rom_crc32, found });
+    }
+    Ok(reader)
+}
+
+/// CRC-32(IEEE 802.3), 与 zip 及各模拟器显示的 ROM CRC 相同
+pub(crate) fn crc32<'a, I: IntoIterator<Item = &'a [u8]>>(chunks: I) -> u32 {
+    let mut crc = !0u32;
+    for chunk in chunks {
+        for &byte in chunk {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+            }
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cpu, CpuBus, Peek, JoypadButton, PlayerId, cartridge::tests::test_rom_with_2_bank_prg};
+
+    fn test_cpu(prg: Vec<u8>) -> Cpu {
+        let mut cpu = Cpu::new(test_rom_with_2_bank_prg(prg));
+        cpu.reset();
+        cpu
+    }
+
+    #[test]
+    fn test_save_and_load_state() {
+        assert_eq!(crc32([&b"123456789"[..]]), 0xcbf4_3926);
+
+        let mut prg = vec![0xea; 0x8000];
+        let program: &[u8] = &[
+            0xa9, 0x90, // 8000 LDA #$90
+            0x8d, 0x00, 0x20, // 8002 STA $2000
+            0xa9, 0x0f, // 8005 LDA #$0f
+            0x8d, 0x15, 0x40, // 8007 STA $4015
+            0xa9, 0xbf, // 800A LDA #$bf
+            0x8d, 0x00, 0x40, // 800C STA $4000
+            0xe6, 0x10, // 800F INC $10
+            0x4c, 0x0f, 0x80, // 8011 JMP $800F
+            0xe6, 0x11, // 8014 INC $11
+            0x40, // 8016 RTI
+        ];
+        prg[..program.len()].copy_from_slice(program);
+        prg[0x7ffa..0x7ffc].copy_from_slice(&[0x14, 0x80]); // NMI 向量
+        let mut cpu = test_cpu(prg.clone());
+        cpu.bus_mut().write(0x6000, 0x42);
+        cpu.io_interface().1.set_button_pressed(PlayerId::P1, JoypadButton::A, true);
+        for _ in 0..3 {
+            cpu.run_next_frame().unwrap();
+        }
+        for _ in 0..123 {
+            cpu.run_next_instruction();
+        }
+
+        let state = cpu.save_state();
+        assert_eq!(&state[..8], MAGIC);
+        cpu.io_interface().2.clear();
+
+        // 继续运行后恢复, 之后的运行与存档时完全相同
+        let mut expected = vec![];
+        for _ in 0..2 {
+            cpu.run_next_frame().unwrap();
+            expected.push((cpu.registers(), cpu.bus().cycles(), cpu.bus().peek(0x0010), cpu.bus().peek(0x0011), cpu.io_interface().0.data().to_vec()));
+        }
+        let samples = cpu.io_interface().2.data().len();
+        cpu.load_state(&state).unwrap();
+        cpu.io_interface().2.clear();
+        for expected in &expected {
+            cpu.run_next_frame().unwrap();
+            assert_eq!(&(cpu.registers(), cpu.bus().cycles(), cpu.bus().peek(0x0010), cpu.bus().peek(0x0011), cpu.io_interface().0.data().to_vec()), expected);
+        }
+        assert_eq!(cpu.io_interface().2.data().len(), samples);
+        assert_eq!(cpu.save_state().len(), state.len());
+
+        // 在另一台机器上恢复
+        let mut other = test_cpu(prg.clone());
+        other.load_state(&state).unwrap();
+        assert_eq!(other.bus().peek(0x6000), 0x42);
+        assert_eq!(other.save_state(), state);
+
+        // 失败时状态不变
+        let before = other.save_state();
+        let mut wrong_version = state.clone();
+        wrong_version[8] = 99;
+        assert_eq!(other.load_state(&wrong_version), Err(SaveStateError::UnsupportedVersion(99)));
+        assert_eq!(other.load_state(&state[..state.len() - 1]).unwrap_err().to_string(), "corrupted save state: unexpected end of data");
+        assert_eq!(other.load_state(b"not a state"), Err(SaveStateError::InvalidFormat));
+        let mut other_prg = prg;
+        other_prg[0x100] = 0;
+        let mut other_rom = test_cpu(other_prg);
+        assert!(matches!(other_rom.load_state(&state), Err(SaveStateError::RomMismatch { .. })));
+        assert_eq!(other.save_state(), before);
+    }
+}
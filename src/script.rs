@@ -0,0 +1,354 @@
+//! 嵌入式 Rhai 脚本, 用于自动化操作与画面叠加(需要 `scripting` feature)
+//!
+//! 脚本可以读写内存, 按下手柄按键, 推进帧, 在内存被写入或每帧结束时回调, 并在画面上画文字与矩形.
+//! 无界面运行时由脚本的顶层代码调用 `frame_advance()` 推进模拟;
+//! 在 `simple_run` 中由前端推进帧, 脚本只在加载时执行一次顶层代码, 之后通过 `on_frame` 注册的闭包每帧执行.
+//!
+//! ```text
+//! let lives = 0;
+//! on_write(0x075a, |addr, value| { lives = value; });
+//! on_frame(|| { draw_text(8, 8, `LIVES ${lives}`, 0xffffff); });
+//! ```
+//!
+//! 可用的函数:
+//! - `read(addr)`, `read_word(addr)`: 无副作用地读取内存; `write(addr, value)`: 写入内存
+//! - `press(player, button)`, `release(player, button)`: player 为 1..=4(3, 4 需要 Four Score), button 为
+//!   `"A"`, `"B"`, `"SELECT"`, `"START"`, `"UP"`, `"DOWN"`, `"LEFT"`, `"RIGHT"` 之一
+//! - `frame_advance()`: 运行一帧, 然后像前端一样调用回调; `frame_count()`, `cycles()`, `reset()`
+//! - `lag_frame()`: 刚结束的一帧是否没有读取手柄; `lag_count()`: lag 帧总数
+//! - `save_state()`: 返回即时存档(blob); `load_state(state)`: 恢复即时存档
+//! - `registers()`: 返回包含 `a`, `x`, `y`, `p`, `sp`, `pc` 的对象
+//! - `on_frame(fn)`, `on_write(addr, fn)`, `on_write(start, end, fn)`: 注册回调, 写入回调的参数为地址与值,
+//!   在当前帧结束后按写入顺序调用
+//! - `draw_text(x, y, text, color)`, `draw_rect(x, y, width, height, color)`,
+//!   `fill_rect(x, y, width, height, color)`: 画在当前画面上, color 为 0xRRGGBB
+//! - `print(text)`: 输出到日志
+
+use std::{cell::{Cell, RefCell}, error::Error, fmt, fs, ops::RangeInclusive, path::Path, rc::Rc};
+
+use rhai::{Blob, Dynamic, Engine, EvalAltResult, FnPtr, Map, NativeCallContext, AST, INT};
+
+use crate::{Cpu, CpuBus, Peek, HookEvents, HookId, BusEvent, JoypadButton, PlayerId};
+
+/// 脚本的编译或运行错误
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptError {
+    pub message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for ScriptError {}
+
+impl From<Box<EvalAltResult>> for ScriptError {
+    fn from(err: Box<EvalAltResult>) -> Self {
+        ScriptError { message: err.to_string() }
+    }
+}
+
+impl From<rhai::ParseError> for ScriptError {
+    fn from(err: rhai::ParseError) -> Self {
+        ScriptError { message: err.to_string() }
+    }
+}
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// 脚本与注册到引擎中的函数共享的状态
+struct Shared {
+    cpu: Rc<RefCell<Cpu>>,
+    frame_callbacks: RefCell<Vec<FnPtr>>,
+    write_callbacks: RefCell<Vec<(RangeInclusive<u16>, FnPtr)>>,
+    watched: Rc<RefCell<Vec<RangeInclusive<u16>>>>, // 与 write_callbacks 相同, 供总线回调过滤
+    pending_writes: Rc<RefCell<Vec<(u16, u8)>>>,
+    hook: Cell<Option<HookId>>,
+}
+
+impl Shared {
+    fn add_write_callback(&self, range: RangeInclusive<u16>, callback: FnPtr) {
+        if self.hook.get().is_none() {
+            let watched = self.watched.clone();
+            let pending_writes = self.pending_writes.clone();
+            let id = self.cpu.borrow_mut().bus_mut().add_hook(HookEvents::CPU_WRITE, move |event| {
+                if let BusEvent::CpuWrite { addr, data, .. } = *event {
+                    if watched.borrow().iter().any(|range| range.contains(&addr)) {
+                        pending_writes.borrow_mut().push((addr, data));
+                    }
+                }
+            });
+            self.hook.set(Some(id));
+        }
+        self.watched.borrow_mut().push(range.clone());
+        self.write_callbacks.borrow_mut().push((range, callback));
+    }
+
+    /// 一帧结束后要调用的回调及其参数, 先是各次写入, 最后是每帧回调
+    fn take_calls(&self) -> Vec<(FnPtr, Vec<Dynamic>)> {
+        let writes = std::mem::take(&mut *self.pending_writes.borrow_mut());
+        let write_callbacks = self.write_callbacks.borrow();
+        let mut calls = vec![];
+        for (addr, data) in writes {
+            for (range, callback) in write_callbacks.iter() {
+                if range.contains(&addr) {
+                    calls.push((callback.clone(), vec![Dynamic::from(addr as INT), Dynamic::from(data as INT)]));
+                }
+            }
+        }
+        calls.extend(self.frame_callbacks.borrow().iter().map(|callback| (callback.clone(), vec![])));
+        calls
+    }
+}
+
+/// 脚本宿主, 与前端共享同一个 CPU
+///
+/// 调用 `run`, `run_file` 与 `after_frame` 时不能持有 CPU 的借用.
+pub struct ScriptHost {
+    engine: Engine,
+    ast: AST, // 已加载脚本中定义的函数
+    shared: Rc<Shared>,
+}
+
+impl ScriptHost {
+    pub fn new(cpu: Rc<RefCell<Cpu>>) -> Self {
+        let shared = Rc::new(Shared {
+            cpu,
+            frame_callbacks: RefCell::new(vec![]),
+            write_callbacks: RefCell::new(vec![]),
+            watched: Rc::new(RefCell::new(vec![])),
+            pending_writes: Rc::new(RefCell::new(vec![])),
+            hook: Cell::new(None),
+        });
+        let mut engine = Engine::new();
+        engine.on_print(|text| log::info!("{}", text));
+        register_functions(&mut engine, &shared);
+        ScriptHost { engine, ast: AST::empty(), shared }
+    }
+
+    pub fn cpu(&self) -> &Rc<RefCell<Cpu>> {
+        &self.shared.cpu
+    }
+
+    /// 编译并执行一段脚本的顶层代码
+    pub fn run(&mut self, source: &str) -> Result<(), ScriptError> {
+        let ast = self.engine.compile(source)?;
+        self.ast = self.ast.merge(&ast.clone_functions_only());
+        self.engine.run_ast(&ast)?;
+        Ok(())
+    }
+
+    pub fn run_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), ScriptError> {
+        let source = fs::read_to_string(path).map_err(|err| ScriptError { message: err.to_string() })?;
+        self.run(&source)
+    }
+
+    /// 前端每运行完一帧调用一次, 依次调用写入回调与每帧回调
+    pub fn after_frame(&mut self) -> Result<(), ScriptError> {
+        for (callback, args) in self.shared.take_calls() {
+            let _ = callback.call::<Dynamic>(&self.engine, &self.ast, args)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ScriptHost {
+    fn drop(&mut self) {
+        if let Some(id) = self.shared.hook.take() {
+            self.shared.cpu.borrow_mut().bus_mut().remove_hook(id);
+        }
+    }
+}
+
+fn to_addr(value: INT) -> ScriptResult<u16> {
+    u16::try_from(value).map_err(|_| format!("address {} out of range", value).into())
+}
+
+fn to_player(player: INT) -> ScriptResult<PlayerId> {
+    match player {
+        1 => Ok(PlayerId::P1),
+        2 => Ok(PlayerId::P2),
+        3 => Ok(PlayerId::P3),
+        4 => Ok(PlayerId::P4),
+        _ => Err(format!("invalid player {}", player).into()),
+    }
+}
+
+fn to_button(name: &str) -> ScriptResult<JoypadButton> {
+    match name.to_ascii_uppercase().as_str() {
+        "A" => Ok(JoypadButton::A),
+        "B" => Ok(JoypadButton::B),
+        "SELECT" => Ok(JoypadButton::SELECT),
+        "START" => Ok(JoypadButton::START),
+        "UP" => Ok(JoypadButton::UP),
+        "DOWN" => Ok(JoypadButton::DOWN),
+        "LEFT" => Ok(JoypadButton::LEFT),
+        "RIGHT" => Ok(JoypadButton::RIGHT),
+        _ => Err(format!("invalid button '{}'", name).into()),
+    }
+}
+
+fn to_rgb(color: INT) -> (u8, u8, u8) {
+    ((color >> 16) as u8, (color >> 8) as u8, color as u8)
+}
+
+fn register_functions(engine: &mut Engine, shared: &Rc<Shared>) {
+    let s = shared.clone();
+    engine.register_fn("read", move |addr: INT| -> ScriptResult<INT> {
+        Ok(s.cpu.borrow().bus().peek(to_addr(addr)?) as INT)
+    });
+    let s = shared.clone();
+    engine.register_fn("read_word", move |addr: INT| -> ScriptResult<INT> {
+        let addr = to_addr(addr)?;
+        let cpu = s.cpu.borrow();
+        Ok(u16::from_le_bytes([cpu.bus().peek(addr), cpu.bus().peek(addr.wrapping_add(1))]) as INT)
+    });
+    let s = shared.clone();
+    engine.register_fn("write", move |addr: INT, value: INT| -> ScriptResult<()> {
+        s.cpu.borrow_mut().bus_mut().write(to_addr(addr)?, value as u8);
+        Ok(())
+    });
+
+    for (name, pressed) in [("press", true), ("release", false)] {
+        let s = shared.clone();
+        engine.register_fn(name, move |player: INT, button: &str| -> ScriptResult<()> {
+            let (player, button) = (to_player(player)?, to_button(button)?);
+            s.cpu.borrow_mut().io_interface().1.set_button_pressed(player, button, pressed);
+            Ok(())
+        });
+    }
+
+    let s = shared.clone();
+    engine.register_fn("frame_advance", move |context: NativeCallContext| -> ScriptResult<()> {
+        s.cpu.borrow_mut().run_next_frame().map_err(|halted| halted.to_string())?;
+        for (callback, args) in s.take_calls() {
+            let _ = callback.call_within_context::<Dynamic>(&context, args)?;
+        }
+        Ok(())
+    });
+    let s = shared.clone();
+    engine.register_fn("frame_count", move || s.cpu.borrow().bus().frame_count() as INT);
+    let s = shared.clone();
+    engine.register_fn("cycles", move || s.cpu.borrow().bus().cycles() as INT);
+    let s = shared.clone();
+    engine.register_fn("lag_frame", move || s.cpu.borrow().bus().lag_frame());
+    let s = shared.clone();
+    engine.register_fn("lag_count", move || s.cpu.borrow().bus().lag_count() as INT);
+    let s = shared.clone();
+    engine.register_fn("reset", move || s.cpu.borrow_mut().reset());
+    let s = shared.clone();
+    engine.register_fn("save_state", move || -> Blob { s.cpu.borrow().save_state() });
+    let s = shared.clone();
+    engine.register_fn("load_state", move |state: Blob| -> ScriptResult<()> {
+        s.cpu.borrow_mut().load_state(&state).map_err(|err| err.to_string().into())
+    });
+    let s = shared.clone();
+    engine.register_fn("registers", move || {
+        let registers = s.cpu.borrow().registers();
+        let mut map = Map::new();
+        map.insert("a".into(), (registers.a as INT).into());
+        map.insert("x".into(), (registers.x as INT).into());
+        map.insert("y".into(), (registers.y as INT).into());
+        map.insert("p".into(), (registers.p as INT).into());
+        map.insert("sp".into(), (registers.sp as INT).into());
+        map.insert("pc".into(), (registers.pc as INT).into());
+        map
+    });
+
+    let s = shared.clone();
+    engine.register_fn("on_frame", move |callback: FnPtr| s.frame_callbacks.borrow_mut().push(callback));
+    let s = shared.clone();
+    engine.register_fn("on_write", move |addr: INT, callback: FnPtr| -> ScriptResult<()> {
+        let addr = to_addr(addr)?;
+        s.add_write_callback(addr..=addr, callback);
+        Ok(())
+    });
+    let s = shared.clone();
+    engine.register_fn("on_write", move |start: INT, end: INT, callback: FnPtr| -> ScriptResult<()> {
+        s.add_write_callback(to_addr(start)?..=to_addr(end)?, callback);
+        Ok(())
+    });
+
+    let s = shared.clone();
+    engine.register_fn("draw_text", move |x: INT, y: INT, text: &str, color: INT| {
+        s.cpu.borrow_mut().bus_mut().frame_mut().draw_text(x as i32, y as i32, text, to_rgb(color));
+    });
+    let s = shared.clone();
+    engine.register_fn("draw_rect", move |x: INT, y: INT, width: INT, height: INT, color: INT| {
+        s.cpu.borrow_mut().bus_mut().frame_mut().draw_rect(x as i32, y as i32, width as i32, height as i32, to_rgb(color));
+    });
+    let s = shared.clone();
+    engine.register_fn("fill_rect", move |x: INT, y: INT, width: INT, height: INT, color: INT| {
+        s.cpu.borrow_mut().bus_mut().frame_mut().fill_rect(x as i32, y as i32, width as i32, height as i32, to_rgb(color));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Frame, cartridge::tests::test_rom_with_2_bank_prg};
+
+    #[test]
+    fn test_script() {
+        let mut prg = vec![0xea; 0x8000];
+        let program: &[u8] = &[
+            0xe6, 0x10, // 8000 INC $10
+            0x4c, 0x00, 0x80, // 8002 JMP $8000
+            0xe6, 0x11, // 8005 INC $11
+            0x40, // 8007 RTI
+        ];
+        prg[..program.len()].copy_from_slice(program);
+        prg[0x7ffa..0x7ffc].copy_from_slice(&[0x05, 0x80]); // NMI 向量
+        let mut cpu = Cpu::new(test_rom_with_2_bank_prg(prg));
+        cpu.reset();
+        let cpu = Rc::new(RefCell::new(cpu));
+        let mut host = ScriptHost::new(cpu.clone());
+
+        host.run(r#"
+            let writes = 0;
+            let frames = 0;
+            on_write(0x0011, |addr, value| { writes += 1; });
+            on_frame(|| { frames += 1; draw_rect(0, 0, 10, 10, 0x00ff00); });
+            write(0x0200, 0x34);
+            write(0x0201, 0x12);
+            if read_word(0x0200) != 0x1234 { throw "read_word"; }
+            // 开启 NMI
+            write(0x2000, 0x80);
+            press(1, "start");
+            for i in 0..3 { frame_advance(); }
+            // frame_advance 停在 vblank 开始处, 帧数在预渲染扫描线结束时才增加
+            if frame_count() != 2 { throw "frame_count"; }
+            // 第一帧结束时 NMI 尚未执行, 之后每个 NMI 中的 INC 写入两次(先写回原值)
+            if writes != 4 || frames != 3 { throw `callbacks ${writes} ${frames}`; }
+            write(0x0300, writes);
+            let state = save_state();
+            write(0x0400, 1);
+            load_state(state);
+            if read(0x0400) != 0 { throw "load_state"; }
+            draw_text(20, 20, "HI", 0xff0000);
+        "#).unwrap();
+
+        assert_eq!(cpu.borrow().bus().peek(0x0300), 4);
+        cpu.borrow_mut().run_next_frame().unwrap();
+        host.after_frame().unwrap();
+        host.run("write(0x0302, read(0x0011)); let r = registers(); if r.pc < 0x8000 { throw \"pc\"; }").unwrap();
+        assert_eq!(cpu.borrow().bus().peek(0x0302), 3);
+
+        {
+            let mut cpu = cpu.borrow_mut();
+            let frame = cpu.bus_mut().frame_mut();
+            assert_eq!(frame.pixel(0, 0), (0, 0xff, 0));
+            assert_eq!(frame.pixel(9, 5), (0, 0xff, 0));
+            // "H" 的左上角与中间的空白
+            assert_eq!(frame.pixel(20, 20), (0xff, 0, 0));
+            assert_ne!(frame.pixel(21, 20), (0xff, 0, 0));
+            assert_eq!(frame.pixel(20 + Frame::CHAR_WIDTH as usize + 1, 20), (0xff, 0, 0));
+        }
+
+        let err = host.run("press(5, \"A\");").unwrap_err();
+        assert!(err.message.contains("invalid player"));
+        assert!(host.run("let x = ;").is_err());
+    }
+}
@@ -1,13 +1,38 @@
-use std::{collections::HashMap, time::{Duration, Instant}};
+use std::{cell::RefCell, collections::HashMap, fs, path::{Path, PathBuf}, rc::Rc, time::{Duration, Instant}};
 use ringbuf::{HeapRb, HeapProducer, HeapConsumer};
-use sdl2::{pixels::PixelFormatEnum, event::Event, keyboard::Keycode, audio::{AudioSpecDesired, AudioCallback}};
-use crate::{Cpu, Rom, PlayerId, JoypadButton};
+use sdl2::{pixels::PixelFormatEnum, event::{Event, WindowEvent}, keyboard::Keycode, mouse::MouseButton, audio::{AudioSpecDesired, AudioCallback}};
+use crate::{Cpu, Rom, PlayerId, JoypadButton, ControllerPort, Zapper, Rewind, RunAhead, Movie, MovieRecorder, MoviePlayer};
+#[cfg(feature = "scripting")]
+use crate::ScriptHost;
 
 // 帧率应为 60 左右, 从 NES CPU主频的计算方式: 1.8MHz * 3 / (341*262) = 60.44Hz
 const FPS: f32 = 60f32;
 const FRAME_TIME: f32 = 1f32 / FPS;
+// 倒带缓冲区的内存预算, 可以倒退数分钟
+const REWIND_BUDGET: usize = 64 * 1024 * 1024;
+
+/// `run_with_options` 的选项
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// 加载的脚本文件(需要 `scripting` feature)
+    pub script: Option<String>,
+    /// 预运行的帧数, 用于减少输入延迟, 0 表示关闭
+    pub run_ahead: usize,
+    /// 从上电开始录像, 退出时保存为该 `.fm2` 文件
+    pub record_movie: Option<String>,
+    /// 回放的 `.fm2` 录像, 回放结束后恢复键盘输入
+    pub play_movie: Option<String>,
+    /// 在画面左上角显示 lag 帧数, 运行时按 F9 切换
+    pub show_lag: bool,
+    /// 在端口 2 连接 Zapper, 由鼠标瞄准, 左键扣扳机(NES 2.0 文件头指定时自动连接)
+    pub zapper: bool,
+}
 
 pub fn run(rom_filename: &str) {
+    run_with_options(rom_filename, &RunOptions::default())
+}
+
+pub fn run_with_options(rom_filename: &str, options: &RunOptions) {
     env_logger::init();
     let sdl_ctx = sdl2::init().unwrap();
     let video_sys = sdl_ctx.video().unwrap();
@@ -65,9 +90,64 @@ pub fn run(rom_filename: &str) {
     let rom_bytes = std::fs::read(rom_filename).unwrap();
     let rom = Rom::new(&rom_bytes).unwrap();
     let mut cpu = Cpu::new(rom);
+    cpu.set_tick_while_halted(true); // 停机后画面与声音照常输出
+    if options.zapper {
+        cpu.io_interface().1.set_device(ControllerPort::Port2, Box::new(Zapper::new()));
+    }
     cpu.reset();
 
+    // 与 ROM 同名的 .cht 金手指文件
+    let cheat_path = Path::new(rom_filename).with_extension("cht");
+    if cheat_path.exists() {
+        let cheats = cpu.bus_mut().cheats_mut();
+        match cheats.load_file(&cheat_path) {
+            Ok(()) => log::info!("Loaded {} cheats from {}", cheats.len(), cheat_path.display()),
+            Err(err) => log::error!("Failed to load cheats from {}: {}", cheat_path.display(), err),
+        }
+    }
+
+    let mut recorder = options.record_movie.as_ref().map(|_| {
+        let name = Path::new(rom_filename).file_stem().unwrap_or_default().to_string_lossy();
+        MovieRecorder::power_on(&name, &mut cpu)
+    });
+    let mut player = options.play_movie.as_deref().and_then(|filename| {
+        let loaded = fs::read_to_string(filename)
+            .map_err(|err| err.to_string())
+            .and_then(|text| Movie::parse_fm2(&text).map_err(|err| err.to_string()))
+            .and_then(|movie| MoviePlayer::new(movie, &mut cpu).map_err(|err| err.to_string()));
+        match loaded {
+            Ok(player) => Some(player),
+            Err(err) => {
+                log::error!("Failed to play movie {}: {}", filename, err);
+                None
+            }
+        }
+    });
+
+    let cpu = Rc::new(RefCell::new(cpu));
+    #[cfg(feature = "scripting")]
+    let mut script = options.script.as_deref().and_then(|filename| {
+        let mut host = ScriptHost::new(cpu.clone());
+        match host.run_file(filename) {
+            Ok(()) => Some(host),
+            Err(err) => {
+                log::error!("Failed to run script {}: {}", filename, err);
+                None
+            }
+        }
+    });
+    #[cfg(not(feature = "scripting"))]
+    if let Some(filename) = &options.script {
+        log::error!("Cannot run script {}: built without the scripting feature", filename);
+    }
+
     let mut frame_cnt = 0;
+    let mut halted_logged = None; // 停机只报告一次
+    let mut state_slot = 0; // F5 存档, F7 读档, F6 切换存档槽
+    let mut run_ahead = RunAhead::new(options.run_ahead);
+    let mut rewind = Rewind::new(REWIND_BUDGET);
+    let mut rewinding = false; // 按住 Backspace 倒带
+    let mut show_lag = options.show_lag;
     // 用于帧率控制的时刻于帧数
     let mut base_instant = Instant::now();
     let mut base_frame = 0;
@@ -75,38 +155,139 @@ pub fn run(rom_filename: &str) {
     loop {
         log::info!("Frame {} start", frame_cnt);
         
+        let mut cpu_ref = cpu.borrow_mut();
+
         // input
-        let (_, joypad, _) = cpu.io_interface();
         for event in event_pump.poll_iter() {
             match event {
                 Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                    if let (Some(recorder), Some(filename)) = (&recorder, &options.record_movie) {
+                        match fs::write(filename, recorder.movie().to_fm2()) {
+                            Ok(()) => log::info!("Saved movie to {}", filename),
+                            Err(err) => log::error!("Failed to save movie to {}: {}", filename, err),
+                        }
+                    }
                     std::process::exit(0);
                 }
+                Event::KeyDown { keycode: Some(Keycode::F7 | Keycode::Backspace), repeat: false, .. }
+                    if recorder.is_some() || player.is_some() => {
+                    log::warn!("Loading states and rewinding are disabled while a movie is active");
+                }
+                Event::KeyDown { keycode: Some(Keycode::F5), repeat: false, .. } => {
+                    let path = state_path(rom_filename, state_slot);
+                    match fs::write(&path, cpu_ref.save_state()) {
+                        Ok(()) => log::info!("Saved state to {}", path.display()),
+                        Err(err) => log::error!("Failed to save state to {}: {}", path.display(), err),
+                    }
+                }
+                Event::KeyDown { keycode: Some(Keycode::F6), repeat: false, .. } => {
+                    state_slot = (state_slot + 1) % 10;
+                    log::info!("Selected save slot {}", state_slot);
+                }
+                Event::KeyDown { keycode: Some(Keycode::F7), repeat: false, .. } => {
+                    let path = state_path(rom_filename, state_slot);
+                    let loaded = fs::read(&path)
+                        .map_err(|err| err.to_string())
+                        .and_then(|data| cpu_ref.load_state(&data).map_err(|err| err.to_string()));
+                    match loaded {
+                        Ok(()) => {
+                            log::info!("Loaded state from {}", path.display());
+                            halted_logged = None;
+                        }
+                        Err(err) => log::error!("Failed to load state from {}: {}", path.display(), err),
+                    }
+                }
+                Event::KeyDown { keycode: Some(Keycode::F9), repeat: false, .. } => show_lag = !show_lag,
+                Event::KeyDown { keycode: Some(Keycode::Backspace), .. } => rewinding = true,
+                Event::KeyUp { keycode: Some(Keycode::Backspace), .. } => rewinding = false,
+                Event::MouseMotion { x, y, .. } => {
+                    update_zappers(&mut cpu_ref, |zapper| zapper.set_aim(window_to_frame(x, y)));
+                }
+                Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } => {
+                    update_zappers(&mut cpu_ref, |zapper| {
+                        zapper.set_aim(window_to_frame(x, y));
+                        zapper.set_trigger(true);
+                    });
+                }
+                Event::MouseButtonUp { mouse_btn: MouseButton::Left, .. } => {
+                    update_zappers(&mut cpu_ref, |zapper| zapper.set_trigger(false));
+                }
+                Event::Window { win_event: WindowEvent::Leave, .. } => {
+                    update_zappers(&mut cpu_ref, |zapper| zapper.set_aim(None));
+                }
                 Event::KeyDown {keycode: Some(key), .. } => {
                     if let Some((id, button)) = key_map.get(&key) {
-                        joypad.set_button_pressed(*id, *button, true);
+                        cpu_ref.io_interface().1.set_button_pressed(*id, *button, true);
                     }
                 }
                 Event::KeyUp{keycode: Some(key), .. } => {
                     if let Some((id, button)) = key_map.get(&key) {
-                        joypad.set_button_pressed(*id, *button, false);
+                        cpu_ref.io_interface().1.set_button_pressed(*id, *button, false);
                     }
                 }
                 _ => {}
             }
         }
 
-        // update
-        cpu.run_next_frame();
-        let (frame, _, samples) = cpu.io_interface();
+        // update, 倒带时重建前一帧
+        let rewound = rewinding && rewind.step_back(&mut cpu_ref);
+        if !rewound {
+            if let Some(recorder) = recorder.as_mut() {
+                recorder.before_frame(&mut cpu_ref);
+            }
+            if let Some(player) = player.as_mut() {
+                player.before_frame(&mut cpu_ref);
+            }
+            if let Err(halted) = run_ahead.run_frame(&mut cpu_ref) {
+                if halted_logged.is_none() {
+                    log::error!("{}", halted);
+                    halted_logged = Some(halted);
+                }
+            }
+            rewind.push(&cpu_ref);
+            if let Some(recorder) = recorder.as_mut() {
+                recorder.after_frame(&cpu_ref);
+            }
+            if let Some(playing) = player.as_mut() {
+                if let Err(err) = playing.after_frame(&cpu_ref) {
+                    log::error!("{}", err);
+                }
+                if playing.is_finished() {
+                    log::info!("Movie finished after {} frames", playing.frame());
+                    player = None;
+                }
+            }
+        }
+        drop(cpu_ref);
+        #[cfg(feature = "scripting")]
+        if let (false, Some(host)) = (rewound, script.as_mut()) {
+            if let Err(err) = host.after_frame() {
+                log::error!("Script error: {}", err);
+                script = None;
+            }
+        }
+        let mut cpu_ref = cpu.borrow_mut();
+        if show_lag { // lag 帧数, 本帧为 lag 帧时显示为红色
+            let bus = cpu_ref.bus_mut();
+            let color = if bus.lag_frame() { (255, 64, 64) } else { (255, 255, 255) };
+            let text = format!("LAG {}", bus.lag_count());
+            bus.frame_mut().draw_text(8, 10, &text, color);
+        }
+        let (frame, _, samples) = cpu_ref.io_interface();
         sender.input_frequency = samples.data().len() as f32 * FPS;
-        sender.append_samples(samples.data());
+        if rewound { // 倒带时倒放声音
+            let reversed: Vec<f32> = samples.data().iter().rev().copied().collect();
+            sender.append_samples(&reversed);
+        } else {
+            sender.append_samples(samples.data());
+        }
         samples.clear();
 
         // render
         texture.update(None, &frame.data()[256 * 3 * 8..(256 * 3 * 232)], 256 * 3).unwrap();
         canvas.copy(&texture, None, None).unwrap();
         canvas.present();
+        drop(cpu_ref);
 
         // sleep
         let secs_from_base = base_instant.elapsed().as_secs_f32();
@@ -123,6 +304,26 @@ pub fn run(rom_filename: &str) {
     }
 }
 
+/// 存档槽的文件, 与 ROM 位于同一目录, 扩展名为 ss0-ss9
+fn state_path(rom_filename: &str, slot: u8) -> PathBuf {
+    Path::new(rom_filename).with_extension(format!("ss{}", slot))
+}
+
+/// 对两个端口上的 Zapper 执行 f
+fn update_zappers<F: Fn(&mut Zapper)>(cpu: &mut Cpu, f: F) {
+    let joypad = cpu.io_interface().1;
+    for port in [ControllerPort::Port1, ControllerPort::Port2] {
+        if let Some(zapper) = joypad.device_mut::<Zapper>(port) {
+            f(zapper);
+        }
+    }
+}
+
+/// 窗口坐标转换为画面坐标, 窗口放大 3 倍并且只显示第 8..232 行
+fn window_to_frame(x: i32, y: i32) -> Option<(usize, usize)> {
+    (x >= 0 && y >= 0).then(|| (x as usize / 3, y as usize / 3 + 8))
+}
+
 struct AudioSender {
     producer: HeapProducer<f32>,
     input_frequency: f32,